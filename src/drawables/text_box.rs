@@ -24,13 +24,25 @@ impl TickData<'_> {
         self.title_box_color(title, rect, white(), black());
     }
 
-    pub fn text_box(&mut self, title: &str, text: &str, rect: Rect, fail_on_out_of_space: bool) {
+    /// Returns how many lines the text takes once wrapped, after the empty line at the top
+    pub fn text_box(
+        &mut self,
+        title: &str,
+        text: &str,
+        rect: Rect,
+        fail_on_out_of_space: bool,
+    ) -> i32 {
         self.title_box(title, rect);
         let Rect { pos, size } = rect;
         let mut tb = bl::TextBuilder::empty();
         tb.ln();
+        let mut lines = 0;
         for line in text.trim().split('\n') {
-            tb.line_wrap(line.trim()).ln();
+            // Wrapped here instead of by the text builder, so the lines can be counted
+            for wrapped in textwrap::wrap(line.trim(), (size.w - 3) as usize) {
+                tb.append(&wrapped).ln();
+                lines += 1;
+            }
         }
         tb.reset();
 
@@ -40,6 +52,7 @@ impl TickData<'_> {
             r.debug_unwrap();
         }
         block.render(&mut self.console);
+        lines
     }
 
     /// Position given is the position of the top left corner of the rectangle
@@ -1,26 +1,252 @@
-use super::base::*;
+use crossbeam::channel::Receiver;
+
+use super::{base::*, string_reader::StringReaderState};
 use crate::{
-    interpreter::{interpret_itermediates, ConstantProvider, Node}, levels::{Level, TestCaseRun}, prelude::*, save_system::SaveProfile
+    drawables::{black, white}, interpreter::{
+        decode, interpret_steps, traversers::PathStep, CancelToken, ConstantNames, ConstantProvider, ReductionStep, ReductionSteps, ReductionStrategy
+    }, levels::{Expected, Level, TestCaseRun}, prelude::*
 };
+
+/// Reductions kept in memory, the debugger doesn't go further than this
+const MAX_STEPS: usize = 500;
+
+const SHOW_RAW: &str = "Show raw";
+const SHOW_DECODED: &str = "Show decoded";
+const PREVIOUS: &str = "< Previous";
+const NEXT: &str = "Next >";
+const JUMP: &str = "Jump to step";
+const SET_BREAKPOINT: &str = "Set breakpoint";
+const RUN: &str = "Run to breakpoint";
+
+fn function_color() -> bl::RGBA { bl::RGBA::from_u8(50, 80, 160, 255) }
+fn argument_color() -> bl::RGBA { bl::RGBA::from_u8(40, 120, 60, 255) }
+
+fn term_box() -> Rect { Rect::new(8, 2, W - 4, H - 22) }
+
+#[derive(Debug)]
+enum Reading {
+    Jump,
+    Breakpoint,
+}
+
+/// Text of the term being shown, split in lines, with the background of each character
+#[derive(Debug, Default)]
+struct View {
+    lines:      Vec<Vec<(char, bl::RGBA)>>,
+    /// Line where the redex starts
+    redex_line: usize,
+}
+
 #[derive(Debug)]
 pub struct DebuggerState {
-    run:   TestCaseRun,
-    steps: Vec1<Box<Node>>,
+    run:        TestCaseRun,
+    /// Reductions taken so far from the interpretation
+    steps:      Vec<ReductionStep>,
+    remaining:  ReductionSteps,
+    /// Index of the reduction being shown, steps.len() shows the final result
+    cur:        usize,
+    /// Running to the breakpoint stops before the next reduction that expands this constant
+    breakpoint: Option<String>,
+    /// Asking the player for a step or a breakpoint
+    reading:    Option<(Reading, Receiver<Option<String>>)>,
+    view:       View,
+    /// First line of the view that is shown
+    scroll:     usize,
+    /// Expected result with the results decoded into values
+    expected:   String,
+    /// Names of the constants, to show them instead of their expansions
+    names:      ConstantNames,
+    /// Show the expected result as lambda terms instead of decoded
+    raw:        bool,
 }
 
 impl DebuggerState {
-    pub fn new(level: Level, save_profile: Arc<SaveProfile>, run: TestCaseRun) -> Self {
-        let mut steps = vec1![run.test_expression.clone()];
-        steps.append(
-            &mut interpret_itermediates(
-                run.test_expression.clone(),
-                false,
-                ConstantProvider::new(level, Some(save_profile)),
-            )
-            .take(30)
-            .collect(),
+    pub fn new(level: Level, provider: ConstantProvider, run: TestCaseRun) -> Self {
+        let numerals = level.base().numerals;
+        let names = provider.names();
+        let remaining = interpret_steps(
+            run.test_expression.clone(),
+            ReductionStrategy::CallByName,
+            provider,
         );
-        Self { run, steps }
+        let expected = match &run.expected {
+            Expected::AnyOf(results) => results
+                .iter()
+                .map(|r| {
+                    decode(r, numerals, CancelToken::default())
+                        .with_names(&names)
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(" or "),
+            predicate => predicate.to_string(),
+        };
+        let mut this = Self {
+            run,
+            steps: vec![],
+            remaining,
+            cur: 0,
+            breakpoint: None,
+            reading: None,
+            view: View::default(),
+            scroll: 0,
+            expected,
+            names,
+            raw: false,
+        };
+        this.go_to(0);
+        this
+    }
+
+    /// Takes reductions from the interpretation until there are `n`, if there are that many
+    fn fetch(&mut self, n: usize) {
+        while self.steps.len() < n.min(MAX_STEPS) {
+            match self.remaining.next() {
+                Some(step) => self.steps.push(step),
+                None => break,
+            }
+        }
+    }
+
+    fn go_to(&mut self, i: usize) {
+        self.fetch(i + 1);
+        self.cur = i.min(self.steps.len());
+        self.refresh();
+        self.scroll = self.view.redex_line.saturating_sub(2);
+    }
+
+    /// Goes to the next reduction that expands the breakpoint, or to the end if there is none
+    fn run_to_breakpoint(&mut self) {
+        let breakpoint = match &self.breakpoint {
+            Some(b) => b.clone(),
+            None => return,
+        };
+        let mut i = self.cur + 1;
+        loop {
+            self.fetch(i + 1);
+            if i >= self.steps.len() || self.steps[i].expanded.contains(&breakpoint) {
+                break;
+            }
+            i += 1;
+        }
+        self.go_to(i);
+    }
+
+    fn total(&self) -> String {
+        if self.remaining.result().is_some() {
+            self.steps.len().to_string()
+        } else {
+            format!("{}+", self.steps.len())
+        }
+    }
+
+    fn final_text(&self) -> String {
+        match self.remaining.result() {
+            Some(Ok(term)) if self.raw => format!("Final result: {}", term),
+            Some(Ok(term)) => format!("Final result: {}", term.with_names(&self.names)),
+            Some(Err(e)) => format!("Failed: {}", e),
+            None => format!("Only the first {} reductions are shown", MAX_STEPS),
+        }
+    }
+
+    /// Recomputes the text of the term shown
+    fn refresh(&mut self) {
+        let width = (term_box().size.w - 4) as usize;
+        let (text, marks) = match self.steps.get(self.cur) {
+            Some(step) => {
+                let term = if self.raw {
+                    step.before.clone()
+                } else {
+                    self.names.fold_around(&step.before, &step.redex)
+                };
+                let (mut function, mut argument) = (step.redex.clone(), step.redex.clone());
+                function.push(PathStep::Left);
+                argument.push(PathStep::Right);
+                let (text, found) =
+                    term.to_string_marking(&[function.as_slice(), argument.as_slice()]);
+                let marks: Vec<_> = found
+                    .into_iter()
+                    .zip([function_color(), argument_color()])
+                    .filter_map(|(range, color)| range.map(|r| (r, color)))
+                    .collect();
+                (text, marks)
+            },
+            None => (self.final_text(), vec![]),
+        };
+        let chars: Vec<_> = text
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                let color = marks.iter().find(|(r, _)| r.contains(&i));
+                (c, color.map_or(black(), |(_, color)| *color))
+            })
+            .collect();
+        self.view = View {
+            lines:      chars.chunks(width).map(|line| line.to_vec()).collect(),
+            redex_line: marks.first().map_or(0, |(r, _)| r.start / width),
+        };
+    }
+
+    fn read(&mut self, reading: Reading) -> GameStateEvent {
+        let (title, action) = match reading {
+            Reading::Jump => ("Step", "jump to it"),
+            Reading::Breakpoint => ("Constant", "stop when it is expanded"),
+        };
+        let (state, recv) = StringReaderState::new(title.to_string(), action, 20);
+        self.reading = Some((reading, recv));
+        GameStateEvent::Push(Box::new(state))
+    }
+
+    fn draw_term(&self, data: &mut TickData) {
+        let rect = term_box();
+        let title = if self.cur < self.steps.len() {
+            format!("Reduction {} of {}", self.cur + 1, self.total())
+        } else {
+            format!("After {} reductions", self.steps.len())
+        };
+        data.title_box(&title, rect);
+        let height = (rect.size.h - 2) as usize;
+        for (i, line) in self
+            .view
+            .lines
+            .iter()
+            .skip(self.scroll)
+            .take(height)
+            .enumerate()
+        {
+            for (j, (c, bg)) in line.iter().enumerate() {
+                data.console.set(
+                    rect.left() + 2 + j as i32,
+                    rect.top() + 1 + i as i32,
+                    white(),
+                    *bg,
+                    bl::to_cp437(*c),
+                );
+            }
+        }
+        if let Some(step) = self.steps.get(self.cur) {
+            let legend_i = rect.bottom() + 1;
+            data.console.print_color(
+                rect.left(),
+                legend_i,
+                white(),
+                function_color(),
+                " function ",
+            );
+            data.console.print_color(
+                rect.left() + 11,
+                legend_i,
+                white(),
+                argument_color(),
+                " argument ",
+            );
+            if !step.expanded.is_empty() {
+                data.print(
+                    Pos::new(legend_i, rect.left() + 23),
+                    &format!("Expanded before it: {}", step.expanded.join(", ")),
+                );
+            }
+        }
     }
 }
 
@@ -28,22 +254,74 @@ impl GameState for DebuggerState {
     fn name(&self) -> &'static str { "Debugger" }
 
     fn tick(&mut self, mut data: TickData) -> GameStateEvent {
+        // The reader is popped before this runs again, so it already sent the text
+        if let Some((reading, recv)) = self.reading.take() {
+            match (reading, recv.try_recv()) {
+                (Reading::Jump, Ok(Some(text))) =>
+                    if let Ok(n) = text.parse::<usize>() {
+                        self.go_to(n.max(1) - 1);
+                    },
+                (Reading::Breakpoint, Ok(Some(text))) => {
+                    self.breakpoint = Some(text.to_uppercase());
+                    self.run_to_breakpoint();
+                },
+                _ => {},
+            }
+        }
+
+        let expected = if self.raw {
+            self.run.expected.to_string()
+        } else {
+            self.expected.clone()
+        };
         data.console
-            .print_centered(2, &format!("Expected result: {}", self.run.expected_result));
+            .print_centered(2, &format!("Expected result: {}", expected));
+        let toggle = if self.raw { SHOW_DECODED } else { SHOW_RAW };
+        if data.button(toggle, Pos::new(1, W - toggle.len() as i32 - 3), black()) {
+            SFX::Select.play();
+            self.raw = !self.raw;
+            self.refresh();
+        }
         data.console
             .print_centered(5, "Step by step test case reduction");
-        let mut j = 8;
-        for code in &self.steps {
-            data.console.print_centered(j, &format!("{}", code));
-            j += 3;
+        self.draw_term(&mut data);
+
+        let breakpoint = match &self.breakpoint {
+            Some(b) => format!("Breakpoint: {}", b),
+            None => SET_BREAKPOINT.to_string(),
+        };
+        let buttons = [PREVIOUS, NEXT, JUMP, breakpoint.as_str(), RUN];
+        let mut clicked = None;
+        let mut j = 2;
+        for (idx, text) in buttons.iter().enumerate() {
+            if data.button(*text, Pos::new(H - 11, j), black()) {
+                clicked = Some(idx);
+            }
+            j += text.len() as i32 + 4;
+        }
+        if clicked.is_some() {
+            SFX::Select.play();
         }
 
-        data.instructions(&["Press ESC to go back"]);
+        data.instructions(&[
+            "Left and right to go through the reductions",
+            "Up and down to scroll",
+            "Press ESC to go back",
+        ]);
 
-        if data.pressed_key == Some(bl::VirtualKeyCode::Escape) {
-            GameStateEvent::Pop(1)
-        } else {
-            GameStateEvent::None
+        let height = (term_box().size.h - 2) as usize;
+        let max_scroll = self.view.lines.len().saturating_sub(height);
+        match (clicked, data.pressed_key) {
+            (Some(0), _) | (_, Some(Key::Left)) => self.go_to(self.cur.saturating_sub(1)),
+            (Some(1), _) | (_, Some(Key::Right)) => self.go_to(self.cur + 1),
+            (Some(2), _) => return self.read(Reading::Jump),
+            (Some(3), _) => return self.read(Reading::Breakpoint),
+            (Some(4), _) => self.run_to_breakpoint(),
+            (_, Some(Key::Up)) => self.scroll = self.scroll.saturating_sub(1),
+            (_, Some(Key::Down)) => self.scroll = (self.scroll + 1).min(max_scroll),
+            (_, Some(Key::Escape)) => return GameStateEvent::Pop(1),
+            _ => {},
         }
+        GameStateEvent::None
     }
 }
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
-use crate::prelude::*;
+use crate::{
+    interpreter::Numerals, levels::{TestCaseConfig, WrongSolution}, prelude::*
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 /// UserLevelConfig is the format in which the JSON level config file must be specified.
@@ -33,8 +35,29 @@ pub struct UserLevelConfig {
     /// Example for the level "boolean or":
     /// `[["f: f TRUE FALSE A B", "A"], ["f: FALSE FALSE A B", "B"]]`
     ///
+    /// A test case may also be an object with the `application` and exactly one of:
+    /// - `result`: the expected result, like the second string above.
+    /// - `any_of`: a list of results, any of them is accepted.
+    /// - `predicate`: a function that receives the result and must reduce to TRUE.
+    ///
+    /// Objects with `result` or `any_of` may choose how the results are compared through
+    /// `equivalence`:
+    /// - `structural` (default): the results must be the same term.
+    /// - `beta_normal`: the results must have the same normal form.
+    /// - `beta_eta`: the results must have the same normal form, and `x: f x` is the same as `f`.
+    ///
+    /// Examples:
+    /// - `{application: "f: f 2", result: "3", equivalence: "beta_normal"}`
+    /// - `{application: "f: f A B", any_of: ["A", "B"]}`
+    /// - `{application: "f: f 2", predicate: "n: EQ n 2"}`
+    ///
+    /// Objects may also list `wrong_results`, results known to be wrong, each with a message shown
+    /// to players whose code gives that result. They are compared the same way as `result`.
+    ///
+    /// Example: `{application: "f: f A B", result: "A", wrong_results: [["B", "It's the other one"]]}`
+    ///
     /// You must specify at least one test case.
-    pub test_cases:      Vec1<(String, String)>,
+    pub test_cases:      Vec1<TestCaseConfig>,
     /// Each of the strings must be a correct solution for the problem, and must pass all test
     /// cases. You must specify at least one valid solution.
     ///
@@ -44,7 +67,12 @@ pub struct UserLevelConfig {
     #[serde(default)]
     /// You may optionally specify wrong solutions, and validation will make sure they do not
     /// pass all tests.
-    pub wrong_solutions: Vec<String>,
+    ///
+    /// A wrong solution may also be an object with the `code` and a `feedback` message. Players
+    /// whose code fails the same test cases with the same results are shown the message.
+    ///
+    /// Example: `["x: x", {code: "a:b: a", feedback: "Remember to check both arguments"}]`
+    pub wrong_solutions: Vec<WrongSolution>,
     #[serde(default)]
     /// This is an optional list of additional constants the player may use. By default the
     /// user knows all constants from the game.
@@ -54,4 +82,13 @@ pub struct UserLevelConfig {
     ///
     /// Example: `[["DUPLICATE", "x: x x"]]`
     pub extra_constants: Vec<(String, String)>,
+    /// If present, running a solution on a test case fails if it takes more reductions than
+    /// this. Useful to reject solutions that are correct but too slow.
+    pub max_reductions:  Option<u32>,
+    /// If present, running a solution on a test case fails if it takes longer than this many
+    /// milliseconds.
+    pub time_limit_ms:   Option<u64>,
+    /// How number literals like `3` are encoded, in solutions and test cases. One of `church`
+    /// (default), `scott`, `binary`, or `none` to forbid them.
+    pub numerals:        Option<Numerals>,
 }
@@ -103,7 +103,8 @@ impl GameState for LevelCreatorLevelListState {
         if data.button("Create new level", Pos::new(H - 4, 1), black())
             || (data.ctrl && data.pressed_key == Some(Key::N))
         {
-            let (state, recv) = StringReaderState::new("Level title".to_string(), 15);
+            let (state, recv) =
+                StringReaderState::new("Level title".to_string(), "create level", 15);
             self.title_recv = Some(recv);
             return GameStateEvent::Push(Box::new(state));
         }
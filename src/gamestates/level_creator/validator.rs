@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap, convert::{TryFrom, TryInto}, path::PathBuf
+    collections::HashMap, convert::{TryFrom, TryInto}, path::PathBuf, time::Duration
 };
 
 use crossbeam::channel::{Receiver, Sender};
@@ -9,8 +9,10 @@ use serde::{Deserialize, Serialize};
 use super::{super::base::*, UserLevelConfig, WorkshopConfig};
 use crate::{
     drawables::{black, XiEditor}, gamestates::{base::GameStateEvent, editor::EditorState, level_creator::UploadingLevelState}, interpreter::{
-        parse, tokenize, ConstantProvider, InterpretError, Node, ParseError, TokenizeError
-    }, levels::{BaseLevel, Level, TestCase, UserCreatedLevel}, prelude::*, save_system::SaveProfile
+        parse, tokenize, Budget, CancelToken, ConstantProvider, Difference, InterpretError, Node, Numerals, ParseError, TokenizeError
+    }, levels::{
+        BaseLevel, Expected, ExpectedConfig, Level, TestCase, TestCaseConfig, UserCreatedLevel, WrongSolution
+    }, prelude::*, save_system::SaveProfile
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -31,12 +33,13 @@ pub enum ValidationError {
     TokenizeError(TokenizeError, String),
     #[error("Error when interpreting {1}: {0}")]
     InterpretError(InterpretError, String),
-    #[error("Test case #{test_idx} is not solved by solution #{sol_idx}.\nExpected: {expected}\nGot: {got}")]
+    #[error("Test case #{test_idx} is not solved by solution #{sol_idx}.\nExpected: {expected}\nGot: {got}{}", .difference.as_ref().map_or(String::new(), |d| format!("\nFirst difference: {}", d)))]
     WrongSolution {
-        sol_idx:  usize,
-        test_idx: usize,
-        expected: Box<Node>,
-        got:      Box<Node>,
+        sol_idx:    usize,
+        test_idx:   usize,
+        expected:   Expected,
+        got:        Box<Node>,
+        difference: Option<Difference>,
     },
     #[error("Wrong solution #{0} passes all tests.")]
     WrongSolutionIsCorrect(usize),
@@ -64,17 +67,56 @@ impl UserLevelConfig {
         }
     }
 
+    fn test_case(
+        idx: usize,
+        config: &TestCaseConfig,
+        numerals: Numerals,
+    ) -> Result<TestCase, ValidationError> {
+        let result = |term: &str| Self::parse(|| format!("test case #{}'s result", idx), term);
+        let expected = match &config.expected {
+            ExpectedConfig::Result(r) => Expected::AnyOf(vec1![result(r)?]),
+            ExpectedConfig::AnyOf(rs) => Expected::AnyOf(rs.try_mapped_ref(|r| result(r))?),
+            ExpectedConfig::Predicate(p) => Expected::Predicate(Self::parse(
+                || format!("test case #{}'s predicate", idx),
+                p,
+            )?),
+        };
+        let wrong_results = config
+            .wrong_results
+            .iter()
+            .enumerate()
+            .map(|(i, (r, feedback))| {
+                Ok((
+                    Self::parse(
+                        || format!("test case #{}'s wrong result #{}", idx, i + 1),
+                        r,
+                    )?,
+                    feedback.clone(),
+                ))
+            })
+            .collect::<Result<_, ValidationError>>()?;
+        TestCase::new(
+            Self::parse(
+                || format!("test case #{}'s application", idx),
+                &config.application,
+            )?,
+            expected,
+            config.equivalence,
+            numerals,
+            wrong_results,
+        )
+        .map_err(|err| ValidationError::InterpretError(err, format!("test case #{}'s result", idx)))
+    }
+
     fn validate(self, workshop: WorkshopConfig) -> Result<ParsedUserLevelConfig, ValidationError> {
         if self.extra_info.is_some() && self.hint.is_some() {
             Err(ValidationError::HasExtraInfoAndHint)?
         }
+        let numerals = self.numerals.unwrap_or(Numerals::Church);
         let mut idx = 0;
-        let test_cases = self.test_cases.try_mapped_ref(|(application, result)| {
+        let test_cases = self.test_cases.try_mapped_ref(|config| {
             idx += 1;
-            Result::<_, ValidationError>::Ok(TestCase::from(
-                Self::parse(|| format!("test case #{}'s application", idx), application)?,
-                Self::parse(|| format!("test case #{}'s result", idx), result)?,
-            ))
+            Self::test_case(idx, config, numerals)
         })?;
         idx = 0;
         let solutions = self.solutions.try_mapped_ref(|sol| {
@@ -85,7 +127,7 @@ impl UserLevelConfig {
             .wrong_solutions
             .iter()
             .enumerate()
-            .map(|(idx, sol)| Self::parse(|| format!("wrong solution #{}", idx + 1), sol))
+            .map(|(idx, sol)| Self::parse(|| format!("wrong solution #{}", idx + 1), &sol.code))
             .collect::<Result<Vec<_>, ValidationError>>()?;
         let extra_constants = self
             .extra_constants
@@ -101,23 +143,30 @@ impl UserLevelConfig {
 
         let parsed = Arc::new(UserCreatedLevel {
             base: BaseLevel {
-                name:               String::new(),
-                description:        String::new(),
+                name: String::new(),
+                description: String::new(),
                 extra_info_is_hint: false,
-                extra_info:         None,
-                test_cases:         test_cases.clone(),
+                extra_info: None,
+                test_cases: test_cases.clone(),
+                budget: budget(self.max_reductions, self.time_limit_ms),
+                numerals,
+                allow_functions: true,
+                wrong_solutions: self.wrong_solutions.clone(),
             },
             extra_constants,
             id: None,
         });
         let provider = ConstantProvider::new(Level::UserCreatedLevel(parsed.clone()), None);
+        let options = parsed.base.interpret_options(CancelToken::default());
         solutions
             .into_par_iter()
             .enumerate()
             .try_for_each(|(si, s)| {
                 test_cases.par_iter().enumerate().try_for_each(|(ti, t)| {
-                    let run = t.test(s.clone(), provider.clone());
-                    let expected = run.expected_result;
+                    let run = t.test(s.clone(), provider.clone(), options.clone());
+                    let correct = run.is_correct();
+                    let difference = run.difference().map(|(_, _, d)| d);
+                    let expected = run.expected;
                     run.result
                         .map_err(|err| {
                             ValidationError::InterpretError(
@@ -126,12 +175,13 @@ impl UserLevelConfig {
                             )
                         })
                         .and_then(|int| {
-                            if int.term == expected {
+                            if correct {
                                 Ok(())
                             } else {
                                 Err(ValidationError::WrongSolution {
                                     sol_idx: si + 1,
                                     test_idx: ti + 1,
+                                    difference,
                                     expected,
                                     got: int.term,
                                 })
@@ -146,7 +196,10 @@ impl UserLevelConfig {
             .try_for_each(|(wsi, ws)| {
                 if test_cases
                     .par_iter()
-                    .map(|t| t.test(ws.clone(), provider.clone()).is_correct())
+                    .map(|t| {
+                        t.test(ws.clone(), provider.clone(), options.clone())
+                            .is_correct()
+                    })
                     .all(|b| b)
                 {
                     Err(ValidationError::WrongSolutionIsCorrect(wsi))
@@ -161,7 +214,11 @@ impl UserLevelConfig {
             extra_info_is_hint: self.hint.is_some(),
             extra_info:         self.extra_info.or(self.hint),
             test_cases:         self.test_cases,
+            wrong_solutions:    self.wrong_solutions,
             extra_constants:    self.extra_constants,
+            max_reductions:     self.max_reductions,
+            time_limit_ms:      self.time_limit_ms,
+            numerals:           self.numerals,
         })
     }
 }
@@ -172,37 +229,48 @@ pub struct ParsedUserLevelConfig {
     description:        String,
     extra_info:         Option<String>,
     extra_info_is_hint: bool,
-    test_cases:         Vec1<(String, String)>,
+    test_cases:         Vec1<TestCaseConfig>,
+    #[serde(default)]
+    wrong_solutions:    Vec<WrongSolution>,
     #[serde(default)]
     extra_constants:    Vec<(String, String)>,
+    #[serde(default)]
+    max_reductions:     Option<u32>,
+    #[serde(default)]
+    time_limit_ms:      Option<u64>,
+    #[serde(default)]
+    numerals:           Option<Numerals>,
+}
+
+fn budget(max_reductions: Option<u32>, time_limit_ms: Option<u64>) -> Budget {
+    Budget {
+        max_reductions,
+        time_limit: time_limit_ms.map(Duration::from_millis),
+    }
 }
 
 impl TryFrom<ParsedUserLevelConfig> for UserCreatedLevel {
     type Error = ValidationError;
 
     fn try_from(config: ParsedUserLevelConfig) -> Result<Self, ValidationError> {
+        let numerals = config.numerals.unwrap_or(Numerals::Church);
         Ok(Self {
             base:            BaseLevel {
-                name:               config.name,
-                description:        config.description,
-                extra_info:         config.extra_info,
+                name: config.name,
+                description: config.description,
+                extra_info: config.extra_info,
                 extra_info_is_hint: config.extra_info_is_hint,
-                test_cases:         {
+                test_cases: {
                     let mut idx = 0;
-                    config.test_cases.try_mapped(|(term, result)| {
+                    config.test_cases.try_mapped(|test_case| {
                         idx += 1;
-                        Result::<_, ValidationError>::Ok(TestCase::from(
-                            UserLevelConfig::parse(
-                                || format!("test case {}'s application", idx),
-                                &term,
-                            )?,
-                            UserLevelConfig::parse(
-                                || format!("test case {}'s result", idx),
-                                &result,
-                            )?,
-                        ))
+                        UserLevelConfig::test_case(idx, &test_case, numerals)
                     })?
                 },
+                budget: budget(config.max_reductions, config.time_limit_ms),
+                numerals,
+                allow_functions: true,
+                wrong_solutions: config.wrong_solutions,
             },
             extra_constants: config
                 .extra_constants
@@ -393,11 +461,12 @@ mod test {
         assert_matches!(
             validate_with_json(r#"{"test_cases": [["f:A", "B"]], "solutions": ["x:x"]}"#),
             Err(ValidationError::WrongSolution {
-                sol_idx:  1,
-                test_idx: 1,
-                got:      _,
-                expected: _,
-            })
+                sol_idx:    1,
+                test_idx:   1,
+                got:        _,
+                expected:   _,
+                difference: Some(Difference { path, .. }),
+            }) if path.is_empty()
         );
         assert_matches!(
             validate_with_json(
@@ -439,6 +508,15 @@ mod test {
             ),
             Ok(()),
         );
+        assert_matches!(
+            validate_with_json(
+                r#"{
+                test_cases: [{application: "f: f A B", result: "A", wrong_results: [["B", "Other one"]]}],
+                solutions: ["a:b: a"],
+                wrong_solutions: [{code: "a:b: b", feedback: "Other one"}]}"#
+            ),
+            Ok(()),
+        );
         assert_matches!(
             validate_with_json(
                 r#"{
@@ -448,5 +526,14 @@ mod test {
             ),
             Ok(()),
         );
+        let scott = r#"test_cases: [["f: f 2", "s:z: s (s:z: s (s:z: z))"]], solutions: ["x: x"]"#;
+        assert_matches!(
+            validate_with_json(&format!(r#"{{{}, numerals: "scott"}}"#, scott)),
+            Ok(())
+        );
+        assert_matches!(
+            validate_with_json(&format!("{{{}}}", scott)),
+            Err(ValidationError::WrongSolution { .. })
+        );
     }
 }
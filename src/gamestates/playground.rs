@@ -1,20 +1,38 @@
 use super::base::*;
 use crate::{
     drawables::{black, TextEditor}, interpreter::{
-        interpret, interpret_itermediates, parse, tokenize, ConstantProvider, InterpretError, Interpreted, Node, ParseError, TokenizeError
+        decode, infer_type, interpret_itermediates, interpret_profiled, parse, reduce_combinators, to_combinators, tokenize, CancelToken, ConstantNames, ConstantProvider, InterpretError, Interpreted, Node, ParseError, Profile, ReductionStrategy, TokenizeError, Type, TypeError
     }, prelude::*
 };
 #[derive(Debug)]
 struct DebugData {
     steps:       Vec<Box<Node>>,
     interpreted: Result<Interpreted, InterpretError>,
+    /// Reductions by the constant their function came from
+    profile:     Profile,
+    /// Decoded final result, if interpretation succeeded
+    decoded:     Option<String>,
+    /// Type of the term before evaluating it
+    inferred:    Result<Type, TypeError>,
+    /// Term before evaluating it, written only with combinators
+    combinators: Box<Node>,
+    /// SKI form reduced with the combinator rules instead of the lambda calculus ones
+    ski_normal:  Result<Interpreted, InterpretError>,
 }
 
 #[derive(Debug)]
 pub struct PlaygroundState<Editor: TextEditor> {
     editor:   Editor,
     data:     Option<Result<Result<DebugData, ParseError>, TokenizeError>>,
+    /// Code that was last evaluated, used to point to errors
+    code:     String,
     provider: ConstantProvider,
+    /// Used for the next evaluations, levels always use call-by-name
+    strategy: ReductionStrategy,
+    /// Names of the constants, to show them instead of their expansions
+    names:    ConstantNames,
+    /// Show the final result as a lambda term instead of decoded
+    raw:      bool,
 }
 
 const EDITOR_W: i32 = 40;
@@ -29,7 +47,11 @@ impl<Editor: TextEditor> PlaygroundState<Editor> {
                 initial_text,
             ),
             data: None,
+            code: String::new(),
+            names: provider.names(),
             provider,
+            strategy: ReductionStrategy::CallByName,
+            raw: false,
         }
     }
 
@@ -41,21 +63,49 @@ impl<Editor: TextEditor> PlaygroundState<Editor> {
                         let steps_txt = d
                             .steps
                             .iter()
-                            .map(|term| term.to_string())
+                            .map(|term| {
+                                if self.raw {
+                                    term.to_string()
+                                } else {
+                                    term.with_names(&self.names).to_string()
+                                }
+                            })
                             .collect::<Vec<_>>()
                             .join("\n\n");
                         let mut txt = match &d.interpreted {
                             Ok(i) => {
                                 let reds = i.stats.reductions;
-                                let mut txt =
-                                    format!("Interpreted successfully. Reductions: {}", reds);
-                                if reds as usize > 10 {
-                                    txt.push_str(&format!("\n\nFinal result: {}", i.term));
+                                let mut txt = format!(
+                                    "Interpreted successfully with {}. Reductions: {}",
+                                    self.strategy.name().to_lowercase(),
+                                    reds
+                                );
+                                match &d.decoded {
+                                    Some(decoded) if !self.raw =>
+                                        txt.push_str(&format!("\n\nFinal result: {}", decoded)),
+                                    _ if reds as usize > 10 =>
+                                        txt.push_str(&format!("\n\nFinal result: {}", i.term)),
+                                    _ => {},
                                 }
                                 txt
                             },
                             Err(e) => format!("Failed to interpret: {}", e),
                         };
+                        match &d.inferred {
+                            Ok(ty) => txt.push_str(&format!("\n\nType: {}", ty)),
+                            Err(e) => txt.push_str(&format!("\n\nNo type: {}", e)),
+                        }
+                        if !d.profile.entries.is_empty() {
+                            txt.push_str(&format!("\n\nProfile:\n{}", d.profile));
+                        }
+                        txt.push_str(&format!("\n\nSKI form: {}", d.combinators));
+                        match &d.ski_normal {
+                            Ok(i) => txt.push_str(&format!(
+                                "\nReduces to {} in {} reductions",
+                                i.term, i.stats.reductions
+                            )),
+                            Err(e) => txt.push_str(&format!("\nFailed to reduce: {}", e)),
+                        }
                         if !steps_txt.is_empty() {
                             txt.push_str(&format!("\n\nStep by step reduction:\n\n{}", steps_txt));
                         }
@@ -68,28 +118,56 @@ impl<Editor: TextEditor> PlaygroundState<Editor> {
                 },
             }
         } else {
-            "Evaluate some term to see results here...\n\nAll code loaded on playground is lost when it's closed.".to_string()
+            "Evaluate some term to see results here...\n\nClick the button next to Evaluate to change the reduction strategy. Levels always use call-by-name.\n\nFinal results are decoded into numbers, booleans, lists and trees when possible. Known constants are shown by name. Click Raw to see the full terms instead.\n\nThe type of the term is also shown when it has one, which tells the order of the arguments of functions.\n\nThe SKI form is the same term written only with combinators, which are reduced on their own too.\n\nThe profile counts the reductions of the functions written in each constant, and the largest term they produced.\n\nAll code loaded on playground is lost when it's closed.".to_string()
         };
-        data.text_box(
+        let text_lines = data.text_box(
             "Run details",
             &txt,
             Rect::new(0, EDITOR_W, W - EDITOR_W, H),
             false,
         );
+        let err_span = match &self.data {
+            Some(Ok(Err(e))) => Some(e.span()),
+            Some(Err(e)) => Some(e.span()),
+            _ => None,
+        };
+        if let Some(span) = err_span {
+            let lines = span.caret_diagnostic(&self.code, (W - EDITOR_W) as usize - 4);
+            for (i, line) in lines.iter().enumerate() {
+                data.print(Pos::new(3 + text_lines + i as i32, EDITOR_W + 2), line);
+            }
+        }
     }
 
     fn eval(&mut self) {
-        self.data = Some(tokenize(self.editor.to_string().chars()).map(|tokens| {
-            parse(tokens).map(|term| DebugData {
-                steps:       std::iter::once(term.clone())
-                    .chain(interpret_itermediates(
-                        term.clone(),
-                        false,
-                        self.provider.clone(),
-                    ))
-                    .take(MAX_STEPS)
-                    .collect(),
-                interpreted: interpret(term, false, self.provider.clone()),
+        self.code = self.editor.to_string();
+        self.data = Some(tokenize(self.code.chars()).map(|tokens| {
+            parse(tokens).map(|term| {
+                let (interpreted, profile) =
+                    interpret_profiled(term.clone(), self.strategy, self.provider.clone());
+                let inferred = infer_type(&term, &self.provider);
+                let combinators = to_combinators(&term);
+                let ski_normal = reduce_combinators(&term, self.strategy, self.provider.clone());
+                DebugData {
+                    steps: std::iter::once(term.clone())
+                        .chain(interpret_itermediates(
+                            term,
+                            self.strategy,
+                            self.provider.clone(),
+                        ))
+                        .take(MAX_STEPS)
+                        .collect(),
+                    decoded: interpreted.as_ref().ok().map(|i| {
+                        decode(&i.term, self.provider.numerals(), CancelToken::default())
+                            .with_names(&self.names)
+                            .to_string()
+                    }),
+                    interpreted,
+                    profile,
+                    inferred,
+                    combinators,
+                    ski_normal,
+                }
             })
         }));
     }
@@ -103,12 +181,38 @@ impl<Editor: TextEditor> GameState for PlaygroundState<Editor> {
 
         data.instructions(&["Press ESC to go back", "Press CTRL+ENTER to evaluate"]);
 
-        if data.button("Evaluate", Pos::new(H - 3, 0), black())
+        const EVALUATE: &str = "Evaluate";
+        if data.button(EVALUATE, Pos::new(H - 3, 0), black())
             || (data.ctrl && data.pressed_key == Some(Key::Return))
         {
             SFX::Confirm.play();
             self.eval();
         }
+        let raw_text = if self.raw { "Decoded" } else { "Raw" };
+        if data.button(
+            raw_text,
+            Pos::new(
+                H - 3,
+                EVALUATE.len() as i32 + self.strategy.name().len() as i32 + 6,
+            ),
+            black(),
+        ) {
+            SFX::Select.play();
+            self.raw = !self.raw;
+        }
+        if data.button(
+            self.strategy.name(),
+            Pos::new(H - 3, EVALUATE.len() as i32 + 3),
+            black(),
+        ) {
+            SFX::Select.play();
+            let all = ReductionStrategy::ALL;
+            let cur = all.iter().position(|s| *s == self.strategy).unwrap_or(0);
+            self.strategy = all[(cur + 1) % all.len()];
+            if self.data.is_some() {
+                self.eval();
+            }
+        }
 
         self.print_run_details(&mut data);
 
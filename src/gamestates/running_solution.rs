@@ -4,34 +4,44 @@ use crossbeam::channel;
 
 use super::{base::*, show_results::ShowResultsState};
 use crate::{
-    interpreter::ConstantProvider, levels::{get_result, Level, TestRunResults}, math::*, prelude::*, save_system::SaveProfile
+    interpreter::{CancelToken, ConstantProvider}, levels::{get_result, Level, TestRunResults}, math::*, prelude::*, save_system::SaveProfile
 };
 #[derive(Debug)]
 pub struct RunningSolutionState {
     level:        Level,
+    code:         String,
     save_profile: Arc<SaveProfile>,
     #[allow(unused)]
     handle:       thread::JoinHandle<()>,
     receiver:     channel::Receiver<TestRunResults>,
+    /// Stops the thread running the solution
+    cancel:       CancelToken,
 }
 
 impl RunningSolutionState {
     pub fn new(level: Level, code: String, save_profile: Arc<SaveProfile>) -> Self {
         let (sender, receiver) = channel::bounded(0);
         let provider = ConstantProvider::new(level.clone(), Some(save_profile.clone()));
+        let cancel = CancelToken::default();
         let handle = std::thread::spawn({
             let level = level.clone();
+            let code = code.clone();
+            let cancel = cancel.clone();
             move || {
-                sender
-                    .send(level.test(code.chars(), provider))
-                    .debug_unwrap()
+                let results = level.test_cancellable(code.chars(), provider, cancel.clone());
+                // Nobody is listening anymore if the run was cancelled
+                if !cancel.is_cancelled() {
+                    sender.send(results).debug_unwrap()
+                }
             }
         });
         Self {
             level,
+            code,
             save_profile,
             handle,
             receiver,
+            cancel,
         }
     }
 }
@@ -59,10 +69,12 @@ impl GameState for RunningSolutionState {
             }
             GameStateEvent::Switch(Box::new(ShowResultsState::new(
                 self.level.clone(),
+                std::mem::take(&mut self.code),
                 results,
                 self.save_profile.clone(),
             )))
         } else if data.pressed_key == Some(bl::VirtualKeyCode::Escape) {
+            self.cancel.cancel();
             SFX::Back.play();
             GameStateEvent::Pop(1)
         } else {
@@ -1,13 +1,107 @@
+use std::ops::Range;
+
+use crossbeam::channel::Receiver;
+
 use super::{base::*, debugger::DebuggerState};
 use crate::{
-    drawables::{black, Leaderboards}, interpreter::InterpretError, levels::{get_result, Level, TestRunResults}, math::*, prelude::*, save_system::{LevelResult, SaveProfile}
+    drawables::{black, gray, white, Leaderboards}, interpreter::{
+        decode, interpret_profiled, CancelToken, ConstantNames, ConstantProvider, InterpretError, Node, Profile
+    }, levels::{get_result, Expected, Level, TestCaseRun, TestCaseRuns, TestRunResults}, math::*, prelude::*, save_system::{LevelResult, SaveProfile}
 };
 #[derive(Debug)]
 pub struct ShowResultsState {
-    level:        Level,
-    save_profile: Arc<SaveProfile>,
-    results:      TestRunResults,
-    leaderboards: Leaderboards,
+    level:         Level,
+    /// Code that was run, used to point to errors
+    code:          String,
+    save_profile:  Arc<SaveProfile>,
+    results:       TestRunResults,
+    leaderboards:  Leaderboards,
+    /// Decoded result of each run, if it didn't fail. Normalizing the results to decode them may
+    /// take a while, so it is done in the background and they are shown raw until then.
+    decoded:       Option<Vec<Option<String>>>,
+    decoded_recv:  Option<Receiver<Vec<Option<String>>>>,
+    /// Constants of the level, shared with the debugger so their names are built once
+    provider:      ConstantProvider,
+    /// Names of the constants, to show them in terms that are not decoded
+    names:         ConstantNames,
+    /// Feedback of the known wrong solution that fails the same way, if any
+    feedback:      Option<String>,
+    /// Gets the feedback while it is being looked for in the background
+    feedback_recv: Option<Receiver<Option<String>>>,
+    /// Stops the work done in the background once the results are left
+    cancel:        CancelToken,
+    /// Show results as lambda terms instead of decoded
+    raw:           bool,
+    /// Reductions of all test cases by constant, computed in the background the first time it
+    /// is shown
+    profile:       Option<Profile>,
+    profile_recv:  Option<Receiver<Profile>>,
+    view:          View,
+}
+
+/// What is shown about the test cases
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    /// Result of each one
+    Tests,
+    /// Table with the numbers of each one
+    Stats,
+    /// Reductions of all of them by constant
+    Profile,
+    /// Result of a wrong one next to the expected result
+    Diff(usize),
+}
+
+impl View {
+    const ALL: [View; 3] = [View::Tests, View::Stats, View::Profile];
+
+    fn name(self) -> &'static str {
+        match self {
+            View::Tests => "Tests",
+            View::Stats => "Stats",
+            View::Profile => "Profile",
+            View::Diff(_) => "Diff",
+        }
+    }
+}
+
+fn difference_color() -> bl::RGBA { bl::RGBA::from_u8(150, 50, 50, 255) }
+
+/// Prints the text wrapped to the width, with the marked characters highlighted and scrolled to
+/// be shown
+fn print_marked(
+    data: &mut TickData,
+    text: &str,
+    mark: Option<Range<usize>>,
+    pos: Pos,
+    width: usize,
+    lines: usize,
+) {
+    let chars: Vec<char> = text.chars().collect();
+    let total = (chars.len() + width - 1) / width;
+    let first = mark
+        .as_ref()
+        .map_or(0, |r| (r.start / width).saturating_sub(1))
+        .min(total.saturating_sub(lines));
+    for (k, c) in chars
+        .iter()
+        .enumerate()
+        .skip(first * width)
+        .take(lines * width)
+    {
+        let bg = if mark.as_ref().map_or(false, |r| r.contains(&k)) {
+            difference_color()
+        } else {
+            black()
+        };
+        data.console.set(
+            pos.j + (k % width) as i32,
+            pos.i + (k / width - first) as i32,
+            white(),
+            bg,
+            bl::to_cp437(*c),
+        );
+    }
 }
 
 const BOX_W: i32 = 60;
@@ -15,7 +109,12 @@ const LDB_W: i32 = 40;
 const BOX_H: i32 = 30;
 
 impl ShowResultsState {
-    pub fn new(level: Level, results: TestRunResults, save_profile: Arc<SaveProfile>) -> Self {
+    pub fn new(
+        level: Level,
+        code: String,
+        results: TestRunResults,
+        save_profile: Arc<SaveProfile>,
+    ) -> Self {
         save_profile.mark_level_as_tried(&level, get_result(&results));
         let stats = match get_result(&results) {
             LevelResult::Success { stats } => Some(stats),
@@ -27,8 +126,55 @@ impl ShowResultsState {
             LDB_W,
             BOX_H,
         );
+        let provider = ConstantProvider::new(level.clone(), Some(save_profile.clone()));
+        let names = provider.names();
+        let cancel = CancelToken::default();
+        let decoded_recv = results.as_ref().ok().map(|runs| {
+            let (sender, receiver) = crossbeam::channel::bounded(1);
+            let terms: Vec<_> = runs
+                .runs
+                .iter()
+                .map(|run| run.result.as_ref().ok().map(|r| r.term.clone()))
+                .collect();
+            let numerals = level.base().numerals;
+            let names = names.clone();
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                let decoded = terms
+                    .iter()
+                    .map(|term| {
+                        term.as_ref().map(|term| {
+                            decode(term, numerals, cancel.clone())
+                                .with_names(&names)
+                                .to_string()
+                        })
+                    })
+                    .collect();
+                // Nobody is listening anymore if the results were left
+                sender.send(decoded).ok();
+            });
+            receiver
+        });
+        // Running the wrong solutions may take a while, so they are run in the background
+        let feedback_recv = results
+            .as_ref()
+            .ok()
+            .map(|runs| runs.outcomes())
+            .filter(|outcomes| !outcomes.all_correct())
+            .map(|outcomes| {
+                let (sender, receiver) = crossbeam::channel::bounded(1);
+                let level = level.clone();
+                let cancel = cancel.clone();
+                std::thread::spawn(move || {
+                    let feedback = level.feedback(&outcomes, cancel).map(str::to_string);
+                    // Nobody is listening anymore if the results were left
+                    sender.send(feedback).ok();
+                });
+                receiver
+            });
         Self {
             level: level.clone(),
+            code,
             save_profile,
             results,
             leaderboards: Leaderboards::new(
@@ -37,61 +183,348 @@ impl ShowResultsState {
                 stats,
                 Rect::new(ldr.pos.i + BOX_H + 1, ldr.pos.j, ldr.size.w, ldr.size.h),
             ),
+            decoded: None,
+            decoded_recv,
+            provider,
+            names,
+            feedback: None,
+            feedback_recv,
+            cancel,
+            raw: false,
+            profile: None,
+            profile_recv: None,
+            view: View::Tests,
+        }
+    }
+}
+
+/// Table with the numbers of each test case, pointing out the one with the most reductions
+fn stats_table(runs: &TestCaseRuns) -> Vec<String> {
+    let row = |test: &str, reductions: &str, peak_size: &str, depth: &str, time: &str| {
+        format!(
+            "{:<6}{:>11}{:>10}{:>8}{:>11}",
+            test, reductions, peak_size, depth, time
+        )
+    };
+    let mut lines = vec![row("Test", "Reductions", "Peak size", "Depth", "Time")];
+    let worst = runs.worst();
+    for (i, run) in runs.runs.iter().enumerate() {
+        let (reductions, peak_size, depth) = match &run.result {
+            Ok(r) => (
+                r.stats.reductions.to_string(),
+                r.usage.peak_size.to_string(),
+                r.usage.max_depth.to_string(),
+            ),
+            Err(_) => ("-".to_string(), "-".to_string(), "-".to_string()),
+        };
+        let mut line = row(
+            &format!("#{}", i),
+            &reductions,
+            &peak_size,
+            &depth,
+            &format!("{:.2} ms", run.time.as_secs_f64() * 1000.0),
+        );
+        if worst == Some(i) {
+            line.push_str(" <- worst");
         }
+        lines.push(line);
+    }
+    if let Some(i) = worst {
+        lines.push(String::new());
+        lines.push(format!("Test Case #{} has the most reductions", i));
     }
+    lines
+}
+
+impl ShowResultsState {
+    /// Shows the closest expected result and the result of the run side by side, in the form the
+    /// test case compares them, with the first subterm where they differ highlighted
+    fn draw_diff(&self, data: &mut TickData, idx: usize, run: &TestCaseRun, i: i32, rect: Rect) {
+        let (expected, actual, difference) = match run.difference() {
+            Some(d) => d,
+            None => {
+                data.print(
+                    Pos::new(i, rect.left() + 2),
+                    &format!("Test Case #{}: no difference with the expected", idx),
+                );
+                return;
+            },
+        };
+        let line: String = format!("Test Case #{}: {}", idx, difference)
+            .chars()
+            .take(rect.size.w as usize - 4)
+            .collect();
+        data.print(Pos::new(i, rect.left() + 2), &line);
+        let width = (rect.size.w - 6) / 2;
+        let lines = (rect.bottom() - 6 - (i + 3)) as usize;
+        for (k, (title, term)) in [("Expected", &expected), ("Got", &actual)]
+            .iter()
+            .enumerate()
+        {
+            let j = rect.left() + 2 + k as i32 * (width + 2);
+            data.print(Pos::new(i + 2, j), title);
+            let shown = if self.raw {
+                (*term).clone()
+            } else {
+                self.names.fold_around(term, &difference.path)
+            };
+            let (text, found) = shown.to_string_marking(&[difference.path.as_slice()]);
+            print_marked(
+                data,
+                &text,
+                found[0].clone(),
+                Pos::new(i + 3, j),
+                width as usize,
+                lines,
+            );
+        }
+    }
+}
+
+/// Runs the test expressions again, profiling them the same way they were tested
+fn profile_runs(level: &Level, expressions: Vec<Box<Node>>, cancel: CancelToken) -> Profile {
+    let provider = ConstantProvider::all().with_numerals(level.base().numerals);
+    expressions
+        .into_par_iter()
+        .map(|expression| {
+            interpret_profiled(
+                expression,
+                level.base().interpret_options(cancel.clone()),
+                provider.clone(),
+            )
+            .1
+        })
+        .reduce(Profile::default, |mut acc, profile| {
+            acc.merge(&profile);
+            acc
+        })
 }
 
 const DEBUG: &str = "Explain";
+const DIFF: &str = "Diff";
+const SHOW_RAW: &str = "Show raw";
+const SHOW_DECODED: &str = "Show decoded";
+const LOOKING_FOR_HINT: &str = "Looking for a hint";
+const PROFILING: &str = "Running the test cases again";
 
 impl GameState for ShowResultsState {
     fn name(&self) -> &'static str { "ShowResults" }
 
     fn tick(&mut self, mut data: TickData) -> GameStateEvent {
+        if let Some(feedback) = self.feedback_recv.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.feedback = feedback;
+            self.feedback_recv = None;
+        }
+        if let Some(decoded) = self.decoded_recv.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.decoded = Some(decoded);
+            self.decoded_recv = None;
+        }
+        if let Some(profile) = self.profile_recv.as_ref().and_then(|r| r.try_recv().ok()) {
+            self.profile = Some(profile);
+            self.profile_recv = None;
+        }
         let text = if let Err(err) = &self.results {
             format!("Failed to parse expression:\n{}", err)
         } else {
             "Parsed expression successfully.".to_owned()
         };
         let ret = Rect::new((H - BOX_H) / 2, (W - BOX_W - LDB_W - 6) / 2, BOX_W, BOX_H);
-        data.text_box("Solution results", &text, ret.clone(), true);
+        let text_lines = data.text_box("Solution results", &text, ret.clone(), true);
+        if let Err(err) = &self.results {
+            // Below the message, however many lines it was wrapped into, after an empty line
+            let top = ret.pos.i + 3 + text_lines;
+            let lines = err.span().caret_diagnostic(&self.code, BOX_W as usize - 4);
+            for (i, line) in lines.iter().enumerate() {
+                data.print(Pos::new(top + i as i32, ret.pos.j + 2), line);
+            }
+        }
 
         let mut instructions = Vec::with_capacity(2);
         instructions.push("Press ESC to go back to editor");
         let mut success = false;
 
         if let Ok(runs) = &self.results {
+            let toggle = if self.raw { SHOW_DECODED } else { SHOW_RAW };
+            if data.button(
+                toggle,
+                Pos::new(
+                    ret.pos.i + 1,
+                    ret.pos.j + ret.size.w - toggle.len() as i32 - 4,
+                ),
+                black(),
+            ) {
+                SFX::Select.play();
+                self.raw = !self.raw;
+            }
+            let mut j = ret.pos.j + ret.size.w - 2;
+            for view in View::ALL.iter().rev() {
+                j -= view.name().len() as i32 + 3;
+                let bg = if *view == self.view { gray() } else { black() };
+                if data.button(view.name(), Pos::new(ret.bottom() - 4, j), bg) {
+                    SFX::Select.play();
+                    self.view = *view;
+                }
+            }
             let mut cur_i = ret.pos.i + 5;
-            for (i, run) in runs.runs.iter().enumerate() {
-                let result_str = match &run.result {
-                    Ok(node) =>
-                        if node.term == run.expected_result {
-                            format!("SUCCESS! ({} reductions)", node.stats.reductions)
-                        } else {
-                            "WRONG ANSWER!".to_owned()
-                        },
-                    Err(err) => match err {
-                        InterpretError::AlgorithmError => "UNKNOWN ERROR, CONTACT DEVELOPERS!",
-                        InterpretError::TooDeep => "NO REDUCTION (INFINITE LOOP)",
-                        InterpretError::TooLarge => "NO REDUCTION (GREW TOO BIG)",
+            match self.view {
+                View::Stats => {
+                    let lines = stats_table(runs);
+                    let max_lines = (ret.bottom() - 6 - cur_i) as usize;
+                    for (k, line) in lines.iter().take(max_lines).enumerate() {
+                        data.print(Pos::new(cur_i + k as i32, ret.pos.j + 2), line);
                     }
-                    .to_owned(),
-                };
-                data.print(
-                    Pos::new(cur_i, ret.pos.j + 2),
-                    &format!("Test Case #{}: {}", i, result_str),
-                );
-                if data.button(
-                    DEBUG,
-                    Pos::new(cur_i - 1, ret.pos.j + ret.size.w - DEBUG.len() as i32 - 4),
-                    black(),
-                ) {
-                    return GameStateEvent::Push(Box::new(DebuggerState::new(
-                        self.level.clone(),
-                        self.save_profile.clone(),
-                        run.clone(),
-                    )));
-                }
-                cur_i += 3;
+                },
+                View::Profile =>
+                    if let Some(profile) = &self.profile {
+                        data.print(
+                            Pos::new(cur_i, ret.pos.j + 2),
+                            "Reductions of all test cases, by function origin:",
+                        );
+                        let table = profile.to_string();
+                        let max_lines = (ret.bottom() - 6 - cur_i) as usize;
+                        for (k, line) in table.lines().take(max_lines).enumerate() {
+                            data.print(Pos::new(cur_i + 2 + k as i32, ret.pos.j + 2), line);
+                        }
+                    } else {
+                        // Running the test cases again may take a while
+                        if self.profile_recv.is_none() {
+                            let (sender, receiver) = crossbeam::channel::bounded(1);
+                            let level = self.level.clone();
+                            let expressions: Vec<_> = runs
+                                .runs
+                                .iter()
+                                .map(|r| r.test_expression.clone())
+                                .collect();
+                            let cancel = self.cancel.clone();
+                            std::thread::spawn(move || {
+                                // Nobody is listening anymore if the results were left
+                                sender.send(profile_runs(&level, expressions, cancel)).ok();
+                            });
+                            self.profile_recv = Some(receiver);
+                        }
+                        let dots = (data.time.as_millis() / 500) % 4;
+                        data.print(
+                            Pos::new(cur_i, ret.pos.j + 2),
+                            &format!("{}{}", PROFILING, ".".repeat(dots as usize)),
+                        );
+                    },
+                View::Diff(idx) => self.draw_diff(&mut data, idx, &runs.runs[idx], cur_i, ret),
+                View::Tests => {
+                    if self.feedback_recv.is_some() {
+                        let dots = (data.time.as_millis() / 500) % 4;
+                        data.print(
+                            Pos::new(cur_i, ret.pos.j + 2),
+                            &format!("{}{}", LOOKING_FOR_HINT, ".".repeat(dots as usize)),
+                        );
+                        cur_i += 2;
+                    } else if let Some(feedback) = &self.feedback {
+                        for line in
+                            textwrap::wrap(&format!("Hint: {}", feedback), BOX_W as usize - 4)
+                        {
+                            data.print(Pos::new(cur_i, ret.pos.j + 2), &line);
+                            cur_i += 1;
+                        }
+                        cur_i += 1;
+                    }
+                    for (i, run) in runs.runs.iter().enumerate() {
+                        let result_str = match &run.result {
+                            Ok(node) =>
+                                if run.is_correct() {
+                                    format!("SUCCESS! ({} reductions)", node.stats.reductions)
+                                } else if let Expected::Predicate(_) = run.expected {
+                                    "WRONG ANSWER! (REJECTED BY CHECK)".to_owned()
+                                } else {
+                                    "WRONG ANSWER!".to_owned()
+                                },
+                            Err(err) => match err {
+                                InterpretError::AlgorithmError =>
+                                    "UNKNOWN ERROR, CONTACT DEVELOPERS!".to_owned(),
+                                InterpretError::TooDeep =>
+                                    "NO REDUCTION (INFINITE LOOP)".to_owned(),
+                                InterpretError::TooLarge =>
+                                    "NO REDUCTION (GREW TOO BIG)".to_owned(),
+                                InterpretError::Cancelled => "CANCELLED".to_owned(),
+                                InterpretError::Timeout =>
+                                    "NO REDUCTION (TOOK TOO LONG)".to_owned(),
+                                InterpretError::TooManyReductions =>
+                                    "TOO MANY REDUCTIONS".to_owned(),
+                                InterpretError::Loop { period, .. } =>
+                                    format!("LOOPS EVERY {} REDUCTIONS", period),
+                            },
+                        };
+                        data.print(
+                            Pos::new(cur_i, ret.pos.j + 2),
+                            &format!("Test Case #{}: {}", i, result_str),
+                        );
+                        let second_line = match &run.result {
+                            Ok(r) => Some(format!(
+                                "Result: {}",
+                                match &self.decoded {
+                                    Some(decoded) if !self.raw =>
+                                        decoded[i].clone().unwrap_or_default(),
+                                    _ => r.term.to_string(),
+                                }
+                            )),
+                            Err(InterpretError::Loop { term, .. }) => Some(format!(
+                                "Repeats: {}",
+                                if self.raw {
+                                    term.to_string()
+                                } else {
+                                    term.with_names(&self.names).to_string()
+                                }
+                            )),
+                            Err(_) => None,
+                        };
+                        // Wrong results can be compared with the expected ones
+                        let has_diff = run.result.is_ok()
+                            && !run.is_correct()
+                            && matches!(run.expected, Expected::AnyOf(_));
+                        if let Some(line) = second_line {
+                            let mut width = BOX_W as usize - DEBUG.len() - 8;
+                            if has_diff {
+                                width -= DIFF.len() + 3;
+                            }
+                            let line: String = line.chars().take(width).collect();
+                            data.print(Pos::new(cur_i + 1, ret.pos.j + 2), &line);
+                        }
+                        if has_diff
+                            && data.button(
+                                DIFF,
+                                Pos::new(
+                                    cur_i - 1,
+                                    ret.pos.j + ret.size.w
+                                        - DEBUG.len() as i32
+                                        - DIFF.len() as i32
+                                        - 7,
+                                ),
+                                black(),
+                            )
+                        {
+                            SFX::Select.play();
+                            self.view = View::Diff(i);
+                        }
+                        if data.button(
+                            DEBUG,
+                            Pos::new(cur_i - 1, ret.pos.j + ret.size.w - DEBUG.len() as i32 - 4),
+                            black(),
+                        ) {
+                            return GameStateEvent::Push(Box::new(DebuggerState::new(
+                                self.level.clone(),
+                                self.provider.clone(),
+                                run.clone(),
+                            )));
+                        }
+                        if let Some(hint) = &run.hint {
+                            let line: String = format!("Hint: {}", hint)
+                                .chars()
+                                .take(BOX_W as usize - 4)
+                                .collect();
+                            data.print(Pos::new(cur_i + 2, ret.pos.j + 2), &line);
+                            cur_i += 1;
+                        }
+                        cur_i += 3;
+                    }
+                },
             }
             if let LevelResult::Success { stats } = get_result(&self.results) {
                 success = true;
@@ -117,9 +550,11 @@ impl GameState for ShowResultsState {
         data.instructions(&instructions);
 
         if data.pressed_key == Some(bl::VirtualKeyCode::Escape) {
+            self.cancel.cancel();
             SFX::Back.play();
             GameStateEvent::Pop(1)
         } else if success && data.pressed_key == Some(bl::VirtualKeyCode::Return) {
+            self.cancel.cancel();
             SFX::Confirm.play();
             GameStateEvent::Pop(2)
         } else {
@@ -13,16 +13,23 @@ pub struct StringReaderState {
     max_width: i32,
     sender:    Sender<Option<String>>,
     editor:    BasicTextEditor,
+    /// What pressing ENTER does, shown in the instructions
+    action:    &'static str,
 }
 
 impl StringReaderState {
-    pub fn new(title: String, max_width: i32) -> (Self, Receiver<Option<String>>) {
+    pub fn new(
+        title: String,
+        action: &'static str,
+        max_width: i32,
+    ) -> (Self, Receiver<Option<String>>) {
         let (send, recv) = bounded(1);
         (
             Self {
                 max_width,
                 sender: send,
                 editor: BasicTextEditor::new(title, Rect::centered(max_width, 1), String::new()),
+                action,
             },
             recv,
         )
@@ -34,7 +41,8 @@ impl GameState for StringReaderState {
 
     fn tick(&mut self, mut data: TickData) -> GameStateEvent {
         self.editor.draw(&mut data);
-        data.instructions(&["Press ENTER to create level", "Press ESC to go back"]);
+        let action = format!("Press ENTER to {}", self.action);
+        data.instructions(&[&action, "Press ESC to go back"]);
         if data.pressed_key == Some(Key::Escape) {
             self.sender.send(None).debug_unwrap();
             GameStateEvent::Pop(1)
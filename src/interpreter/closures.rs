@@ -0,0 +1,338 @@
+use std::{
+    cell::{Cell, RefCell}, collections::HashMap, rc::Rc
+};
+
+use super::{
+    interpreter::{InterpretError, MAX_SIZE}, parser::{Node, Variable}, tokenizer::{Constant, TVariable}, traversers::{all_constants, subterms}, ConstantProvider, Span
+};
+
+// Pieces shared by the evaluators that keep the arguments in an environment instead of
+// substituting them into the term.
+
+/// Argument that was not yet evaluated, together with the variables it can see.
+pub struct ThunkData<'a> {
+    pub term:  &'a Node,
+    pub env:   Env<'a>,
+    /// Value of the term, and how many reductions it took to evaluate it. Only call-by-need
+    /// fills it, call-by-name evaluates the thunk every time.
+    pub value: RefCell<Option<(Value<'a>, u32)>>,
+    /// Size of the term with the thunks of the environment expanded, once it is needed
+    size:      Cell<Option<u32>>,
+}
+
+pub type Thunk<'a> = Rc<ThunkData<'a>>;
+
+/// Thunks for the bound variables, the first one is the innermost variable (depth 0).
+pub type Env<'a> = Option<Rc<Frame<'a>>>;
+
+pub struct Frame<'a> {
+    thunk: Thunk<'a>,
+    next:  Env<'a>,
+    len:   usize,
+}
+
+/// Environment or thunk being dropped
+enum Owned<'a> {
+    Env(Env<'a>),
+    Thunk(Thunk<'a>),
+}
+
+/// Environments and thunks can form chains as long as the evaluation was deep, so they are
+/// dropped one by one instead of recursing. Only what is referenced from nowhere else is taken.
+fn drop_owned(mut owned: Vec<Owned>) {
+    while let Some(item) = owned.pop() {
+        match item {
+            Owned::Env(Some(mut frame)) =>
+                if let Some(frame) = Rc::get_mut(&mut frame) {
+                    owned.push(Owned::Env(frame.next.take()));
+                    owned.push(Owned::Thunk(frame.thunk.clone()));
+                },
+            Owned::Env(None) => {},
+            Owned::Thunk(mut thunk) =>
+                if let Some(data) = Rc::get_mut(&mut thunk) {
+                    owned.push(Owned::Env(data.env.take()));
+                    owned.extend(data.take_value());
+                },
+        }
+    }
+}
+
+impl<'a> ThunkData<'a> {
+    /// The environments and thunks of the value, if it was evaluated
+    fn take_value(&mut self) -> Vec<Owned<'a>> {
+        match self.value.get_mut().take() {
+            Some((Value::Function { env, .. }, _)) => vec![Owned::Env(env)],
+            Some((Value::Stuck { args, .. }, _)) =>
+                args.into_iter().map(|(arg, _)| Owned::Thunk(arg)).collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for ThunkData<'_> {
+    fn drop(&mut self) {
+        let mut owned = self.take_value();
+        owned.push(Owned::Env(self.env.take()));
+        drop_owned(owned);
+    }
+}
+
+impl Drop for Frame<'_> {
+    fn drop(&mut self) { drop_owned(vec![Owned::Env(self.next.take())]) }
+}
+
+pub fn env_len(env: &Env) -> usize { env.as_ref().map_or(0, |f| f.len) }
+
+pub fn env_push<'a>(env: Env<'a>, thunk: Thunk<'a>) -> Env<'a> {
+    let len = env_len(&env) + 1;
+    Some(Rc::new(Frame {
+        thunk,
+        next: env,
+        len,
+    }))
+}
+
+pub fn env_get<'a>(env: &Env<'a>, depth: usize) -> Option<Thunk<'a>> {
+    let mut cur = env.as_ref()?;
+    for _ in 0..depth {
+        cur = cur.next.as_ref()?;
+    }
+    Some(cur.thunk.clone())
+}
+
+#[derive(Clone)]
+pub enum Head<'a> {
+    /// Variable unbound in the whole term, with how many levels above the root it is
+    Free(&'a TVariable, usize, Span),
+    /// Constant that has no definition
+    Constant(&'a Constant, Span),
+}
+
+/// Result of evaluating a term, which is in weak head normal form.
+#[derive(Clone)]
+pub enum Value<'a> {
+    Function {
+        variable: &'a TVariable,
+        body:     &'a Node,
+        span:     Span,
+        env:      Env<'a>,
+    },
+    /// Something that can't be reduced applied to some arguments, with the span of each
+    /// application.
+    Stuck {
+        head: Head<'a>,
+        args: Vec<(Thunk<'a>, Span)>,
+    },
+}
+
+/// Thunk for the argument of an application evaluated in env
+pub fn argument<'a>(right: &'a Node, env: &Env<'a>) -> Thunk<'a> {
+    match right {
+        // No need for a new thunk if it would just point to another one. This also avoids long
+        // chains of thunks pointing to each other.
+        Node::Variable(v, _) if v.depth < env_len(env) => env_get(env, v.depth).unwrap(),
+        _ => Rc::new(ThunkData {
+            term:  right,
+            env:   env.clone(),
+            value: RefCell::new(None),
+            size:  Cell::new(None),
+        }),
+    }
+}
+
+enum Task<'a> {
+    /// Read back the term with the environment, `extra` and `nest`
+    Read(&'a Node, Env<'a>, usize, usize),
+    /// Wrap the last result in a function
+    Function(&'a TVariable, Span),
+    /// Apply the second to last result to the last one
+    Apply(Span),
+}
+
+/// Size of the term, taking the thunks whose size is known, and adding the others to `missing`
+fn known_size<'a>(term: &'a Node, env: &Env<'a>, missing: &mut Vec<Thunk<'a>>) -> u32 {
+    subterms(term, 0)
+        .map(|(node, cur_depth)| match node {
+            Node::Variable(v, _) if v.depth >= cur_depth => match env_get(env, v.depth - cur_depth)
+            {
+                Some(thunk) => thunk.size.get().unwrap_or_else(|| {
+                    missing.push(thunk);
+                    0
+                }),
+                None => 1,
+            },
+            Node::Apply { .. } => 0,
+            _ => 1,
+        })
+        .fold(0, u32::saturating_add)
+}
+
+/// Size of the term with the thunks of the environment in place of the variables, the term
+/// call-by-name would have built by substituting the arguments. The size of each thunk is only
+/// computed once, and the ones it uses are sized before it instead of recursing.
+pub fn expanded_size<'a>(term: &'a Node, env: &Env<'a>) -> u32 {
+    let mut missing = vec![];
+    loop {
+        let size = known_size(term, env, &mut missing);
+        if missing.is_empty() {
+            return size;
+        }
+        while let Some(thunk) = missing.last().cloned() {
+            if thunk.size.get().is_some() {
+                missing.pop();
+                continue;
+            }
+            let len = missing.len();
+            let size = known_size(thunk.term, &thunk.env, &mut missing);
+            if missing.len() == len {
+                thunk.size.set(Some(size));
+                missing.pop();
+            }
+        }
+    }
+}
+
+/// Turns values back into terms. Thunks are read back as the term they were created from, even if
+/// they were evaluated, since that is what call-by-name would have substituted.
+#[derive(Default)]
+pub struct Readback {
+    size: u32,
+}
+
+impl Readback {
+    fn grow(&mut self) -> Result<(), InterpretError> {
+        self.size += 1;
+        if self.size > MAX_SIZE {
+            Err(InterpretError::TooLarge)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `extra` is how many functions inside term we are, and `nest` how many functions inside
+    /// the resulting term.
+    fn term<'a>(
+        &mut self,
+        term: &'a Node,
+        env: Env<'a>,
+        extra: usize,
+        nest: usize,
+    ) -> Result<Box<Node>, InterpretError> {
+        // Read back on an explicit stack, since terms may be as deep as the evaluation went
+        let mut tasks = vec![Task::Read(term, env, extra, nest)];
+        let mut done: Vec<Box<Node>> = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Read(term, env, extra, nest) => match term {
+                    Node::Constant(..) => {
+                        self.grow()?;
+                        done.push(Box::new(term.clone()));
+                    },
+                    Node::Variable(v, span) =>
+                        if v.depth < extra {
+                            self.grow()?;
+                            done.push(Box::new(term.clone()));
+                        } else if let Some(thunk) = env_get(&env, v.depth - extra) {
+                            tasks.push(Task::Read(thunk.term, thunk.env.clone(), 0, nest));
+                        } else {
+                            self.grow()?;
+                            done.push(Box::new(Node::Variable(
+                                Variable {
+                                    depth:    nest + (v.depth - extra - env_len(&env)),
+                                    original: v.original.clone(),
+                                },
+                                *span,
+                            )));
+                        },
+                    Node::Function {
+                        variable,
+                        body,
+                        span,
+                    } => {
+                        self.grow()?;
+                        tasks.push(Task::Function(variable, *span));
+                        tasks.push(Task::Read(body, env, extra + 1, nest + 1));
+                    },
+                    Node::Apply { left, right, span } => {
+                        tasks.push(Task::Apply(*span));
+                        tasks.push(Task::Read(right, env.clone(), extra, nest));
+                        tasks.push(Task::Read(left, env, extra, nest));
+                    },
+                },
+                Task::Function(variable, span) => {
+                    let body = done.pop().unwrap();
+                    done.push(Box::new(Node::Function {
+                        variable: variable.clone(),
+                        body,
+                        span,
+                    }));
+                },
+                Task::Apply(span) => {
+                    let right = done.pop().unwrap();
+                    let left = done.pop().unwrap();
+                    done.push(Box::new(Node::Apply { left, right, span }));
+                },
+            }
+        }
+        Ok(done.pop().unwrap())
+    }
+
+    fn thunk(&mut self, thunk: &Thunk, nest: usize) -> Result<Box<Node>, InterpretError> {
+        self.term(thunk.term, thunk.env.clone(), 0, nest)
+    }
+
+    pub fn value(&mut self, value: &Value, nest: usize) -> Result<Box<Node>, InterpretError> {
+        match value {
+            Value::Function {
+                variable,
+                body,
+                span,
+                env,
+            } => {
+                self.grow()?;
+                Ok(Box::new(Node::Function {
+                    variable: (*variable).clone(),
+                    body:     self.term(body, env.clone(), 1, nest + 1)?,
+                    span:     *span,
+                }))
+            },
+            Value::Stuck { head, args } => {
+                self.grow()?;
+                let mut node = Box::new(match head {
+                    Head::Free(original, free, span) => Node::Variable(
+                        Variable {
+                            depth:    nest + free,
+                            original: (*original).clone(),
+                        },
+                        *span,
+                    ),
+                    Head::Constant(c, span) => Node::Constant((*c).clone(), *span),
+                });
+                for (arg, span) in args {
+                    node = Box::new(Node::Apply {
+                        left:  node,
+                        right: self.thunk(arg, nest)?,
+                        span:  *span,
+                    });
+                }
+                Ok(node)
+            },
+        }
+    }
+}
+
+/// Definitions for all constants used by the term, directly or through other constants
+pub fn used_constants(root: &Node, provider: &ConstantProvider) -> HashMap<Constant, Box<Node>> {
+    let mut constants = HashMap::new();
+    let mut pending: Vec<Constant> = all_constants(root).map(|(c, _)| c.clone()).collect();
+    while let Some(c) = pending.pop() {
+        if constants.contains_key(&c) {
+            continue;
+        }
+        if let Some(def) = provider.get(&c) {
+            pending.extend(all_constants(&def).map(|(c, _)| c.clone()));
+            constants.insert(c, def);
+        }
+    }
+    constants
+}
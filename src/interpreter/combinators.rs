@@ -0,0 +1,403 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::{
+    closures::used_constants, interpreter::{InterpretError, InterpretOptions, Interpreted, Limits, Stats, Usage, MAX_SIZE}, parser::{Node, Variable}, tokenizer::{Constant, TVariable}, ConstantProvider, Span
+};
+
+// Bracket abstraction: every function is replaced by combinators that move the argument to where
+// the variable was used, so the result has no functions or bound variables:
+// - x: x = I
+// - x: E = K E, if E doesn't use x
+// - x: E x = E, if E doesn't use x
+// - x: A B = C (x: A) B if only A uses x, B A (x: B) if only B uses x, or S (x: A) (x: B)
+//
+// Combinator terms are reduced in normal order, by rewriting the combinator at the head once it
+// has all of its arguments. Arguments are shared instead of copied, so S doesn't duplicate terms.
+//
+// Combinators are also constants, for the levels that don't allow functions. Their names end in
+// COMB, so they don't clash with the constants that levels use as placeholders, like B and C.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Combinator {
+    S,
+    K,
+    I,
+    B,
+    C,
+}
+
+impl Combinator {
+    pub const ALL: [Combinator; 5] = [
+        Combinator::S,
+        Combinator::K,
+        Combinator::I,
+        Combinator::B,
+        Combinator::C,
+    ];
+
+    /// Name of the constant
+    pub fn name(self) -> &'static str {
+        match self {
+            Combinator::S => "SCOMB",
+            Combinator::K => "KCOMB",
+            Combinator::I => "ICOMB",
+            Combinator::B => "BCOMB",
+            Combinator::C => "CCOMB",
+        }
+    }
+
+    /// How many arguments it needs before it can be reduced
+    pub fn arity(self) -> usize {
+        match self {
+            Combinator::I => 1,
+            Combinator::K => 2,
+            Combinator::S | Combinator::B | Combinator::C => 3,
+        }
+    }
+
+    /// Same combinator as a lambda term
+    pub fn definition(self) -> &'static str {
+        match self {
+            Combinator::S => "x:y:z: x z (y z)",
+            Combinator::K => "x:y: x",
+            Combinator::I => "x: x",
+            Combinator::B => "x:y:z: x (y z)",
+            Combinator::C => "x:y:z: x z y",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Ski {
+    Combinator(Combinator),
+    Constant(Constant),
+    /// Variable not bound in the term, with how many functions above the root it is
+    Free(TVariable, usize),
+    /// Variable of a function that is still being abstracted
+    Bound(usize),
+    Apply(Rc<Ski>, Rc<Ski>),
+}
+
+fn apply(left: Rc<Ski>, right: Rc<Ski>) -> Rc<Ski> { Rc::new(Ski::Apply(left, right)) }
+
+fn combinator(c: Combinator) -> Rc<Ski> { Rc::new(Ski::Combinator(c)) }
+
+fn uses_bound(ski: &Ski, depth: usize) -> bool {
+    match ski {
+        Ski::Bound(d) => *d == depth,
+        Ski::Apply(left, right) => uses_bound(left, depth) || uses_bound(right, depth),
+        Ski::Combinator(_) | Ski::Constant(_) | Ski::Free(..) => false,
+    }
+}
+
+/// Removes the innermost function, which must not be used
+fn shift(ski: &Rc<Ski>) -> Rc<Ski> {
+    match &**ski {
+        Ski::Bound(d) => Rc::new(Ski::Bound(d - 1)),
+        Ski::Apply(left, right) => apply(shift(left), shift(right)),
+        Ski::Combinator(_) | Ski::Constant(_) | Ski::Free(..) => ski.clone(),
+    }
+}
+
+/// Combinator term equal to a function with the given body
+fn abstract_bound(body: &Rc<Ski>) -> Rc<Ski> {
+    if !uses_bound(body, 0) {
+        return apply(combinator(Combinator::K), shift(body));
+    }
+    match &**body {
+        Ski::Apply(left, right) => match (uses_bound(left, 0), uses_bound(right, 0)) {
+            (false, true) if matches!(**right, Ski::Bound(0)) => shift(left),
+            (false, _) => apply(
+                apply(combinator(Combinator::B), shift(left)),
+                abstract_bound(right),
+            ),
+            (true, false) => apply(
+                apply(combinator(Combinator::C), abstract_bound(left)),
+                shift(right),
+            ),
+            (true, true) => apply(
+                apply(combinator(Combinator::S), abstract_bound(left)),
+                abstract_bound(right),
+            ),
+        },
+        // The only term that uses the variable without applications is the variable itself
+        _ => combinator(Combinator::I),
+    }
+}
+
+fn from_node(node: &Node, cur_depth: usize) -> Rc<Ski> {
+    match node {
+        Node::Constant(c, _) => Rc::new(Ski::Constant(c.clone())),
+        Node::Variable(v, _) =>
+            if v.depth < cur_depth {
+                Rc::new(Ski::Bound(v.depth))
+            } else {
+                Rc::new(Ski::Free(v.original.clone(), v.depth - cur_depth))
+            },
+        Node::Function { body, .. } => abstract_bound(&from_node(body, cur_depth + 1)),
+        Node::Apply { left, right, .. } =>
+            apply(from_node(left, cur_depth), from_node(right, cur_depth)),
+    }
+}
+
+fn to_node(ski: &Ski) -> Box<Node> {
+    let span = Span::default();
+    Box::new(match ski {
+        Ski::Combinator(c) => Node::Constant(c.name().to_string(), span),
+        Ski::Constant(c) => Node::Constant(c.clone(), span),
+        Ski::Free(original, depth) => Node::Variable(
+            Variable {
+                depth:    *depth,
+                original: original.clone(),
+            },
+            span,
+        ),
+        Ski::Bound(_) => unreachable!("All functions are abstracted"),
+        Ski::Apply(left, right) => Node::Apply {
+            left: to_node(left),
+            right: to_node(right),
+            span,
+        },
+    })
+}
+
+/// Same term with only applications of combinators, constants and unbound variables. Combinators
+/// are shown as their constants, so the result means the same as the term.
+pub fn to_combinators(node: &Node) -> Box<Node> { to_node(&from_node(node, 0)) }
+
+/// What is left to do to get the normal form
+enum Task {
+    Normalize(Rc<Ski>, usize),
+    /// Apply the head to the last `args` normalized terms
+    Rebuild {
+        head: Rc<Ski>,
+        args: usize,
+    },
+}
+
+struct Reducer<'a> {
+    options:    &'a InterpretOptions,
+    limits:     Limits,
+    reductions: u32,
+    usage:      Usage,
+    /// Definitions of the constants, already as combinator terms
+    constants:  HashMap<Constant, Rc<Ski>>,
+}
+
+impl<'a> Reducer<'a> {
+    /// Rewrites the head until it can't be reduced. Returns the head and its arguments, with the
+    /// first argument at the end.
+    fn head_normal(
+        &mut self,
+        term: Rc<Ski>,
+        level: &mut usize,
+    ) -> Result<(Rc<Ski>, Vec<Rc<Ski>>), InterpretError> {
+        let mut head = term;
+        let mut args = vec![];
+        loop {
+            if *level > self.options.max_depth {
+                return Err(InterpretError::TooDeep);
+            }
+            self.usage.max_depth = self.usage.max_depth.max(*level);
+            self.limits.check(self.reductions)?;
+            match &*head {
+                Ski::Apply(left, right) => {
+                    args.push(right.clone());
+                    head = left.clone();
+                },
+                Ski::Constant(c) if self.constants.contains_key(c) => {
+                    head = self.constants[c].clone();
+                    *level += 1;
+                },
+                Ski::Combinator(c) if args.len() >= c.arity() => {
+                    let c = *c;
+                    let mut arg = || args.pop().unwrap();
+                    head = match c {
+                        Combinator::I => arg(),
+                        Combinator::K => {
+                            let (x, _) = (arg(), arg());
+                            x
+                        },
+                        Combinator::S => {
+                            let (x, y, z) = (arg(), arg(), arg());
+                            apply(apply(x, z.clone()), apply(y, z))
+                        },
+                        Combinator::B => {
+                            let (x, y, z) = (arg(), arg(), arg());
+                            apply(x, apply(y, z))
+                        },
+                        Combinator::C => {
+                            let (x, y, z) = (arg(), arg(), arg());
+                            apply(apply(x, z), y)
+                        },
+                    };
+                    self.reductions = self.reductions.saturating_add(1);
+                    *level += 1;
+                },
+                _ => return Ok((head, args)),
+            }
+        }
+    }
+
+    /// Normalizes without recursion, so it can go as deep as the other interpreters
+    fn normalize(&mut self, root: Rc<Ski>) -> Result<Box<Node>, InterpretError> {
+        let mut tasks = vec![Task::Normalize(root, 0)];
+        // Normalized terms with their sizes
+        let mut done: Vec<(Rc<Ski>, u32)> = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Normalize(term, mut level) => {
+                    let (head, args) = self.head_normal(term, &mut level)?;
+                    tasks.push(Task::Rebuild {
+                        head,
+                        args: args.len(),
+                    });
+                    // The first argument is at the end, so it is normalized first
+                    tasks.extend(args.into_iter().map(|arg| Task::Normalize(arg, level + 1)));
+                },
+                Task::Rebuild { head, args } => {
+                    let args = done.split_off(done.len() - args);
+                    let (term, size) = args
+                        .into_iter()
+                        .fold((head, 1), |(term, size), (arg, arg_size)| {
+                            (apply(term, arg), size + arg_size + 1)
+                        });
+                    if size > MAX_SIZE {
+                        return Err(InterpretError::TooLarge);
+                    }
+                    self.usage.peak_size = self.usage.peak_size.max(size);
+                    done.push((term, size));
+                },
+            }
+        }
+        match done.as_slice() {
+            [(term, _)] => Ok(to_node(term)),
+            _ => Err(InterpretError::AlgorithmError),
+        }
+    }
+}
+
+/// Translates the term to combinators and finds its normal form. Constants of the provider are
+/// translated too, and replaced when they are at the head. The strategy is ignored, combinator
+/// terms are always reduced in normal order.
+pub fn reduce_combinators<O: Into<InterpretOptions>>(
+    root: &Node,
+    options: O,
+    provider: ConstantProvider,
+) -> Result<Interpreted, InterpretError> {
+    let options = options.into();
+    let constants = used_constants(root, &provider)
+        .into_iter()
+        .map(|(c, def)| (c, from_node(&def, 0)))
+        .collect();
+    let mut reducer = Reducer {
+        options: &options,
+        limits: Limits::new(&options),
+        reductions: 0,
+        usage: Usage::default(),
+        constants,
+    };
+    let term = reducer.normalize(from_node(root, 0))?;
+    Ok(Interpreted {
+        term,
+        stats: Stats {
+            reductions: reducer.reductions,
+        },
+        usage: reducer.usage,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        super::{interpret, parser::test::parse_ok, ReductionStrategy}, *
+    };
+
+    fn combinators(code: &str) -> String { to_combinators(&parse_ok(code)).to_string() }
+
+    fn reduced(code: &str) -> Result<Box<Node>, InterpretError> {
+        reduce_combinators(
+            &parse_ok(code),
+            ReductionStrategy::Normal,
+            ConstantProvider::all(),
+        )
+        .map(|i| i.term)
+    }
+
+    #[test]
+    fn translation() {
+        assert_eq!(combinators("x: x"), "ICOMB");
+        assert_eq!(combinators("x: y: x"), "KCOMB");
+        assert_eq!(combinators("x: y: y"), "KCOMB ICOMB");
+        assert_eq!(combinators("f: x: f x"), "ICOMB");
+        assert_eq!(combinators("x: y: z: x z (y z)"), "SCOMB");
+        assert_eq!(combinators("x: y: z: x (y z)"), "BCOMB");
+        assert_eq!(combinators("x: y: z: x z y"), "CCOMB");
+        assert_eq!(combinators("x: x x"), "SCOMB ICOMB ICOMB");
+        assert_eq!(combinators("x: A"), "KCOMB A");
+        assert_eq!(combinators("y: x y"), "x");
+        // Not the combinators B and C
+        assert_eq!(combinators("A (x: B C)"), "A (KCOMB (B C))");
+    }
+
+    #[test]
+    fn combinators_are_constants() {
+        for c in Combinator::ALL {
+            assert_eq!(
+                ConstantProvider::all().get(c.name()).unwrap(),
+                parse_ok(c.definition())
+            );
+        }
+        // So the translation means the same as the term
+        let code = "(n: f:x: f (n f x)) 2 F X";
+        assert_eq!(
+            interpret(
+                to_combinators(&parse_ok(code)),
+                ReductionStrategy::Normal,
+                ConstantProvider::all()
+            )
+            .unwrap()
+            .term,
+            parse_ok("F (F (F X))")
+        );
+    }
+
+    #[test]
+    fn same_as_lambda() {
+        for code in [
+            "TRUE A B",
+            "FALSE A B",
+            "(x: y: x) A B",
+            "3 F X",
+            "(n: f:x: f (n f x)) 2 F X",
+            "POP (PUSH A FALSE) (h:t:d: t) B",
+            "(x: A) ((x: x x) (x: x x))",
+            "A ((x: x) B) C",
+        ] {
+            assert_eq!(
+                reduced(code).unwrap(),
+                interpret(
+                    parse_ok(code),
+                    ReductionStrategy::Normal,
+                    ConstantProvider::all()
+                )
+                .unwrap()
+                .term,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn no_normal_form() {
+        assert_eq!(
+            reduced("(x: x x) (x: x x)").unwrap_err(),
+            InterpretError::TooDeep
+        );
+        assert_eq!(
+            reduced("(x: A (x x)) (x: A (x x))").unwrap_err(),
+            InterpretError::TooDeep
+        );
+    }
+}
@@ -1,7 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    interpreter::{parse, tokenize, Node}, levels::{raw_load_level_config, Level, SectionName}, prelude::*, save_system::SaveProfile
+    interpreter::{
+        interpret, parse, tokenize, traversers::PathStep, Budget, Combinator, InterpretOptions, Node, ReductionStrategy, Span, TVariable, Term, TermNode, Variable
+    }, levels::{raw_load_level_config, Level, SectionName}, prelude::*, save_system::SaveProfile
 };
 
 enum DiscoveryMethod {
@@ -19,11 +23,23 @@ enum DiscoveryMethod {
 }
 
 struct ConstantNode {
-    term:   Box<Node>,
+    term:   Term,
+    /// Same as the term, as it was written. Getting the constant copies it, which is cheaper than
+    /// building it again from the term, and shows its own variable names, as terms keep the
+    /// names of the first equal term.
+    node:   Box<Node>,
     method: DiscoveryMethod,
 }
 
 impl ConstantNode {
+    fn new(node: Box<Node>, method: DiscoveryMethod) -> Self {
+        Self {
+            term: Term::from(node.as_ref()),
+            node,
+            method,
+        }
+    }
+
     fn can_be_used(&self, data: &CompletionData) -> bool {
         match data {
             CompletionData {
@@ -76,26 +92,26 @@ fn raw_load_constants() -> HashMap<String, ConstantNode> {
                         .map(|(name, term)| {
                             (
                                 name,
-                                ConstantNode {
-                                    term:   parse_constant(&term),
-                                    method: DiscoveryMethod::BeforeLevel {
+                                ConstantNode::new(
+                                    parse_constant(&term),
+                                    DiscoveryMethod::BeforeLevel {
                                         section: section_name,
                                         lvl_idx: i,
                                     },
-                                },
+                                ),
                             )
                         })
                         .collect();
                     if level.provides_constant {
                         v.push((
                             level.name.to_ascii_uppercase(),
-                            ConstantNode {
-                                term:   parse_constant(&level.solutions[0]),
-                                method: DiscoveryMethod::LevelCompleted {
+                            ConstantNode::new(
+                                parse_constant(&level.solutions[0]),
+                                DiscoveryMethod::LevelCompleted {
                                     name:    level.name,
                                     section: section_name,
                                 },
-                            },
+                            ),
                         ));
                     }
                     v
@@ -104,36 +120,238 @@ fn raw_load_constants() -> HashMap<String, ConstantNode> {
         .collect()
 }
 
+/// Most reductions used to find the normal form of a constant for the reverse index
+const MAX_NORMALIZE_REDUCTIONS: u32 = 1000;
+
+/// Terms that should be shown as the constant: its definition and, if it can be found quickly, its
+/// normal form
+fn index_terms(term: Term) -> Vec<Term> {
+    let options = InterpretOptions {
+        budget: Budget {
+            max_reductions: Some(MAX_NORMALIZE_REDUCTIONS),
+            time_limit:     None,
+        },
+        ..ReductionStrategy::Normal.into()
+    };
+    let mut terms = vec![];
+    if let Ok(i) = interpret(term.to_node(), options, ConstantProvider::all()) {
+        terms.push(Term::from(i.term.as_ref()));
+    }
+    if !terms.contains(&term) {
+        terms.push(term);
+    }
+    terms
+}
+
+/// The identity is left unnamed, as any name a level gives it (IF, for example) would be confusing
+/// everywhere else
+fn is_identity(term: &Term) -> bool {
+    matches!(term.node(), TermNode::Function(_, body) if *body.node() == TermNode::Bound(0))
+}
+
 lazy_static! {
     static ref ALL_CONSTANTS: HashMap<String, ConstantNode> = raw_load_constants();
+    /// Not in the reverse index, results are shown with the constants of the levels instead
+    static ref COMBINATORS: HashMap<&'static str, Box<Node>> = Combinator::ALL
+        .iter()
+        .map(|c| (c.name(), parse_constant(c.definition())))
+        .collect();
+    /// Terms of each constant for the reverse index, sorted by name so ties are always solved the
+    /// same way
+    static ref CONSTANT_TERMS: Vec<(&'static str, Vec<Term>)> = {
+        let mut v: Vec<_> = ALL_CONSTANTS
+            .iter()
+            .map(|(name, c)| {
+                let mut terms = index_terms(c.term.clone());
+                terms.retain(|t| !is_identity(t));
+                (name.as_str(), terms)
+            })
+            .collect();
+        v.sort_by_key(|(name, _)| *name);
+        v
+    };
+}
+
+/// Reverse index from terms to the names of the constants they are equal to, up to the names of
+/// variables. Used to show results with the constants instead of their expansions. Cloning it is
+/// cheap.
+#[derive(Debug, Clone, Default)]
+pub struct ConstantNames {
+    names: Arc<HashMap<Term, String>>,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Numerals {
+impl ConstantNames {
+    pub fn get(&self, term: &Term) -> Option<&str> { self.names.get(term).map(|s| s.as_str()) }
+
+    /// Returns the term of the node, and the node with the largest subterms that are constants
+    /// replaced by their names
+    fn fold_rec(&self, node: &Node, cur_depth: usize) -> (Term, Box<Node>) {
+        let (term, folded) = match node {
+            Node::Constant(..) | Node::Variable(..) =>
+                return (Term::from_node(node, cur_depth), Box::new(node.clone())),
+            Node::Function {
+                variable,
+                body,
+                span,
+            } => {
+                let (body_term, body) = self.fold_rec(body, cur_depth + 1);
+                (
+                    Term::new(TermNode::Function(variable.clone(), body_term)),
+                    Node::Function {
+                        variable: variable.clone(),
+                        body,
+                        span: *span,
+                    },
+                )
+            },
+            Node::Apply { left, right, span } => {
+                let (left_term, left) = self.fold_rec(left, cur_depth);
+                let (right_term, right) = self.fold_rec(right, cur_depth);
+                (
+                    Term::new(TermNode::Apply(left_term, right_term)),
+                    Node::Apply {
+                        left,
+                        right,
+                        span: *span,
+                    },
+                )
+            },
+        };
+        let folded = match self.get(&term) {
+            Some(name) => Node::Constant(name.to_string(), folded.span()),
+            None => folded,
+        };
+        (term, Box::new(folded))
+    }
+
+    /// Replaces the subterms equal to a known constant by its name
+    pub fn fold(&self, node: &Node) -> Box<Node> { self.fold_rec(node, 0).1 }
+
+    /// Same as fold, but doesn't replace the subterms on the path or the one at its end, so the
+    /// path still leads to the same subterm
+    pub fn fold_around(&self, node: &Node, path: &[PathStep]) -> Box<Node> {
+        Box::new(match (node, path.split_first()) {
+            (
+                Node::Function {
+                    variable,
+                    body,
+                    span,
+                },
+                step,
+            ) => Node::Function {
+                variable: variable.clone(),
+                body:     match step {
+                    Some((PathStep::Body, rest)) => self.fold_around(body, rest),
+                    _ => self.fold(body),
+                },
+                span:     *span,
+            },
+            (Node::Apply { left, right, span }, step) => {
+                let (left, right) = match step {
+                    Some((PathStep::Left, rest)) =>
+                        (self.fold_around(left, rest), self.fold(right)),
+                    Some((PathStep::Right, rest)) =>
+                        (self.fold(left), self.fold_around(right, rest)),
+                    _ => (self.fold(left), self.fold(right)),
+                };
+                Node::Apply {
+                    left,
+                    right,
+                    span: *span,
+                }
+            },
+            (node, _) => node.clone(),
+        })
+    }
+}
+
+/// How number literals are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Numerals {
+    /// Number literals can't be used
     None,
+    /// N = f:x: f^N x
     Church,
+    /// 0 = s:z: z, N+1 = s:z: s N
+    Scott,
+    /// List of bits, least significant first, ending with a 1. 0 is the empty list.
+    Binary,
+}
+
+// Numerals are built directly as nodes, they are too many to keep, and building them as terms
+// would go through the interner for every node.
+
+fn function(variable: &str, body: Box<Node>) -> Box<Node> {
+    Box::new(Node::Function {
+        variable: variable.into(),
+        body,
+        span: Span::default(),
+    })
+}
+
+fn apply(left: Box<Node>, right: Box<Node>) -> Box<Node> {
+    Box::new(Node::Apply {
+        left,
+        right,
+        span: Span::default(),
+    })
+}
+
+fn variable(original: &TVariable, depth: usize) -> Box<Node> {
+    Box::new(Node::Variable(
+        Variable {
+            depth,
+            original: original.clone(),
+        },
+        Span::default(),
+    ))
 }
 
 impl Numerals {
-    fn get_church_num(x: u16, str: &mut String) {
-        if x == 0 {
-            str.push('x');
-        } else {
-            str.push_str("f (");
-            Self::get_church_num(x - 1, str);
-            str.push(')');
+    /// f:x: f (f (... x))
+    fn get_church_num(n: u16) -> Box<Node> {
+        let (f, x): (TVariable, TVariable) = ("f".into(), "x".into());
+        let mut body = variable(&x, 0);
+        for _ in 0..n {
+            body = apply(variable(&f, 1), body);
+        }
+        function("f", function("x", body))
+    }
+
+    /// s:z: s (s:z: s (... (s:z: z)))
+    fn get_scott_num(x: u16) -> Box<Node> {
+        let (s, z): (TVariable, TVariable) = ("s".into(), "z".into());
+        let mut num = function("s", function("z", variable(&z, 0)));
+        for _ in 0..x {
+            num = function("s", function("z", apply(variable(&s, 1), num)));
+        }
+        num
+    }
+
+    /// PUSH bit0 (PUSH bit1 (... FALSE)), with the constants expanded
+    fn get_binary_num(mut x: u16) -> Box<Node> {
+        let (a, b, f): (TVariable, TVariable, TVariable) = ("a".into(), "b".into(), "f".into());
+        let boolean = |bit: bool| {
+            let chosen = if bit { &a } else { &b };
+            function("a", function("b", variable(chosen, bit as usize)))
+        };
+        let mut bits = vec![];
+        while x > 0 {
+            bits.push(x % 2 == 1);
+            x /= 2;
         }
+        bits.into_iter().rev().fold(boolean(false), |rest, bit| {
+            function("f", apply(apply(variable(&f, 0), boolean(bit)), rest))
+        })
     }
 
     fn get_num(self, x: u16) -> Option<Box<Node>> {
         match self {
             Numerals::None => None,
-            Numerals::Church => {
-                let mut term = String::with_capacity(x as usize * 4 + 7);
-                term.push_str("f:x: ");
-                Self::get_church_num(x, &mut term);
-                Some(parse_constant(&term))
-            },
+            Numerals::Church => Some(Self::get_church_num(x)),
+            Numerals::Scott => Some(Self::get_scott_num(x)),
+            Numerals::Binary => Some(Self::get_binary_num(x)),
         }
     }
 }
@@ -151,6 +369,8 @@ pub struct ConstantProvider {
     // None currently means use all constants
     completion_data: Option<CompletionData>,
     numerals:        Numerals,
+    /// Built the first time they are needed, and shared by the clones
+    names:           Arc<OnceLock<ConstantNames>>,
 }
 
 impl ConstantProvider {
@@ -159,16 +379,17 @@ impl ConstantProvider {
             numerals:        match &current_level {
                 Level::GameLevel(gl) =>
                     if gl.section >= SectionName::Numerals {
-                        Numerals::Church
+                        gl.base.numerals
                     } else {
                         Numerals::None
                     },
-                Level::UserCreatedLevel(..) => Numerals::Church,
+                Level::UserCreatedLevel(uc) => uc.base.numerals,
             },
             completion_data: Some(CompletionData {
                 level: current_level,
                 profile,
             }),
+            names:           Arc::default(),
         }
     }
 
@@ -177,9 +398,24 @@ impl ConstantProvider {
         Self {
             completion_data: None,
             numerals:        Numerals::Church,
+            names:           Arc::default(),
         }
     }
 
+    /// Same constants, but number literals use the given encoding
+    pub fn with_numerals(self, numerals: Numerals) -> Self { Self { numerals, ..self } }
+
+    pub fn numerals(&self) -> Numerals { self.numerals }
+
+    /// Combinators can only be used in the levels that don't allow functions, or with all constants
+    fn combinators_allowed(&self) -> bool {
+        self.completion_data
+            .as_ref()
+            .map_or(true, |d| !d.level.base().allow_functions)
+    }
+
+    /// Node of the constant, or of the number literal in the level's encoding. The interpreters
+    /// take ownership of the nodes they reduce, so it is a new copy every time.
     pub fn get(&self, name: &str) -> Option<Box<Node>> {
         if let Ok(x) = name.parse::<u16>() {
             self.numerals.get_num(x)
@@ -191,17 +427,62 @@ impl ConstantProvider {
                 }) => uc.extra_constants.get(name).cloned(),
                 _ => None,
             };
-            constant.or_else(|| {
-                ALL_CONSTANTS
-                    .get(name)
-                    .filter(|n| {
-                        self.completion_data
-                            .as_ref()
-                            .map(|l| n.can_be_used(l))
-                            .unwrap_or(true)
-                    })
-                    .map(|n| n.term.clone())
-            })
+            constant
+                .or_else(|| {
+                    ALL_CONSTANTS
+                        .get(name)
+                        .filter(|n| {
+                            self.completion_data
+                                .as_ref()
+                                .map(|l| n.can_be_used(l))
+                                .unwrap_or(true)
+                        })
+                        .map(|n| n.node.clone())
+                })
+                .or_else(|| {
+                    COMBINATORS
+                        .get(name)
+                        .filter(|_| self.combinators_allowed())
+                        .cloned()
+                })
+        }
+    }
+
+    /// Reverse index of the constants that can be used, plus the extra constants of user levels,
+    /// which take priority
+    pub fn names(&self) -> ConstantNames { self.names.get_or_init(|| self.build_names()).clone() }
+
+    fn build_names(&self) -> ConstantNames {
+        let mut names = HashMap::new();
+        if let Some(CompletionData {
+            level: Level::UserCreatedLevel(uc),
+            ..
+        }) = &self.completion_data
+        {
+            let mut extra: Vec<_> = uc.extra_constants.iter().collect();
+            extra.sort_by_key(|(name, _)| name.as_str());
+            for (name, node) in extra {
+                for term in index_terms(Term::from(node.as_ref())) {
+                    names.entry(term).or_insert_with(|| name.clone());
+                }
+            }
+        }
+        for (name, terms) in CONSTANT_TERMS.iter() {
+            if self
+                .completion_data
+                .as_ref()
+                .map(|l| ALL_CONSTANTS[*name].can_be_used(l))
+                .unwrap_or(true)
+            {
+                for term in terms {
+                    names
+                        .entry(term.clone())
+                        .or_insert_with(|| name.to_string());
+                }
+            }
+        }
+        ConstantNames {
+            names: Arc::new(names),
         }
     }
 
@@ -221,9 +502,12 @@ impl ConstantProvider {
                 }
             })
             .collect();
+        if self.combinators_allowed() {
+            ans.extend(Combinator::ALL.iter().map(|c| c.name()));
+        }
         match self.numerals {
             Numerals::None => {},
-            Numerals::Church => ans.append(&mut vec!["0", "1", "..."]),
+            _ => ans.append(&mut vec!["0", "1", "..."]),
         }
         ans
     }
@@ -249,7 +533,7 @@ mod test {
     use super::*;
     use crate::{
         interpreter::{
-            interpret, interpreter::test::{interpret_ok, interpret_ok_full}
+            interpret, interpreter::test::{interpret_ok, interpret_ok_full}, parser::test::parse_ok, ReductionStrategy
         }, levels::LEVELS
     };
     #[test]
@@ -281,14 +565,28 @@ mod test {
         assert!(p2.get("IF").is_none());
     }
 
+    #[test]
+    fn names_are_built_once() {
+        let provider = ConstantProvider::all();
+        let names = provider.names();
+        assert!(Arc::ptr_eq(&names.names, &provider.clone().names().names));
+        assert!(!Arc::ptr_eq(
+            &names.names,
+            &ConstantProvider::all().names().names
+        ));
+    }
+
     #[test]
     fn test_numbers_are_resolved() {
-        let interpret_clean =
-            |n: Box<Node>| interpret(n, false, ConstantProvider::all()).map(|i| i.term);
-        vec![0u16, 2, 20].into_iter().for_each(|n| {
-            let constant = Numerals::Church.get_num(n).unwrap();
-            assert_eq!(interpret_clean(constant.clone()), Ok(constant));
-        })
+        let interpret_clean = |n: Box<Node>| {
+            interpret(n, ReductionStrategy::CallByName, ConstantProvider::all()).map(|i| i.term)
+        };
+        for numerals in [Numerals::Church, Numerals::Scott, Numerals::Binary] {
+            vec![0u16, 2, 20].into_iter().for_each(|n| {
+                let constant = numerals.get_num(n).unwrap();
+                assert_eq!(interpret_clean(constant.clone()), Ok(constant));
+            })
+        }
     }
 
     #[test]
@@ -298,7 +596,22 @@ mod test {
         assert_eq!(Numerals::Church.get_num(0), Some(interpret_ok("f:x: x")));
         assert_eq!(
             Numerals::Church.get_num(2),
-            Some(interpret_ok_full("f:x: f (f x)", true))
+            Some(interpret_ok_full(
+                "f:x: f (f x)",
+                ReductionStrategy::Applicative
+            ))
+        );
+        assert_eq!(Numerals::Scott.get_num(0), Some(parse_ok("s:z: z")));
+        assert_eq!(
+            Numerals::Scott.get_num(2),
+            Some(parse_ok("s:z: s (s:z: s (s:z: z))"))
+        );
+        assert_eq!(Numerals::Binary.get_num(0), Some(parse_ok("a:b: b")));
+        assert_eq!(
+            Numerals::Binary.get_num(6),
+            Some(parse_ok(
+                "f: f (a:b: b) (f: f (a:b: a) (f: f (a:b: a) (a:b: b)))"
+            ))
         );
     }
 }
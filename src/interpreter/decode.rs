@@ -0,0 +1,325 @@
+use std::{fmt, time::Duration};
+
+use super::{
+    interpret, Budget, CancelToken, ConstantNames, ConstantProvider, InterpretOptions, Node, Numerals, ReductionStrategy, Term, TermNode
+};
+
+// Recognizes the encodings used by the levels, so results can be shown as values instead of
+// lambda terms:
+// - TRUE = a:b: a, FALSE = a:b: b
+// - N = f:x: f^N x, or the Scott or binary encodings if the level uses them
+// - PAIR A B = f: f A B, and lists are pairs ending in FALSE
+// - NODE L V R = f: f L V R, and empty trees are FALSE
+//
+// FALSE is also 0, the empty list and the empty tree, it is always shown as FALSE.
+
+/// Normalizing terms to decode them is only for display, so it can't take long
+const MAX_REDUCTIONS: u32 = 10000;
+const TIME_LIMIT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded {
+    Boolean(bool),
+    Numeral(u64),
+    Pair(Box<Decoded>, Box<Decoded>),
+    List(Vec<Decoded>),
+    /// Children are FALSE or other trees
+    Tree(Box<Decoded>, Box<Decoded>, Box<Decoded>),
+    /// Term that is not any of the encodings
+    Raw(Box<Node>),
+}
+
+/// Whether the variable bound `depth` functions above term is used in it
+fn uses_bound(term: &Term, depth: usize) -> bool {
+    match term.node() {
+        TermNode::Constant(_) | TermNode::Free(..) => false,
+        TermNode::Bound(d) => *d == depth,
+        TermNode::Function(_, body) => uses_bound(body, depth + 1),
+        TermNode::Apply(left, right) => uses_bound(left, depth) || uses_bound(right, depth),
+    }
+}
+
+/// Body of `n` nested functions, if it has them
+fn function_body(term: &Term, n: usize) -> Option<&Term> {
+    (0..n).try_fold(term, |term, _| match term.node() {
+        TermNode::Function(_, body) => Some(body),
+        _ => None,
+    })
+}
+
+/// Arguments of `f: f A B ...`, which must not use f
+fn selector_args(term: &Term) -> Option<Vec<Term>> {
+    let mut args = vec![];
+    let mut cur = function_body(term, 1)?;
+    while let TermNode::Apply(left, right) = cur.node() {
+        if uses_bound(right, 0) {
+            return None;
+        }
+        args.push(right.clone());
+        cur = left;
+    }
+    if *cur.node() != TermNode::Bound(0) {
+        return None;
+    }
+    args.reverse();
+    Some(args)
+}
+
+fn church_numeral(term: &Term) -> Option<u64> {
+    let mut cur = function_body(term, 2)?;
+    let mut n = 0;
+    while let TermNode::Apply(left, right) = cur.node() {
+        if *left.node() != TermNode::Bound(1) {
+            return None;
+        }
+        n += 1;
+        cur = right;
+    }
+    (*cur.node() == TermNode::Bound(0)).then_some(n)
+}
+
+fn scott_numeral(term: &Term) -> Option<u64> {
+    let mut cur = term;
+    let mut n = 0;
+    loop {
+        match function_body(cur, 2)?.node() {
+            TermNode::Bound(0) => return Some(n),
+            TermNode::Apply(left, pred)
+                if *left.node() == TermNode::Bound(1)
+                    && !uses_bound(pred, 0)
+                    && !uses_bound(pred, 1) =>
+            {
+                n += 1;
+                cur = pred;
+            },
+            _ => return None,
+        }
+    }
+}
+
+fn binary_numeral(term: &Term) -> Option<u64> {
+    let mut bits = vec![];
+    let mut cur = term.clone();
+    while boolean(&cur) != Some(false) {
+        match selector_args(&cur)?.as_slice() {
+            [bit, rest] if bits.len() < 64 => {
+                bits.push(boolean(bit)?);
+                cur = rest.clone();
+            },
+            _ => return None,
+        }
+    }
+    // Leading zeros would make the same number have many representations
+    if bits.last() == Some(&false) {
+        return None;
+    }
+    Some(bits.iter().rev().fold(0, |n, bit| 2 * n + *bit as u64))
+}
+
+fn numeral(term: &Term, numerals: Numerals) -> Option<u64> {
+    match numerals {
+        Numerals::None => None,
+        Numerals::Church => church_numeral(term),
+        Numerals::Scott => scott_numeral(term),
+        Numerals::Binary => binary_numeral(term),
+    }
+}
+
+fn boolean(term: &Term) -> Option<bool> {
+    match function_body(term, 2)?.node() {
+        TermNode::Bound(1) => Some(true),
+        TermNode::Bound(0) => Some(false),
+        _ => None,
+    }
+}
+
+fn is_empty(decoded: &Decoded) -> bool { *decoded == Decoded::Boolean(false) }
+
+fn decode_term(term: &Term, numerals: Numerals) -> Decoded {
+    if let Some(b) = boolean(term) {
+        return Decoded::Boolean(b);
+    }
+    if let Some(n) = numeral(term, numerals) {
+        return Decoded::Numeral(n);
+    }
+    match selector_args(term).as_deref() {
+        Some([first, second]) => {
+            let first = decode_term(first, numerals);
+            match decode_term(second, numerals) {
+                Decoded::List(mut rest) => {
+                    rest.insert(0, first);
+                    Decoded::List(rest)
+                },
+                second if is_empty(&second) => Decoded::List(vec![first]),
+                second => Decoded::Pair(Box::new(first), Box::new(second)),
+            }
+        },
+        Some([left, value, right]) => {
+            let is_tree = |d: &Decoded| is_empty(d) || matches!(d, Decoded::Tree(..));
+            let (left, right) = (decode_term(left, numerals), decode_term(right, numerals));
+            if is_tree(&left) && is_tree(&right) {
+                Decoded::Tree(
+                    Box::new(left),
+                    Box::new(decode_term(value, numerals)),
+                    Box::new(right),
+                )
+            } else {
+                Decoded::Raw(term.to_node())
+            }
+        },
+        _ => Decoded::Raw(term.to_node()),
+    }
+}
+
+/// Decodes the normal form of the term, or the term itself if the normal form can't be found
+/// quickly or the token is cancelled. Uses all constants, since they are expanded before decoding,
+/// and numbers are read with the given encoding.
+pub fn decode(node: &Node, numerals: Numerals, cancel: CancelToken) -> Decoded {
+    let options = InterpretOptions {
+        budget: Budget {
+            max_reductions: Some(MAX_REDUCTIONS),
+            time_limit:     Some(TIME_LIMIT),
+        },
+        cancel,
+        ..ReductionStrategy::Normal.into()
+    };
+    let provider = ConstantProvider::all().with_numerals(numerals);
+    let normal = interpret(Box::new(node.clone()), options, provider)
+        .map_or_else(|_| Box::new(node.clone()), |i| i.term);
+    match decode_term(&Term::from(normal.as_ref()), numerals) {
+        // Terms lose the variable names, so show the original
+        Decoded::Raw(_) => Decoded::Raw(normal),
+        decoded => decoded,
+    }
+}
+
+impl Decoded {
+    /// Same value, with the subterms of raw terms that are known constants replaced by their names
+    pub fn with_names(self, names: &ConstantNames) -> Decoded {
+        let boxed = |d: Box<Decoded>| Box::new(d.with_names(names));
+        match self {
+            Decoded::Boolean(_) | Decoded::Numeral(_) => self,
+            Decoded::Pair(first, second) => Decoded::Pair(boxed(first), boxed(second)),
+            Decoded::List(items) =>
+                Decoded::List(items.into_iter().map(|d| d.with_names(names)).collect()),
+            Decoded::Tree(left, value, right) =>
+                Decoded::Tree(boxed(left), boxed(value), boxed(right)),
+            Decoded::Raw(node) => Decoded::Raw(names.fold(&node)),
+        }
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoded::Boolean(true) => write!(f, "TRUE"),
+            Decoded::Boolean(false) => write!(f, "FALSE"),
+            Decoded::Numeral(n) => write!(f, "{}", n),
+            Decoded::Pair(first, second) => write!(f, "({}, {})", first, second),
+            Decoded::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            // Same notation as the trees levels, empty children are hidden
+            Decoded::Tree(left, value, right) => {
+                write!(f, "(")?;
+                if !is_empty(left) {
+                    write!(f, "{} ", left)?;
+                }
+                write!(f, "{}", value)?;
+                if !is_empty(right) {
+                    write!(f, " {}", right)?;
+                }
+                write!(f, ")")
+            },
+            Decoded::Raw(node) => write!(f, "{}", node),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::parser::test::parse_ok, *};
+
+    fn decoded(code: &str) -> String {
+        decode(&parse_ok(code), Numerals::Church, CancelToken::default()).to_string()
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(decoded("a:b: a"), "TRUE");
+        assert_eq!(decoded("x:y: y"), "FALSE");
+        assert_eq!(decoded("f:x: f (f (f x))"), "3");
+        assert_eq!(decoded("f:x: f x"), "1");
+        assert_eq!(decoded("5"), "5");
+        assert_eq!(decoded("(n: f:x: f (n f x)) 2"), "3");
+    }
+
+    #[test]
+    fn structures() {
+        assert_eq!(decoded("f: f A B"), "(A, B)");
+        assert_eq!(decoded("f: f A (g: g 1 (a:b: b))"), "[A, 1]");
+        assert_eq!(decoded("f: f (a:b: a) (a:b: b)"), "[TRUE]");
+        assert_eq!(
+            decoded("f: f (g: g (a:b: b) 1 (a:b: b)) 2 (a:b: b)"),
+            "((1) 2)"
+        );
+        assert_eq!(decoded("f: f A B C"), "f: f A B C");
+    }
+
+    #[test]
+    fn names() {
+        let names = ConstantProvider::all().names();
+        let named = |code: &str| {
+            decode(&parse_ok(code), Numerals::Church, CancelToken::default())
+                .with_names(&names)
+                .to_string()
+        };
+        assert_eq!(named("f: f (a:b: a) (a:b: b)"), "[TRUE]");
+        assert_eq!(named("p: p (a:b: a)"), "FST");
+        // Even though IF is defined as it
+        assert_eq!(named("x: x"), "x: x");
+    }
+
+    #[test]
+    fn encodings() {
+        let decoded = |code: &str, numerals| {
+            decode(&parse_ok(code), numerals, CancelToken::default()).to_string()
+        };
+        assert_eq!(decoded("s:z: s (s:z: s (s:z: z))", Numerals::Scott), "2");
+        assert_eq!(decoded("3", Numerals::Scott), "3");
+        assert_eq!(decoded("f:x: f (f x)", Numerals::Scott), "f: x: f (f x)");
+        assert_eq!(
+            decoded("s:z: s (a:b: a b)", Numerals::Scott),
+            "s: z: s (a: b: a b)"
+        );
+        assert_eq!(decoded("6", Numerals::Binary), "6");
+        assert_eq!(
+            decoded("PUSH TRUE (PUSH TRUE FALSE)", Numerals::Binary),
+            "3"
+        );
+        // Leading zeros are not numbers
+        assert_eq!(
+            decoded("PUSH TRUE (PUSH FALSE FALSE)", Numerals::Binary),
+            "[TRUE, FALSE]"
+        );
+        assert_eq!(decoded("PUSH A FALSE", Numerals::Binary), "[A]");
+        assert_eq!(decoded("PUSH 1 FALSE", Numerals::None), "[1]");
+    }
+
+    #[test]
+    fn not_values() {
+        assert_eq!(decoded("x: x"), "x: x");
+        assert_eq!(decoded("f: f f A"), "f: f f A");
+        assert_eq!(decoded("f:x: f (x f)"), "f: x: f (x f)");
+        assert_eq!(decoded("A"), "A");
+        // No normal form
+        assert_eq!(decoded("(x: x x) (x: x x)"), "(x: x x) (x: x x)");
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+use super::{traversers::PathStep, Node};
+use crate::prelude::*;
+
+/// Where two terms first differ, in the order they are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Path from the root of both terms to the subterms that differ
+    pub path:     Vec<PathStep>,
+    /// Text of the subterm of the first term at the path, as it is written in the whole term
+    pub expected: String,
+    /// Same for the second term
+    pub actual:   String,
+}
+
+fn first_difference_req(
+    expected: &Node,
+    actual: &Node,
+    cur_depth: usize,
+    path: &mut Vec<PathStep>,
+) -> bool {
+    let mut child = |step, e: &Node, a: &Node, depth| {
+        path.push(step);
+        if first_difference_req(e, a, depth, path) {
+            return true;
+        }
+        path.pop();
+        false
+    };
+    match (expected, actual) {
+        (Node::Constant(c1, _), Node::Constant(c2, _)) => c1 != c2,
+        // Same comparison as the equality of nodes
+        (Node::Variable(v1, _), Node::Variable(v2, _)) =>
+            !(v1.depth == v2.depth && (v1.depth < cur_depth || v1.original == v2.original)),
+        (Node::Function { body: b1, .. }, Node::Function { body: b2, .. }) =>
+            child(PathStep::Body, b1, b2, cur_depth + 1),
+        (
+            Node::Apply {
+                left: l1,
+                right: r1,
+                ..
+            },
+            Node::Apply {
+                left: l2,
+                right: r2,
+                ..
+            },
+        ) => child(PathStep::Left, l1, l2, cur_depth) || child(PathStep::Right, r1, r2, cur_depth),
+        _ => true,
+    }
+}
+
+/// Variables may be bound outside of the subterm, so it is written as part of the whole term
+fn subterm_text(root: &Node, path: &[PathStep]) -> String {
+    let (text, found) = root.to_string_marking(&[path]);
+    match &found[0] {
+        Some(range) => text
+            .chars()
+            .skip(range.start)
+            .take(range.end - range.start)
+            .collect(),
+        None => {
+            debug_unreachable!("The path was taken from the term");
+            text
+        },
+    }
+}
+
+/// First subterm where the terms are different, None if they are equal. Names of bound variables
+/// don't matter, same as when comparing them.
+pub fn first_difference(expected: &Node, actual: &Node) -> Option<Difference> {
+    let mut path = vec![];
+    if !first_difference_req(expected, actual, 0, &mut path) {
+        return None;
+    }
+    Some(Difference {
+        expected: subterm_text(expected, &path),
+        actual: subterm_text(actual, &path),
+        path,
+    })
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} but got {}", self.expected, self.actual)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        super::{parser::test::parse_ok, traversers::PathStep::*}, *
+    };
+
+    fn difference(expected: &str, actual: &str) -> Option<(Vec<PathStep>, String, String)> {
+        first_difference(&parse_ok(expected), &parse_ok(actual))
+            .map(|d| (d.path, d.expected, d.actual))
+    }
+
+    #[test]
+    fn equal() {
+        assert_eq!(difference("A (B C)", "A (B C)"), None);
+        assert_eq!(difference("x: y: x", "a: b: a"), None);
+        assert_eq!(difference("x", "x"), None);
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(
+            difference("A B", "A C"),
+            Some((vec![Right], "B".to_string(), "C".to_string()))
+        );
+        // The first one in the text is found
+        assert_eq!(
+            difference("A (B C) D", "A (B E) F"),
+            Some((vec![Left, Right, Right], "C".to_string(), "E".to_string()))
+        );
+        assert_eq!(
+            difference("A B", "x: x"),
+            Some((vec![], "A B".to_string(), "x: x".to_string()))
+        );
+        // Bound variables are compared by the function binding them
+        assert_eq!(
+            difference("x: y: x", "x: y: y"),
+            Some((vec![Body, Body], "x".to_string(), "y".to_string()))
+        );
+        // Unbound ones by name
+        assert_eq!(
+            difference("x: x a", "x: x b"),
+            Some((vec![Body, Right], "a".to_string(), "b".to_string()))
+        );
+    }
+}
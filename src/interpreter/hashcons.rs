@@ -0,0 +1,308 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap}, fmt, hash::{Hash, Hasher}, sync::Weak
+};
+
+use parking_lot::Mutex;
+
+use super::{
+    parser::{Node, Variable}, tokenizer::{Constant, TVariable}, traversers::{fold, Folded}, Span
+};
+use crate::prelude::*;
+
+/// Hash-consed term. Structurally equal terms are always the same allocation, so comparing and
+/// hashing terms is O(1), and equal subterms are shared. Variables use de Bruijn indices, so
+/// terms that only differ on the names of bound variables are equal, like in Node.
+///
+/// Only comparisons use terms: the results expected by test cases, the reverse index of the
+/// constants and decoding. Reduction still works on Node, so the interpreters copy the nodes they
+/// are given, including the constants they expand.
+#[derive(Clone)]
+pub struct Term(Arc<TermNode>);
+
+pub enum TermNode {
+    Constant(Constant),
+    /// Variable bound by a function in the term, with how many functions up is the one binding it
+    Bound(usize),
+    /// Variable unbound in the term, with how many functions above the root it is bound. Unbound
+    /// variables with different names are different.
+    Free(TVariable, usize),
+    /// The variable name is only used to convert back to Node, and is ignored when comparing
+    Function(TVariable, Term),
+    Apply(Term, Term),
+}
+
+impl PartialEq for Term {
+    fn eq(&self, other: &Self) -> bool { Arc::ptr_eq(&self.0, &other.0) }
+}
+
+impl Eq for Term {}
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) { Arc::as_ptr(&self.0).hash(state) }
+}
+
+impl PartialEq for TermNode {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TermNode::Constant(c1), TermNode::Constant(c2)) => c1 == c2,
+            (TermNode::Bound(i1), TermNode::Bound(i2)) => i1 == i2,
+            (TermNode::Free(v1, i1), TermNode::Free(v2, i2)) => v1 == v2 && i1 == i2,
+            (TermNode::Function(_, b1), TermNode::Function(_, b2)) => b1 == b2,
+            (TermNode::Apply(l1, r1), TermNode::Apply(l2, r2)) => l1 == l2 && r1 == r2,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for TermNode {}
+
+impl Hash for TermNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            TermNode::Constant(c) => c.hash(state),
+            TermNode::Bound(i) => i.hash(state),
+            TermNode::Free(v, i) => {
+                v.hash(state);
+                i.hash(state);
+            },
+            TermNode::Function(_, body) => body.hash(state),
+            TermNode::Apply(left, right) => {
+                left.hash(state);
+                right.hash(state);
+            },
+        }
+    }
+}
+
+/// All live terms, by the hash of their node. Only weak references are kept, so terms are freed
+/// once nobody uses them.
+#[derive(Default)]
+struct Interner {
+    terms:     HashMap<u64, Vec<Weak<TermNode>>>,
+    len:       usize,
+    /// Number of terms after the last time dead ones were removed
+    clean_len: usize,
+}
+
+impl Interner {
+    fn intern(&mut self, hash: u64, node: TermNode) -> Term {
+        let bucket = self.terms.entry(hash).or_default();
+        let mut found = None;
+        bucket.retain(|weak| match weak.upgrade() {
+            Some(term) => {
+                if found.is_none() && *term == node {
+                    found = Some(term);
+                }
+                true
+            },
+            None => false,
+        });
+        if let Some(term) = found {
+            return Term(term);
+        }
+        let term = Arc::new(node);
+        bucket.push(Arc::downgrade(&term));
+        self.len += 1;
+        if self.len > 2 * self.clean_len + 1024 {
+            self.terms.retain(|_, bucket| {
+                bucket.retain(|weak| weak.strong_count() > 0);
+                !bucket.is_empty()
+            });
+            self.len = self.terms.values().map(|b| b.len()).sum();
+            self.clean_len = self.len;
+        }
+        Term(term)
+    }
+}
+
+/// The interner is split by hash, so threads building terms at the same time rarely wait for each
+/// other. Equal terms have the same hash, so they are always in the same shard.
+const SHARDS: usize = 64;
+
+lazy_static! {
+    static ref INTERNER: Vec<Mutex<Interner>> = (0..SHARDS)
+        .map(|_| Mutex::new(Interner::default()))
+        .collect();
+}
+
+impl Term {
+    pub fn new(node: TermNode) -> Self {
+        let mut hasher = DefaultHasher::new();
+        node.hash(&mut hasher);
+        let hash = hasher.finish();
+        INTERNER[hash as usize % SHARDS].lock().intern(hash, node)
+    }
+
+    pub fn node(&self) -> &TermNode { &self.0 }
+
+    /// Term of a node that is inside `cur_depth` functions, variables bound by them are free
+    pub(super) fn from_node(node: &Node, cur_depth: usize) -> Self {
+        fold(node, cur_depth, |folded, depth| {
+            Self::new(match folded {
+                Folded::Constant(c, _) => TermNode::Constant(c.clone()),
+                Folded::Variable(v, _) =>
+                    if v.depth < depth {
+                        TermNode::Bound(v.depth)
+                    } else {
+                        TermNode::Free(v.original.clone(), v.depth - depth)
+                    },
+                Folded::Function(variable, body, _) => TermNode::Function(variable.clone(), body),
+                Folded::Apply(left, right, _) => TermNode::Apply(left, right),
+            })
+        })
+    }
+
+    /// Terms don't have spans, so all nodes have the default span
+    pub fn to_node(&self) -> Box<Node> {
+        enum Task<'a> {
+            Convert(&'a Term),
+            /// Wrap the last result in a function, whose variable is the innermost bound one
+            Function,
+            Apply,
+        }
+        let span = Span::default();
+        let mut bound: Vec<TVariable> = vec![];
+        let mut tasks = vec![Task::Convert(self)];
+        let mut done: Vec<Box<Node>> = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Convert(term) => match term.node() {
+                    TermNode::Constant(c) => done.push(Box::new(Node::Constant(c.clone(), span))),
+                    TermNode::Bound(depth) => done.push(Box::new(Node::Variable(
+                        Variable {
+                            depth:    *depth,
+                            original: bound[bound.len() - 1 - depth].clone(),
+                        },
+                        span,
+                    ))),
+                    TermNode::Free(original, depth) => done.push(Box::new(Node::Variable(
+                        Variable {
+                            depth:    bound.len() + depth,
+                            original: original.clone(),
+                        },
+                        span,
+                    ))),
+                    TermNode::Function(variable, body) => {
+                        bound.push(variable.clone());
+                        tasks.push(Task::Function);
+                        tasks.push(Task::Convert(body));
+                    },
+                    TermNode::Apply(left, right) => {
+                        tasks.push(Task::Apply);
+                        tasks.push(Task::Convert(right));
+                        tasks.push(Task::Convert(left));
+                    },
+                },
+                Task::Function => {
+                    let body = done.pop().unwrap();
+                    done.push(Box::new(Node::Function {
+                        variable: bound.pop().unwrap(),
+                        body,
+                        span,
+                    }));
+                },
+                Task::Apply => {
+                    let right = done.pop().unwrap();
+                    let left = done.pop().unwrap();
+                    done.push(Box::new(Node::Apply { left, right, span }));
+                },
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl Drop for Term {
+    /// Dropping the last reference to a deep term would recurse once per level, so the subterms
+    /// that are freed with it are dropped here one by one.
+    fn drop(&mut self) {
+        let mut freed = vec![];
+        take_subterms(&mut self.0, &mut freed);
+        while let Some(mut term) = freed.pop() {
+            take_subterms(&mut term.0, &mut freed);
+        }
+    }
+}
+
+lazy_static! {
+    /// Left in place of the subterms taken from terms that are being dropped
+    static ref PLACEHOLDER: Arc<TermNode> = Arc::new(TermNode::Bound(0));
+}
+
+/// If this is the last reference to the node, moves its subterms to `freed`
+fn take_subterms(node: &mut Arc<TermNode>, freed: &mut Vec<Term>) {
+    if Arc::strong_count(node) != 1 {
+        return;
+    }
+    match Arc::try_unwrap(std::mem::replace(node, PLACEHOLDER.clone())) {
+        Ok(TermNode::Function(_, body)) => freed.push(body),
+        Ok(TermNode::Apply(left, right)) => {
+            freed.push(left);
+            freed.push(right);
+        },
+        // Someone else got it from the interner in the meantime
+        _ => {},
+    }
+}
+
+impl From<&Node> for Term {
+    fn from(node: &Node) -> Self { Self::from_node(node, 0) }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.to_node().fmt(f) }
+}
+
+impl fmt::Debug for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { fmt::Debug::fmt(&self.to_node(), f) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::parser::test::parse_ok, *};
+
+    fn term(code: &str) -> Term { Term::from(parse_ok(code).as_ref()) }
+
+    #[test]
+    fn equality() {
+        assert_eq!(term("x: y: x"), term("a: b: a"));
+        assert_ne!(term("x: y: x"), term("x: y: y"));
+        assert_eq!(term("x: y: z"), term("a: b: z"));
+        assert_ne!(term("x: y: z"), term("x: y: w"));
+        assert_eq!(term("A (B C)"), term("(A (B C))"));
+        assert_ne!(term("A B C"), term("A (B C)"));
+        // Same as equality on nodes
+        for (a, b) in [
+            ("x: x y", "z: z y"),
+            ("(x: x) x", "(y: y) y"),
+            ("x: x", "x"),
+        ] {
+            assert_eq!(term(a) == term(b), parse_ok(a) == parse_ok(b));
+        }
+    }
+
+    #[test]
+    fn sharing() {
+        let t = term("(f: x: f (f x)) (f: x: f (f x))");
+        if let TermNode::Apply(left, right) = t.node() {
+            assert!(Arc::ptr_eq(&left.0, &right.0));
+        } else {
+            panic!("Expected application");
+        }
+    }
+
+    #[test]
+    fn back_to_node() {
+        for code in [
+            "A",
+            "x: y: x (y z)",
+            "a: (b: b a) (c: d: c)",
+            "x: x: x",
+            "w",
+        ] {
+            // Names may be different, if an equal term was created before with other names
+            assert_eq!(term(code).to_node(), parse_ok(code));
+        }
+    }
+}
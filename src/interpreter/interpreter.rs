@@ -1,10 +1,12 @@
 use std::{
-    ops::{Coroutine as Generator, CoroutineState as GeneratorState}, sync::atomic::{AtomicU32, Ordering}
+    collections::{HashMap, VecDeque}, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}
 };
 
 use thiserror::Error;
 
-use super::{parser::Node, ConstantProvider};
+use super::{
+    parser::Node, profile::Profiler, tokenizer::Constant, traversers::{drop_node, fold, for_each_variable, subterms, Folded, PathStep}, ConstantProvider, Profile, Span, TVariable, Term
+};
 use crate::prelude::*;
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
@@ -15,6 +17,19 @@ pub enum InterpretError {
     TooDeep,
     #[error("The interpreted term has become too large, expression probably has no reduction.")]
     TooLarge,
+    #[error("The interpretation was cancelled.")]
+    Cancelled,
+    #[error("The interpretation took too long, expression probably has no reduction.")]
+    Timeout,
+    #[error("The interpretation used too many reductions, expression probably has no reduction.")]
+    TooManyReductions,
+    #[error("The interpretation loops, {term} comes back every {period} reductions.")]
+    Loop {
+        /// Reductions until the term is the same again
+        period: u32,
+        /// Subterm that keeps coming back, the rest of the term stays the same
+        term:   Box<Node>,
+    },
 }
 
 trait AlgorithmAssert<T> {
@@ -34,85 +49,228 @@ impl<T> AlgorithmAssert<T> for Option<T> {
     }
 }
 
-const MAX_LEVEL: usize = 500;
-const MAX_SIZE: u32 = 10000;
-
-use std::pin::Pin;
+/// Default for InterpretOptions::max_depth
+pub const DEFAULT_MAX_DEPTH: usize = 10000;
+pub const MAX_SIZE: u32 = 10000;
 
-// Returns size of the expression
-fn for_each_unbound_req<F: Fn(&mut usize) -> () + Copy>(
-    root: &mut Node,
-    cur_depth: usize,
-    f: F,
-) -> u32 {
-    match root {
-        Node::Constant(_) => 1,
-        Node::Variable(v) =>
+/// Calls f with the depth of every variable unbound in the term
+fn for_each_unbound<F: FnMut(&mut usize)>(root: &mut Node, mut f: F) {
+    for_each_variable(root, 0, |v, cur_depth| {
         // unbound variables in the root expression, not necessarily in the whole expression
         // for example, on (x:y: x), x is considered unbound in the subterm (y: x).
-        {
-            if v.depth >= cur_depth {
-                f(&mut v.depth);
-            }
-            1
-        },
-        Node::Function { variable: _, body } => for_each_unbound_req(body, cur_depth + 1, f) + 1,
-        Node::Apply { left, right } =>
-            for_each_unbound_req(left, cur_depth, f) + for_each_unbound_req(right, cur_depth, f),
-    }
-}
-
-/// Returns size of expression and the expression.
-fn replace_req(root: Box<Node>, cur_depth: usize, value: &Node) -> (u32, Box<Node>) {
-    match *root {
-        Node::Variable(mut v) =>
-            if v.depth == cur_depth {
-                let mut value = Box::new(value.clone());
-                // We need to increase the depth for unbound vars so they keep being unbound
-                let size = for_each_unbound_req(value.as_mut(), 0, |depth| *depth += cur_depth);
-                (size, value)
-            } else {
-                if v.depth > cur_depth {
-                    // for variables that are "unbound" in the root note (may be bound before)
-                    // we need to decrease depth by one
-                    v.depth -= 1;
-                }
-                (1, Box::new(Node::Variable(v)))
+        if v.depth >= cur_depth {
+            f(&mut v.depth)
+        }
+    })
+}
+
+/// Replaces the variable of the function root was the body of by value. Returns the size of the
+/// result.
+fn replace(root: &mut Node, value: &Node) -> u32 {
+    let value_size = term_size(value);
+    let mut size = 0;
+    let mut stack = vec![(root, 0)];
+    while let Some((node, cur_depth)) = stack.pop() {
+        match node {
+            Node::Variable(v, _) =>
+                if v.depth == cur_depth {
+                    let mut value = value.clone();
+                    // We need to increase the depth for unbound vars so they keep being unbound
+                    if cur_depth > 0 {
+                        for_each_unbound(&mut value, |depth| *depth += cur_depth);
+                    }
+                    *node = value;
+                    size += value_size;
+                } else {
+                    if v.depth > cur_depth {
+                        // for variables that are "unbound" in the root note (may be bound before)
+                        // we need to decrease depth by one
+                        v.depth -= 1;
+                    }
+                    size += 1;
+                },
+            Node::Function { body, .. } => {
+                size += 1;
+                stack.push((body, cur_depth + 1));
             },
-        Node::Function { variable, body } => {
-            let (size, body) = replace_req(body, cur_depth + 1, value);
-            (size + 1, Box::new(Node::Function { variable, body }))
-        },
-        Node::Apply { left, right } => {
-            let (sizel, left) = replace_req(left, cur_depth, value);
-            let (sizer, right) = replace_req(right, cur_depth, value);
-            (sizel + sizer, Box::new(Node::Apply { left, right }))
-        },
-        node @ Node::Constant(_) => (1, Box::new(node)),
+            Node::Apply { left, right, .. } => {
+                stack.push((right, cur_depth));
+                stack.push((left, cur_depth));
+            },
+            Node::Constant(..) => size += 1,
+        }
     }
+    size
 }
 
-macro_rules! yield_from {
-    ($x: expr, $f: expr) => {{
-        let mut gen = $x;
-        loop {
-            match gen.as_mut().resume(()) {
-                GeneratorState::Yielded(y) => yield $f(y),
-                GeneratorState::Complete(r) => break r,
-            }
+/// Whether the variable that is `depth` functions above root is used in it
+fn uses_variable(root: &Node, depth: usize) -> bool {
+    subterms(root, depth)
+        .any(|(node, cur_depth)| matches!(node, Node::Variable(v, _) if v.depth == cur_depth))
+}
+
+/// Eta reduces everywhere in the term, that is, turns every (x: f x) where x is not used in f
+/// into f.
+pub fn eta_reduce(root: Box<Node>) -> Box<Node> {
+    let reduced = fold(&root, 0, |folded: Folded<Box<Node>>, _| match folded {
+        Folded::Function(variable, body, span) => match *body {
+            Node::Apply {
+                mut left, right, ..
+            } if matches!(*right, Node::Variable(ref v, _) if v.depth == 0)
+                && !uses_variable(&left, 0) =>
+            {
+                // left is now one function up
+                for_each_unbound(left.as_mut(), |depth| *depth -= 1);
+                left
+            },
+            body => Box::new(Node::Function {
+                variable: variable.clone(),
+                body: Box::new(body),
+                span,
+            }),
+        },
+        Folded::Apply(left, right, span) => Box::new(Node::Apply { left, right, span }),
+        Folded::Constant(c, span) => Box::new(Node::Constant(c.clone(), span)),
+        Folded::Variable(v, span) => Box::new(Node::Variable(v.clone(), span)),
+    });
+    drop_node(root);
+    reduced
+}
+
+/// Order in which redexes are picked, and how far reduction goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReductionStrategy {
+    /// Leftmost outermost redex first, until the full normal form. Arguments are substituted
+    /// unevaluated, so it finds the normal form whenever there is one.
+    Normal,
+    /// Arguments are reduced before being substituted (call-by-value), until the full normal
+    /// form. Loops on some terms that have a normal form, like (x: z) ((x: x x) (x: x x)).
+    Applicative,
+    /// Arguments are substituted unevaluated, and reduction stops at the weak head normal form:
+    /// a function, or something that is not a redex applied to unevaluated arguments.
+    CallByName,
+    /// Like call-by-name, but also reduces inside functions, stopping at the head normal form.
+    Head,
+}
+
+impl ReductionStrategy {
+    pub const ALL: [ReductionStrategy; 4] = [
+        ReductionStrategy::Normal,
+        ReductionStrategy::Applicative,
+        ReductionStrategy::CallByName,
+        ReductionStrategy::Head,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReductionStrategy::Normal => "Normal order",
+            ReductionStrategy::Applicative => "Applicative order",
+            ReductionStrategy::CallByName => "Call-by-name",
+            ReductionStrategy::Head => "Head reduction",
         }
-    }};
-    ($x: expr) => {
-        yield_from!($x, |x| x)
-    };
+    }
+
+    /// Whether the bodies of functions are reduced
+    fn reduces_functions(self) -> bool { self != ReductionStrategy::CallByName }
+}
+
+/// Stops interpretations running in other threads. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) { self.0.store(true, Ordering::Relaxed) }
+
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Relaxed) }
+}
+
+/// Extra limits for a single interpretation, on top of max_depth and MAX_SIZE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Budget {
+    /// Fails with TooManyReductions after this many reductions
+    pub max_reductions: Option<u32>,
+    /// Fails with Timeout if the interpretation takes longer than this
+    pub time_limit:     Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
-struct Interpreter {
-    fully_resolve:       bool,
-    yield_intermediates: bool,
-    provider:            ConstantProvider,
-    reductions:          Arc<AtomicU32>,
+pub struct InterpretOptions {
+    pub strategy:  ReductionStrategy,
+    /// How nested the interpretation can be before failing with TooDeep. Every reduction
+    /// nests the interpretation, so this also stops terms with no reduction. The interpreter
+    /// doesn't use the native stack, so this is only bounded by memory.
+    pub max_depth: usize,
+    pub budget:    Budget,
+    /// Makes the interpretation fail with Cancelled once cancelled
+    pub cancel:    CancelToken,
+}
+
+impl From<ReductionStrategy> for InterpretOptions {
+    fn from(strategy: ReductionStrategy) -> Self {
+        Self {
+            strategy,
+            max_depth: DEFAULT_MAX_DEPTH,
+            budget: Budget::default(),
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+/// Checks the budget and the cancel token while interpreting
+#[derive(Debug)]
+pub struct Limits {
+    budget: Budget,
+    cancel: CancelToken,
+    start:  Instant,
+    steps:  u32,
+}
+
+impl Limits {
+    pub fn new(options: &InterpretOptions) -> Self {
+        Self {
+            budget: options.budget,
+            cancel: options.cancel.clone(),
+            start:  Instant::now(),
+            steps:  0,
+        }
+    }
+
+    /// Must be called on every step of the interpretation, with how many reductions were done
+    pub fn check(&mut self, reductions: u32) -> Result<(), InterpretError> {
+        if self
+            .budget
+            .max_reductions
+            .map_or(false, |max| reductions > max)
+        {
+            return Err(InterpretError::TooManyReductions);
+        }
+        self.steps = self.steps.wrapping_add(1);
+        // Only look at the clock and the token once in a while, steps are very fast
+        if self.steps % 1024 == 0 {
+            if self.cancel.is_cancelled() {
+                return Err(InterpretError::Cancelled);
+            }
+            if self
+                .budget
+                .time_limit
+                .map_or(false, |limit| self.start.elapsed() > limit)
+            {
+                return Err(InterpretError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// What is left of the budget, for another interpretation that must share the same deadline
+    pub fn remaining(&self) -> Budget {
+        Budget {
+            time_limit: self
+                .budget
+                .time_limit
+                .map(|limit| limit.saturating_sub(self.start.elapsed())),
+            ..self.budget
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -121,6 +279,17 @@ pub struct Stats {
     pub reductions: u32,
 }
 
+/// How large and deep the interpretation got. Each interpreter builds different terms, so unlike
+/// the stats these are not the same between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    /// Size of the largest term built, the one compared to MAX_SIZE. Interpreters that share
+    /// arguments give the size the term would have had with them copied.
+    pub peak_size: u32,
+    /// Deepest level reached, the one compared to max_depth
+    pub max_depth: usize,
+}
+
 #[derive(Savefile, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AccStats {
     /// This is the average number of reductions multiplied by 100
@@ -159,134 +328,608 @@ pub fn accumulate_stats<V: IntoIterator<Item = Stats>>(v: V, functions: u16) ->
 pub struct Interpreted {
     pub term:  Box<Node>,
     pub stats: Stats,
+    pub usage: Usage,
+}
+
+/// One beta reduction, with where it happened in the whole term
+#[derive(Debug, Clone)]
+pub struct ReductionStep {
+    /// Whole term right before the reduction
+    pub before:   Box<Node>,
+    /// Path from the root of `before` to the redex. The function is on its left side and the
+    /// argument on its right side.
+    pub redex:    Vec<PathStep>,
+    /// Whole term after the reduction
+    pub after:    Box<Node>,
+    /// Constants expanded since the previous reduction, in order
+    pub expanded: Vec<Constant>,
+}
+
+/// Something the interpreter did, with the path from the root of the whole term to where it
+/// happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReductionEvent {
+    /// Reduced the redex at the path. The function is on its left side and the argument on its
+    /// right side.
+    BetaReduce { path: Vec<PathStep> },
+    /// Replaced the constant at the path by its definition
+    ExpandConstant { name: Constant, path: Vec<PathStep> },
+    /// Started interpreting the argument at the path, on applicative order or because the
+    /// function it is applied to is stuck
+    EnterArgument { path: Vec<PathStep> },
+    /// Started interpreting the body of a function, on the strategies that reduce inside them.
+    /// The path is to the body.
+    EnterBody { path: Vec<PathStep> },
+}
+
+/// What to do with the result of the subterm being interpreted. Also knows how to put
+/// intermediates of the subterm back in the whole term.
+#[derive(Debug)]
+enum Frame {
+    /// Interpreting the left side of an application, to know if it is a function
+    ApplyLeft {
+        right:    Box<Node>,
+        span:     Span,
+        strategy: ReductionStrategy,
+        level:    usize,
+    },
+    /// Interpreting the argument before substituting it, on applicative order
+    ApplyRight {
+        left:     Box<Node>,
+        span:     Span,
+        strategy: ReductionStrategy,
+        level:    usize,
+    },
+    /// Interpreting inside the left side of an application that is not a function, on normal
+    /// order
+    StuckLeft {
+        right:    Box<Node>,
+        span:     Span,
+        strategy: ReductionStrategy,
+        level:    usize,
+    },
+    /// Same as StuckLeft, for the right side
+    StuckRight {
+        left: Box<Node>,
+        span: Span,
+    },
+    FunctionBody {
+        variable: TVariable,
+        span:     Span,
+    },
+}
+
+impl Frame {
+    /// Where the subterm being interpreted is, from the term of the frame
+    fn path_step(&self) -> PathStep {
+        match self {
+            Frame::ApplyLeft { .. } | Frame::StuckLeft { .. } => PathStep::Left,
+            Frame::ApplyRight { .. } | Frame::StuckRight { .. } => PathStep::Right,
+            Frame::FunctionBody { .. } => PathStep::Body,
+        }
+    }
+
+    fn wrap(&self, node: Box<Node>) -> Box<Node> {
+        Box::new(match self {
+            Frame::ApplyLeft { right, span, .. } | Frame::StuckLeft { right, span, .. } =>
+                Node::Apply {
+                    left:  node,
+                    right: right.clone(),
+                    span:  *span,
+                },
+            Frame::ApplyRight { left, span, .. } | Frame::StuckRight { left, span } =>
+                Node::Apply {
+                    left:  left.clone(),
+                    right: node,
+                    span:  *span,
+                },
+            Frame::FunctionBody { variable, span } => Node::Function {
+                variable: variable.clone(),
+                body:     node,
+                span:     *span,
+            },
+        })
+    }
 }
 
-type InterpretResult =
-    Pin<Box<dyn Generator<Yield = Box<Node>, Return = Result<Box<Node>, InterpretError>>>>;
+impl Drop for Interpreter {
+    /// What is left when the interpretation fails may be as deep as it went
+    fn drop(&mut self) {
+        for frame in self.stack.drain(..) {
+            match frame {
+                Frame::ApplyLeft { right: node, .. }
+                | Frame::ApplyRight { left: node, .. }
+                | Frame::StuckLeft { right: node, .. }
+                | Frame::StuckRight { left: node, .. } => drop_node(node),
+                Frame::FunctionBody { .. } => {},
+            }
+        }
+        if let Some(Task::Interpret(node, ..) | Task::Return(node)) = self.task.take() {
+            drop_node(node);
+        }
+    }
+}
+
+/// Subterm right after a reduction, which later ones at the same stack length are compared to
+#[derive(Debug)]
+struct Checkpoint {
+    term:       Term,
+    size:       u32,
+    strategy:   ReductionStrategy,
+    /// Reductions done when it was taken
+    reductions: u32,
+    /// Comparisons until it moves to the current subterm
+    power:      u32,
+    /// Comparisons done since it was taken
+    steps:      u32,
+}
+
+#[derive(Debug)]
+enum Task {
+    /// Interpret the term, level is how nested the interpretation is
+    Interpret(Box<Node>, usize, ReductionStrategy),
+    /// Give the interpreted term to the frame on top of the stack
+    Return(Box<Node>),
+}
+
+enum Next {
+    Continue(Task),
+    /// Yield the reduction of the current subterm, from the redex to the result, then continue
+    Yield(Task, Box<Node>, Box<Node>),
+    Complete(Box<Node>),
+}
+
+enum Step {
+    Yielded(ReductionStep),
+    /// There are events waiting to be taken
+    Recorded,
+    Complete(Result<Box<Node>, InterpretError>),
+}
+
+/// What the interpreter gives back while running, besides the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trace {
+    Nothing,
+    /// Yields every reduction
+    Steps,
+    /// Stops whenever there are events
+    Events,
+}
+
+/// Iterative interpreter, which keeps the subterms it is waiting on in an explicit stack
+/// instead of recursing.
+#[derive(Debug)]
+struct Interpreter {
+    options:    InterpretOptions,
+    limits:     Limits,
+    trace:      Trace,
+    provider:   ConstantProvider,
+    reductions: u32,
+    usage:      Usage,
+    /// Events not taken yet, only kept when tracing
+    events:     VecDeque<ReductionEvent>,
+    /// Only when profiling
+    profiler:   Option<Profiler>,
+    /// Subterm each later one is compared to for finding loops, by the length of the stack. They
+    /// are dropped once the frames below them are popped.
+    seen:       Vec<Option<Checkpoint>>,
+    stack:      Vec<Frame>,
+    /// None when the interpretation is complete
+    task:       Option<Task>,
+}
 
 impl Interpreter {
-    fn interpret(self, level: usize, root: Box<Node>) -> InterpretResult {
-        let fully_resolve = self.fully_resolve;
-        let yield_intermediates = self.yield_intermediates;
-        Box::pin(move || {
-            if level > MAX_LEVEL {
-                return Err(InterpretError::TooDeep);
+    fn new(
+        root: Box<Node>,
+        options: InterpretOptions,
+        provider: ConstantProvider,
+        trace: Trace,
+    ) -> Self {
+        Self {
+            task: Some(Task::Interpret(root, 0, options.strategy)),
+            limits: Limits::new(&options),
+            options,
+            trace,
+            provider,
+            reductions: 0,
+            usage: Usage::default(),
+            events: VecDeque::new(),
+            profiler: None,
+            seen: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Runs until the interpretation is complete, when not tracing
+    fn run(&mut self) -> Result<Interpreted, InterpretError> {
+        loop {
+            match self.resume() {
+                Step::Yielded(_) | Step::Recorded => {
+                    debug_unreachable!("The interpreter is not tracing")
+                },
+                Step::Complete(ret) =>
+                    break ret.map(|term| Interpreted {
+                        term,
+                        stats: Stats {
+                            reductions: self.reductions,
+                        },
+                        usage: self.usage,
+                    }),
             }
-            Ok(match *root {
-                Node::Apply { left, right } => {
-                    let left = yield_from!(self.clone().interpret(level + 1, left), |left| {
-                        Box::new(Node::Apply {
-                            left,
-                            right: right.clone(),
+        }
+    }
+
+    /// Fails with Loop if, after an earlier reduction, the subterm being interpreted was the same
+    /// with the same frames around it. The interpreter would then do the same reductions forever.
+    /// Only the checkpoint is kept, which moves to the current subterm after twice as many
+    /// reductions each time, so loops of any period are found once it is inside them. `size` is
+    /// the size of term, subterms are only compared when their sizes are equal.
+    fn check_loop(
+        &mut self,
+        term: &Node,
+        size: u32,
+        strategy: ReductionStrategy,
+    ) -> Result<(), InterpretError> {
+        let len = self.stack.len();
+        if self.seen.len() <= len {
+            self.seen.resize_with(len + 1, || None);
+        }
+        let power = match &mut self.seen[len] {
+            Some(checkpoint) => {
+                checkpoint.steps += 1;
+                if checkpoint.size == size
+                    && checkpoint.strategy == strategy
+                    && checkpoint.term == Term::from(term)
+                {
+                    return Err(InterpretError::Loop {
+                        period: self.reductions - checkpoint.reductions,
+                        term:   Box::new(term.clone()),
+                    });
+                }
+                if checkpoint.steps < checkpoint.power {
+                    return Ok(());
+                }
+                checkpoint.power.saturating_mul(2)
+            },
+            None => 1,
+        };
+        self.seen[len] = Some(Checkpoint {
+            term: Term::from(term),
+            size,
+            strategy,
+            reductions: self.reductions,
+            power,
+            steps: 0,
+        });
+        Ok(())
+    }
+
+    /// Path from the root of the whole term to the subterm being interpreted
+    fn path(&self) -> Vec<PathStep> { self.stack.iter().map(Frame::path_step).collect() }
+
+    /// Records the event if tracing, the path is only computed then
+    fn record<F: FnOnce(Vec<PathStep>) -> ReductionEvent>(&mut self, event: F) {
+        if self.trace != Trace::Nothing {
+            let event = event(self.path());
+            self.events.push_back(event);
+        }
+    }
+
+    /// Run until the next intermediate, or until the interpretation is complete
+    fn resume(&mut self) -> Step {
+        loop {
+            let task = match self.task.take() {
+                Some(task) => task,
+                None => return Step::Complete(Err(InterpretError::AlgorithmError)),
+            };
+            match self.step(task) {
+                Ok(Next::Continue(task)) => {
+                    self.task = Some(task);
+                    if self.trace == Trace::Events && !self.events.is_empty() {
+                        return Step::Recorded;
+                    }
+                },
+                Ok(Next::Yield(task, redex, result)) => {
+                    self.task = Some(task);
+                    let expanded = self
+                        .events
+                        .drain(..)
+                        .filter_map(|event| match event {
+                            ReductionEvent::ExpandConstant { name, .. } => Some(name),
+                            _ => None,
                         })
-                    })?;
-                    let right = if fully_resolve {
-                        yield_from!(self.clone().interpret(level + 1, right), |right| {
-                            Box::new(Node::Apply {
-                                left: left.clone(),
-                                right,
-                            })
-                        })?
-                    } else {
-                        right
+                        .collect();
+                    let wrap = |node| {
+                        self.stack
+                            .iter()
+                            .rev()
+                            .fold(node, |node, frame| frame.wrap(node))
                     };
-                    match *left {
-                        Node::Function { variable: _, body } => {
-                            self.reductions.fetch_add(1, Ordering::Relaxed);
-                            let (size, body) = replace_req(body, 0, &right);
-                            if size > MAX_SIZE {
-                                return Err(InterpretError::TooLarge);
-                            }
-                            if yield_intermediates {
-                                yield body.clone();
-                            }
-                            yield_from!(self.interpret(level + 1, body))?
-                        },
-                        _ => Box::new(Node::Apply { left, right }),
-                    }
+                    return Step::Yielded(ReductionStep {
+                        before: wrap(redex),
+                        redex: self.path(),
+                        after: wrap(result),
+                        expanded,
+                    });
                 },
-                Node::Variable(v) => Box::new(Node::Variable(v)),
-                Node::Function { variable, body } => {
-                    let inner = if fully_resolve {
-                        yield_from!(self.interpret(level + 1, body), |inner| {
-                            Box::new(Node::Function {
+                Ok(Next::Complete(node)) => return Step::Complete(Ok(node)),
+                Err(err) => return Step::Complete(Err(err)),
+            }
+        }
+    }
+
+    fn step(&mut self, task: Task) -> Result<Next, InterpretError> {
+        Ok(Next::Continue(match task {
+            Task::Interpret(root, level, strategy) => {
+                let checked = if level > self.options.max_depth {
+                    Err(InterpretError::TooDeep)
+                } else {
+                    self.usage.max_depth = self.usage.max_depth.max(level);
+                    self.limits.check(self.reductions)
+                };
+                if let Err(err) = checked {
+                    drop_node(root);
+                    return Err(err);
+                }
+                match *root {
+                    Node::Apply { left, right, span } => {
+                        // Only the weak head is needed to know if left is a function
+                        let left_strategy = match strategy {
+                            ReductionStrategy::Applicative => ReductionStrategy::Applicative,
+                            _ => ReductionStrategy::CallByName,
+                        };
+                        self.stack.push(Frame::ApplyLeft {
+                            right,
+                            span,
+                            strategy,
+                            level,
+                        });
+                        Task::Interpret(left, level + 1, left_strategy)
+                    },
+                    node @ Node::Variable(..) => Task::Return(Box::new(node)),
+                    Node::Function {
+                        variable,
+                        body,
+                        span,
+                    } =>
+                        if strategy.reduces_functions() {
+                            self.stack.push(Frame::FunctionBody { variable, span });
+                            self.record(|path| ReductionEvent::EnterBody { path });
+                            Task::Interpret(body, level + 1, strategy)
+                        } else {
+                            Task::Return(Box::new(Node::Function {
                                 variable,
-                                body: inner,
-                            })
-                        })?
+                                body,
+                                span,
+                            }))
+                        },
+                    Node::Constant(c, span) =>
+                        if let Some(mut term) = self.provider.get(&c) {
+                            if let Some(profiler) = &mut self.profiler {
+                                profiler.expand(&c, &mut term);
+                            }
+                            self.record(|path| ReductionEvent::ExpandConstant { name: c, path });
+                            Task::Interpret(term, level + 1, strategy)
+                        } else {
+                            Task::Return(Box::new(Node::Constant(c, span)))
+                        },
+                }
+            },
+            Task::Return(node) => match self.pop() {
+                None => return Ok(Next::Complete(node)),
+                Some(Frame::ApplyLeft {
+                    right,
+                    span,
+                    strategy,
+                    level,
+                }) =>
+                    if strategy == ReductionStrategy::Applicative {
+                        self.stack.push(Frame::ApplyRight {
+                            left: node,
+                            span,
+                            strategy,
+                            level,
+                        });
+                        self.record(|path| ReductionEvent::EnterArgument { path });
+                        Task::Interpret(right, level + 1, strategy)
                     } else {
-                        body
-                    };
-                    Box::new(Node::Function {
+                        return self.apply(node, right, span, strategy, level);
+                    },
+                Some(Frame::ApplyRight {
+                    left,
+                    span,
+                    strategy,
+                    level,
+                }) => return self.apply(left, node, span, strategy, level),
+                Some(Frame::StuckLeft {
+                    right,
+                    span,
+                    strategy,
+                    level,
+                }) => {
+                    self.stack.push(Frame::StuckRight { left: node, span });
+                    self.record(|path| ReductionEvent::EnterArgument { path });
+                    Task::Interpret(right, level + 1, strategy)
+                },
+                Some(Frame::StuckRight { left, span }) => Task::Return(Box::new(Node::Apply {
+                    left,
+                    right: node,
+                    span,
+                })),
+                Some(Frame::FunctionBody { variable, span }) =>
+                    Task::Return(Box::new(Node::Function {
                         variable,
-                        body: inner,
+                        body: node,
+                        span,
+                    })),
+            },
+        }))
+    }
+
+    /// Subterms seen with the popped frame around them can't come back
+    fn pop(&mut self) -> Option<Frame> {
+        let frame = self.stack.pop();
+        self.seen.truncate(self.stack.len() + 1);
+        frame
+    }
+
+    /// Both sides of the application were interpreted as needed, reduce it if possible
+    fn apply(
+        &mut self,
+        left: Box<Node>,
+        right: Box<Node>,
+        span: Span,
+        strategy: ReductionStrategy,
+        level: usize,
+    ) -> Result<Next, InterpretError> {
+        Ok(match *left {
+            Node::Function {
+                variable,
+                body,
+                span: function_span,
+            } => {
+                self.reductions += 1;
+                self.record(|path| ReductionEvent::BetaReduce { path });
+                let redex = (self.trace == Trace::Steps).then(|| {
+                    Box::new(Node::Apply {
+                        left: Box::new(Node::Function {
+                            variable: variable.clone(),
+                            body:     body.clone(),
+                            span:     function_span,
+                        }),
+                        right: right.clone(),
+                        span,
                     })
-                },
-                Node::Constant(c) =>
-                    if let Some(term) = self.provider.get(&c) {
-                        yield_from!(self.interpret(level + 1, term))?
-                    } else {
-                        Box::new(Node::Constant(c))
-                    },
-            })
+                });
+                let mut body = body;
+                let size = replace(&mut body, &right);
+                drop_node(right);
+                self.usage.peak_size = self.usage.peak_size.max(size);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.reduce(&variable, size);
+                }
+                let checked = if size > MAX_SIZE {
+                    Err(InterpretError::TooLarge)
+                } else {
+                    self.check_loop(&body, size, strategy)
+                };
+                if let Err(err) = checked {
+                    drop_node(body);
+                    return Err(err);
+                }
+                if let Some(redex) = redex {
+                    Next::Yield(
+                        Task::Interpret(body.clone(), level + 1, strategy),
+                        redex,
+                        body,
+                    )
+                } else {
+                    Next::Continue(Task::Interpret(body, level + 1, strategy))
+                }
+            },
+            // Left is stuck, normal order goes on to reduce inside it
+            left if strategy == ReductionStrategy::Normal => {
+                self.stack.push(Frame::StuckLeft {
+                    right,
+                    span,
+                    strategy,
+                    level,
+                });
+                Next::Continue(Task::Interpret(Box::new(left), level + 1, strategy))
+            },
+            left => Next::Continue(Task::Return(Box::new(Node::Apply {
+                left: Box::new(left),
+                right,
+                span,
+            }))),
         })
     }
 }
 
+/// Number of constants, variables and functions in the term, same as the sizes compared to
+/// MAX_SIZE
+pub fn term_size(root: &Node) -> u32 {
+    subterms(root, 0)
+        .filter(|(node, _)| !matches!(node, Node::Apply { .. }))
+        .count() as u32
+}
+
 pub fn count_functions(root: &Node) -> u16 {
-    match root {
-        Node::Constant(_) => 0,
-        Node::Variable(_) => 0,
-        Node::Function { body, .. } => 1 + count_functions(body),
-        Node::Apply { left, right } => count_functions(left) + count_functions(right),
-    }
+    subterms(root, 0)
+        .filter(|(node, _)| matches!(node, Node::Function { .. }))
+        .count() as u16
 }
 
-pub fn interpret(
+pub fn interpret<O: Into<InterpretOptions>>(
     root: Box<Node>,
-    fully_resolve: bool,
+    options: O,
     provider: ConstantProvider,
 ) -> Result<Interpreted, InterpretError> {
-    let reductions = Arc::new(AtomicU32::new(0));
-    let mut gen = Interpreter {
-        fully_resolve,
-        yield_intermediates: false,
-        provider,
-        reductions: reductions.clone(),
-    }
-    .interpret(0, root);
-    loop {
-        match gen.as_mut().resume(()) {
-            GeneratorState::Yielded(_) => {
-                debug_unreachable!("yield_intermediates is set to false")
-            },
-            GeneratorState::Complete(ret) =>
-                break ret.map(|term| Interpreted {
-                    term,
-                    stats: Stats {
-                        reductions: reductions.load(Ordering::Relaxed),
-                    },
-                }),
-        }
-    }
+    Interpreter::new(root, options.into(), provider, Trace::Nothing).run()
 }
 
-struct InterpretIter {
-    gen:      InterpretResult,
-    finished: bool,
+/// For interpreters that can't see the intermediate terms. They call this when they fail with
+/// TooDeep, so the call-by-name interpreter reports the loop if it finds one. It runs with what is
+/// left of their limits, and its result is used whatever it is.
+pub(super) fn find_loop(
+    root: Box<Node>,
+    options: InterpretOptions,
+    provider: ConstantProvider,
+    limits: &Limits,
+) -> Result<Interpreted, InterpretError> {
+    let options = InterpretOptions {
+        budget: limits.remaining(),
+        ..options
+    };
+    interpret(root, options, provider)
+}
+
+/// Interprets, also counting which constant each reduction came from. The profile is of the
+/// reductions done before failing, if it fails.
+pub fn interpret_profiled<O: Into<InterpretOptions>>(
+    root: Box<Node>,
+    options: O,
+    provider: ConstantProvider,
+) -> (Result<Interpreted, InterpretError>, Profile) {
+    let mut interpreter = Interpreter::new(root, options.into(), provider, Trace::Nothing);
+    interpreter.profiler = Some(Profiler::default());
+    let result = interpreter.run();
+    let profile = interpreter
+        .profiler
+        .take()
+        .map(Profiler::finish)
+        .unwrap_or_default();
+    (result, profile)
+}
+
+/// Iterator of the reductions of an interpretation
+#[derive(Debug)]
+pub struct ReductionSteps {
+    interpreter: Interpreter,
+    /// Final result, once there are no more reductions
+    result:      Option<Result<Box<Node>, InterpretError>>,
+}
+
+impl ReductionSteps {
+    /// Result of the interpretation, None until all reductions were taken
+    pub fn result(&self) -> Option<&Result<Box<Node>, InterpretError>> { self.result.as_ref() }
 }
 
-impl Iterator for InterpretIter {
-    type Item = Box<Node>;
+impl Iterator for ReductionSteps {
+    type Item = ReductionStep;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
+        if self.result.is_some() {
             None
         } else {
-            match self.gen.as_mut().resume(()) {
-                GeneratorState::Yielded(y) => Some(y),
-                GeneratorState::Complete(_) => {
-                    self.finished = true;
+            match self.interpreter.resume() {
+                Step::Yielded(y) => Some(y),
+                Step::Recorded => {
+                    debug_unreachable!("Steps don't stop on events");
+                    None
+                },
+                Step::Complete(result) => {
+                    self.result = Some(result);
                     None
                 },
             }
@@ -294,27 +937,84 @@ impl Iterator for InterpretIter {
     }
 }
 
-pub fn interpret_itermediates(
+/// Iterator of the events of an interpretation
+#[derive(Debug)]
+pub struct ReductionEvents {
+    interpreter: Interpreter,
+    /// Final result, once there are no more events
+    result:      Option<Result<Box<Node>, InterpretError>>,
+}
+
+impl ReductionEvents {
+    /// Result of the interpretation, None until all events were taken
+    pub fn result(&self) -> Option<&Result<Box<Node>, InterpretError>> { self.result.as_ref() }
+
+    /// Reductions done so far
+    pub fn reductions(&self) -> u32 { self.interpreter.reductions }
+}
+
+impl Iterator for ReductionEvents {
+    type Item = ReductionEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.interpreter.events.pop_front() {
+                return Some(event);
+            }
+            if self.result.is_some() {
+                return None;
+            }
+            match self.interpreter.resume() {
+                Step::Recorded => {},
+                Step::Yielded(_) => debug_unreachable!("Events don't yield steps"),
+                Step::Complete(result) => self.result = Some(result),
+            }
+        }
+    }
+}
+
+pub fn interpret_itermediates<O: Into<InterpretOptions>>(
     root: Box<Node>,
-    fully_resolve: bool,
+    options: O,
     provider: ConstantProvider,
 ) -> impl Iterator<Item = Box<Node>> {
-    InterpretIter {
-        gen:      Interpreter {
-            fully_resolve,
-            yield_intermediates: true,
-            provider,
-            reductions: Arc::new(AtomicU32::new(0)),
-        }
-        .interpret(0, root),
-        finished: false,
+    interpret_steps(root, options, provider).map(|step| step.after)
+}
+
+/// Every reduction of the interpretation, lazily. Stops after the last one, or when the
+/// interpretation fails.
+pub fn interpret_steps<O: Into<InterpretOptions>>(
+    root: Box<Node>,
+    options: O,
+    provider: ConstantProvider,
+) -> ReductionSteps {
+    ReductionSteps {
+        interpreter: Interpreter::new(root, options.into(), provider, Trace::Steps),
+        result:      None,
+    }
+}
+
+/// Everything the interpreter does, lazily, without building the intermediate terms. Stops once
+/// the interpretation is complete or fails.
+pub fn interpret_events<O: Into<InterpretOptions>>(
+    root: Box<Node>,
+    options: O,
+    provider: ConstantProvider,
+) -> ReductionEvents {
+    ReductionEvents {
+        interpreter: Interpreter::new(root, options.into(), provider, Trace::Events),
+        result:      None,
     }
 }
 
 #[cfg(test)]
 pub mod test {
+    use std::assert_matches::assert_matches;
+
     use super::{
-        super::parser::test::{parse_ok, ConvertToNode}, *
+        super::{
+            interpret_krivine, interpret_shared, parser::test::{parse_ok, ConvertToNode}, Term
+        }, *
     };
 
     const Y_COMB: &str = "(f: (x: f (x x)) (x: f (x x)))";
@@ -322,23 +1022,21 @@ pub mod test {
     fn provider() -> ConstantProvider { ConstantProvider::all() }
 
     fn interpret_lazy(root: Box<Node>) -> Result<Box<Node>, InterpretError> {
-        interpret(root, false, provider()).map(|i| i.term)
+        interpret(root, ReductionStrategy::CallByName, provider()).map(|i| i.term)
     }
 
     pub fn interpret_ok(str: &str) -> Box<Node> { interpret_lazy(parse_ok(str)).unwrap() }
 
     fn interpret_err(str: &str) -> InterpretError { interpret_lazy(parse_ok(str)).unwrap_err() }
 
-    pub fn interpret_ok_full(str: &str, fully_resolve: bool) -> Box<Node> {
-        interpret(parse_ok(str), fully_resolve, provider())
-            .unwrap()
-            .term
+    pub fn interpret_ok_full(str: &str, strategy: ReductionStrategy) -> Box<Node> {
+        interpret(parse_ok(str), strategy, provider()).unwrap().term
     }
 
-    fn interpret_eq_full(src: &str, expected: &str, fully_resolve: bool) {
+    fn interpret_eq_full(src: &str, expected: &str, strategy: ReductionStrategy) {
         assert_eq!(
-            interpret_ok_full(src, fully_resolve),
-            interpret_ok_full(expected, fully_resolve)
+            interpret_ok_full(src, strategy),
+            interpret_ok_full(expected, strategy)
         );
     }
 
@@ -365,7 +1063,7 @@ pub mod test {
         interpret_eq("(x: x x) (y: y)", "(y: y)");
         interpret_eq("(x: x x) (y: z)", "z");
         interpret_eq("(x: x x x) (x: x)", "y: y");
-        interpret_eq_full("((x:x)(y:y))(z:z)", "x:x", true);
+        interpret_eq_full("((x:x)(y:y))(z:z)", "x:x", ReductionStrategy::Applicative);
     }
 
     #[test]
@@ -388,43 +1086,265 @@ pub mod test {
     fn tricky3() {
         // Issue is we'll copy the function twice, with same var names, and at the end we'll have
         // something like (y: y': y y'), which is very easy to interpret as (y: y: y y) which is wrong
-        interpret_eq_full("(f: f f) (x: y: x y)", "(x: y: x y)", true);
+        interpret_eq_full(
+            "(f: f f) (x: y: x y)",
+            "(x: y: x y)",
+            ReductionStrategy::Applicative,
+        );
         // (0: 1: 0 1) 1
         // Using pure expressions to create the conflict in var uids that might
         // come from e.g. concatenating terms
         let expr = (((), ((), (1.n(), 0.n()).n()).n()).n(), (0, 'z').n()).n();
         assert_eq!(
-            interpret(expr, false, provider()).unwrap().term,
+            interpret(expr, ReductionStrategy::CallByName, provider())
+                .unwrap()
+                .term,
             parse_ok("x: z x")
         );
         // No variable conflicts when replacing
         let ex = "y: (x: y: x y) y";
-        interpret_eq_full(ex, "y: z: y z", true);
-        interpret_eq_full(&format!("({}) A B", ex), "A B", true);
-        interpret_eq_full(&format!("({}) A B", ex), "A B", false);
+        interpret_eq_full(ex, "y: z: y z", ReductionStrategy::Applicative);
+        interpret_eq_full(
+            &format!("({}) A B", ex),
+            "A B",
+            ReductionStrategy::Applicative,
+        );
+        interpret_eq_full(
+            &format!("({}) A B", ex),
+            "A B",
+            ReductionStrategy::CallByName,
+        );
         // Display can't reuse variable names for different vars
-        assert_eq!(format!("{}", interpret_ok_full(ex, true)), "y: y': y y'");
+        assert_eq!(
+            format!("{}", interpret_ok_full(ex, ReductionStrategy::Applicative)),
+            "y: y': y y'"
+        );
     }
 
     #[test]
     fn tricky4() {
-        interpret_eq_full("(a: b: a) A B", "A", true);
-        interpret_eq_full("(x: y: (a: b: a) x)", "(x: y: b: x)", true);
-        interpret_eq_full("(b: x: y: b x y) (a: b: a) A B", "A", true);
+        interpret_eq_full("(a: b: a) A B", "A", ReductionStrategy::Applicative);
+        interpret_eq_full(
+            "(x: y: (a: b: a) x)",
+            "(x: y: b: x)",
+            ReductionStrategy::Applicative,
+        );
+        interpret_eq_full(
+            "(b: x: y: b x y) (a: b: a) A B",
+            "A",
+            ReductionStrategy::Applicative,
+        );
     }
 
     #[test]
     fn infinite() {
-        assert_eq!(interpret_err("(x: x x) (y: y y)"), InterpretError::TooDeep);
-        assert_eq!(interpret_err("(x: x x) (x: x x)"), InterpretError::TooDeep);
+        let omega = |period| InterpretError::Loop {
+            period,
+            term: parse_ok("(x: x x) (x: x x)"),
+        };
+        assert_eq!(interpret_err("(x: x x) (y: y y)"), omega(1));
+        assert_eq!(interpret_err("(x: x x) (x: x x)"), omega(1));
+        // The argument left outside stays the same
+        assert_eq!(interpret_err("(x: x x x) (y: y y)"), omega(1));
+        assert_eq!(
+            interpret(
+                parse_ok("(x: z) ((x: x x) (x: x x))"),
+                ReductionStrategy::Applicative,
+                provider()
+            )
+            .unwrap_err(),
+            omega(1)
+        );
+        // Grows without repeating
         assert_eq!(
-            interpret_err("(x: x x x) (y: y y)"),
+            interpret_err("(x: x x A) (x: x x A)"),
             InterpretError::TooDeep
         );
+    }
+
+    #[test]
+    fn loops() {
+        // The head repeats after four reductions
+        assert_matches!(
+            interpret_err(&format!("({} (f: x: y: f x y)) a b", Y_COMB)),
+            InterpretError::Loop { period: 4, .. }
+        );
+        assert_matches!(
+            interpret_err("Y (f: f) A"),
+            InterpretError::Loop { period: 2, .. }
+        );
+        // Loops inside functions are found on the strategies that go there
+        assert_matches!(
+            interpret(
+                parse_ok("x: (y: y y) (y: y y)"),
+                ReductionStrategy::Normal,
+                provider()
+            ),
+            Err(InterpretError::Loop { period: 1, .. })
+        );
+        // Found after the reductions that lead to the loop too
+        assert_matches!(
+            interpret_err(&format!("{} (f: n: n (f FALSE) (f TRUE)) TRUE", Y_COMB)),
+            InterpretError::Loop { .. }
+        );
+        // Recursion that ends is not a loop, even if the same subterms come back
+        interpret_eq("Y (f: n: n (f FALSE) A) TRUE", "A");
+        interpret_eq("(x: x x A) (x: y: y)", "A");
+    }
+
+    #[test]
+    fn deep_terms() {
+        let code = format!("{}A{}", "(x: x) (".repeat(1000), ")".repeat(1000));
+        assert_eq!(interpret_ok(&code), "A".n());
+        let options = InterpretOptions {
+            max_depth: 100,
+            ..ReductionStrategy::CallByName.into()
+        };
         assert_eq!(
-            interpret(parse_ok("(x: z) ((x: x x) (x: x x))"), true, provider()).unwrap_err(),
+            interpret(parse_ok(&code), options.clone(), provider()).unwrap_err(),
             InterpretError::TooDeep
         );
+        assert_eq!(
+            interpret_itermediates(parse_ok(&code), options, provider()).count(),
+            // Each reduction goes a level deeper
+            100
+        );
+    }
+
+    #[test]
+    fn deep_terms_small_stack() {
+        // Each reduction goes a level deeper, and nests as much as the size allows
+        let nests = MAX_SIZE as usize / 2;
+        let applications = format!("{}A{}", "(x: x) (".repeat(nests), ")".repeat(nests));
+        // Reducing inside the functions goes as deep as the default options allow
+        let functions = format!("{}A", "x: ".repeat(DEFAULT_MAX_DEPTH));
+        // On a stack much smaller than a main thread's
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let interpreters: [fn(_, ReductionStrategy, _) -> _; 3] =
+                    [interpret, interpret_krivine, interpret_shared];
+                for (code, strategy, expected) in [
+                    (&applications, ReductionStrategy::CallByName, "A".n()),
+                    (&functions, ReductionStrategy::Normal, parse_ok(&functions)),
+                ] {
+                    for interpreter in interpreters {
+                        let term = interpreter(parse_ok(code), strategy, provider())
+                            .unwrap()
+                            .term;
+                        assert!(term == expected);
+                        drop_node(term);
+                    }
+                    // Goes through the other traversals of the terms. Dropping a deep Box<Node>
+                    // recurses, so they are dropped with drop_node.
+                    let term = parse_ok(code);
+                    for other in [
+                        term.clone(),
+                        Term::from(term.as_ref()).to_node(),
+                        eta_reduce(term.clone()),
+                    ] {
+                        assert!(other == term);
+                        drop_node(other);
+                    }
+                    drop_node(term);
+                    drop_node(expected);
+                }
+                // What is left when failing is as deep too
+                let options = InterpretOptions {
+                    max_depth: DEFAULT_MAX_DEPTH / 2,
+                    ..ReductionStrategy::Normal.into()
+                };
+                assert_eq!(
+                    interpret(parse_ok(&functions), options, provider()).unwrap_err(),
+                    InterpretError::TooDeep
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn budgets() {
+        // Never ends, and the argument keeps growing so it is not a loop
+        let code = format!("{} (f: x: f (s x)) A", Y_COMB);
+        let with_budget = |budget| InterpretOptions {
+            budget,
+            ..ReductionStrategy::CallByName.into()
+        };
+        assert_eq!(
+            interpret(
+                parse_ok(&code),
+                with_budget(Budget {
+                    max_reductions: Some(10),
+                    time_limit:     None,
+                }),
+                provider()
+            )
+            .unwrap_err(),
+            InterpretError::TooManyReductions
+        );
+        assert_eq!(
+            interpret(
+                parse_ok(&code),
+                with_budget(Budget {
+                    max_reductions: None,
+                    time_limit:     Some(Duration::ZERO),
+                }),
+                provider()
+            )
+            .unwrap_err(),
+            InterpretError::Timeout
+        );
+        // Enough budget
+        assert_eq!(
+            interpret(
+                parse_ok("(x: x) A"),
+                with_budget(Budget {
+                    max_reductions: Some(1),
+                    time_limit:     Some(Duration::ZERO),
+                }),
+                provider()
+            )
+            .unwrap()
+            .term,
+            "A".n()
+        );
+    }
+
+    #[test]
+    fn find_loop_shares_limits() {
+        let options = InterpretOptions {
+            budget: Budget {
+                max_reductions: None,
+                time_limit:     Some(Duration::from_secs(10)),
+            },
+            ..ReductionStrategy::CallByName.into()
+        };
+        let limits = Limits::new(&options);
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(limits.remaining().time_limit < options.budget.time_limit);
+        // Terms that don't loop keep the result
+        assert_eq!(
+            find_loop(parse_ok("(x: x) A"), options.clone(), provider(), &limits)
+                .unwrap()
+                .term,
+            "A".n()
+        );
+        assert_matches!(
+            find_loop(parse_ok("(x: x x) (x: x x)"), options, provider(), &limits),
+            Err(InterpretError::Loop { period: 1, .. })
+        );
+    }
+
+    #[test]
+    fn cancel() {
+        let options = InterpretOptions::from(ReductionStrategy::CallByName);
+        options.cancel.cancel();
+        assert_eq!(
+            interpret(parse_ok("(x: x x A) (x: x x A)"), options, provider()).unwrap_err(),
+            InterpretError::Cancelled
+        );
     }
 
     #[test]
@@ -445,33 +1365,115 @@ pub mod test {
     #[test]
     fn stats() {
         assert_eq!(
-            interpret(parse_ok("(x: x x) y z"), false, provider())
-                .unwrap()
-                .stats,
+            interpret(
+                parse_ok("(x: x x) y z"),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap()
+            .stats,
             Stats { reductions: 1 }
         );
         assert_eq!(
-            interpret(parse_ok("(x: z: x x x z) (y: y) A"), false, provider())
-                .unwrap()
-                .stats,
+            interpret(
+                parse_ok("(x: z: x x x z) (y: y) A"),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap()
+            .stats,
             Stats { reductions: 5 }
         );
+        // The body becomes y y, at level 2 after the reduction, and its head y is one deeper
+        assert_eq!(
+            interpret(
+                parse_ok("(x: x x) y z"),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap()
+            .usage,
+            Usage {
+                peak_size: 2,
+                max_depth: 3,
+            }
+        );
     }
 
     #[test]
-    fn recursive() {
+    fn comments_are_ignored() {
+        let commented = "# K combinator\n(x: /* y: is ignored */ y: x) /* a */ A B # (z: z)";
+        let plain = "(x: y: x) A B";
+        assert_eq!(
+            count_functions(&parse_ok(commented)),
+            count_functions(&parse_ok(plain))
+        );
+        assert_eq!(
+            interpret(
+                parse_ok(commented),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap()
+            .stats,
+            interpret(parse_ok(plain), ReductionStrategy::CallByName, provider())
+                .unwrap()
+                .stats
+        );
+    }
+
+    #[test]
+    fn eta() {
+        let eta = |code| eta_reduce(parse_ok(code));
+        assert_eq!(eta("x: f x"), parse_ok("f"));
+        assert_eq!(eta("x: y: f x y"), parse_ok("f"));
+        assert_eq!(eta("x: x x"), parse_ok("x: x x"));
+        assert_eq!(eta("x: (y: y) x"), parse_ok("y: y"));
+        assert_eq!(eta("f: x: g f x"), parse_ok("g"));
+        assert_eq!(eta("f: x: f f x"), parse_ok("f: f f"));
+        assert_eq!(eta("a: (x: a x) (y: B y)"), parse_ok("a: a B"));
+    }
+
+    #[test]
+    fn lets() {
+        let code = "let ID = x: x in ID ID A";
+        // Same as (id: id id A) (x: x), one function and one reduction for the let
+        assert_eq!(count_functions(&parse_ok(code)), 2);
+        let interpreted =
+            interpret(parse_ok(code), ReductionStrategy::CallByName, provider()).unwrap();
+        assert_eq!(interpreted.term, "A".n());
         assert_eq!(
+            interpreted.stats,
+            interpret(
+                parse_ok("(id: id id A) (x: x)"),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap()
+            .stats
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        assert_matches!(
             interpret_err(&format!("({} (f: x:y: f x y)) a b", Y_COMB)),
-            InterpretError::TooDeep
+            InterpretError::Loop { .. }
         );
     }
 
     #[test]
-    fn some_levels() { interpret_eq_full("(f: x: f (f x)) (x: x x) A", "(A A) (A A)", true); }
+    fn some_levels() {
+        interpret_eq_full(
+            "(f: x: f (f x)) (x: x x) A",
+            "(A A) (A A)",
+            ReductionStrategy::Applicative,
+        );
+    }
 
-    fn assert_partial_impl(code: &str, intermediates: Vec<&str>, fully_resolve: bool) {
+    fn assert_partial_impl(code: &str, intermediates: Vec<&str>, strategy: ReductionStrategy) {
         assert_eq!(
-            interpret_itermediates(parse_ok(code), fully_resolve, provider()).collect::<Vec<_>>(),
+            interpret_itermediates(parse_ok(code), strategy, provider()).collect::<Vec<_>>(),
             intermediates
                 .into_iter()
                 .map(|e| parse_ok(e))
@@ -480,18 +1482,183 @@ pub mod test {
     }
 
     fn assert_partial(code: &str, intermediates: Vec<&str>) {
-        assert_partial_impl(code, intermediates, false);
+        assert_partial_impl(code, intermediates, ReductionStrategy::CallByName);
     }
 
     #[test]
     fn partial() { assert_partial("(x: x x) (y: z)", vec!["(y: z) (y:z)", "z"]); }
 
+    #[test]
+    fn partial_normal_order() {
+        assert_partial_impl(
+            "(x: x) (y: (z: z) y)",
+            vec!["y: (z: z) y", "y: y"],
+            ReductionStrategy::Normal,
+        );
+    }
+
+    #[test]
+    fn steps() {
+        use PathStep::*;
+        let mut steps = interpret_steps(
+            parse_ok("TRUE A B"),
+            ReductionStrategy::CallByName,
+            provider(),
+        );
+        let first = steps.next().unwrap();
+        assert_eq!(first.before, parse_ok("(a: b: a) A B"));
+        assert_eq!(first.redex, vec![Left]);
+        assert_eq!(first.after, parse_ok("(b: A) B"));
+        assert_eq!(first.expanded, vec!["TRUE".to_string()]);
+        let second = steps.next().unwrap();
+        assert_eq!(second.redex, vec![]);
+        assert!(second.expanded.is_empty());
+        assert!(steps.result().is_none());
+        assert!(steps.next().is_none());
+        assert_eq!(steps.result(), Some(&Ok(parse_ok("A"))));
+    }
+
+    #[test]
+    fn events() {
+        use PathStep::*;
+        use ReductionEvent::*;
+        let events = |code: &str, strategy| {
+            let mut events = interpret_events(parse_ok(code), strategy, provider());
+            let v: Vec<_> = events.by_ref().collect();
+            assert!(events.result().unwrap().is_ok());
+            v
+        };
+        assert_eq!(
+            events("TRUE A B", ReductionStrategy::CallByName),
+            vec![
+                ExpandConstant {
+                    name: "TRUE".to_string(),
+                    path: vec![Left, Left],
+                },
+                BetaReduce { path: vec![Left] },
+                BetaReduce { path: vec![] },
+            ]
+        );
+        assert_eq!(
+            events("x: (y: y) x", ReductionStrategy::Normal),
+            vec![
+                EnterBody { path: vec![Body] },
+                BetaReduce { path: vec![Body] },
+            ]
+        );
+        assert_eq!(
+            events("A ((x: x) B)", ReductionStrategy::Normal),
+            vec![
+                EnterArgument { path: vec![Right] },
+                BetaReduce { path: vec![Right] },
+            ]
+        );
+        // Same reductions as the steps
+        let code = "(x: x x) (y: (z: z) y)";
+        let betas: Vec<_> = events(code, ReductionStrategy::Normal)
+            .into_iter()
+            .filter_map(|e| match e {
+                BetaReduce { path } => Some(path),
+                _ => None,
+            })
+            .collect();
+        let steps: Vec<_> = interpret_steps(parse_ok(code), ReductionStrategy::Normal, provider())
+            .map(|s| s.redex)
+            .collect();
+        assert_eq!(betas, steps);
+    }
+
+    #[test]
+    fn profiled() {
+        use crate::interpreter::{Origin, ProfileEntry};
+        let profile = |code: &str| {
+            let (result, profile) =
+                interpret_profiled(parse_ok(code), ReductionStrategy::CallByName, provider());
+            let total: u32 = profile.entries.values().map(|e| e.reductions).sum();
+            assert_eq!(total, result.unwrap().stats.reductions);
+            profile
+        };
+        let entry = |reductions, peak_size| ProfileEntry {
+            reductions,
+            peak_size,
+        };
+        let p = profile("(x: x TRUE) (y: y) A B");
+        assert_eq!(p.entries[&Origin::Code], entry(2, 3));
+        assert_eq!(p.entries[&Origin::Constant("TRUE".into())], entry(2, 2));
+        // Every expansion counts for the same constant
+        let p = profile("TRUE (TRUE A B) C");
+        assert_eq!(p.entries.len(), 1);
+        assert_eq!(p.entries[&Origin::Constant("TRUE".into())], entry(4, 4));
+        let mut merged = profile("TRUE A B");
+        merged.merge(&profile("(x: x) A"));
+        assert_eq!(merged.sorted()[0].0, &Origin::Constant("TRUE".into()));
+        assert_eq!(merged.sorted()[1].1, &entry(1, 1));
+    }
+
+    #[test]
+    fn strategies() {
+        use ReductionStrategy::*;
+        let assert_result = |code: &str, expected: &str, strategies: &[ReductionStrategy]| {
+            for &strategy in strategies {
+                assert_eq!(
+                    interpret_ok_full(code, strategy),
+                    parse_ok(expected),
+                    "{:?}",
+                    strategy
+                );
+            }
+        };
+        // Only applicative order reduces the unused argument
+        assert_result(
+            "(x: z) ((x: x x) (x: x x))",
+            "z",
+            &[Normal, CallByName, Head],
+        );
+        assert_eq!(
+            interpret(
+                parse_ok("(x: z) ((x: x x) (x: x x))"),
+                Applicative,
+                provider()
+            )
+            .unwrap_err(),
+            InterpretError::Loop {
+                period: 1,
+                term:   parse_ok("(x: x x) (x: x x)"),
+            }
+        );
+        // Reducing inside functions
+        assert_result("x: (y: y) x", "x: x", &[Normal, Applicative, Head]);
+        assert_result("x: (y: y) x", "x: (y: y) x", &[CallByName]);
+        // Reducing arguments that are never substituted
+        assert_result("x: x ((y: y) A)", "x: x A", &[Normal, Applicative]);
+        assert_result("x: x ((y: y) A)", "x: x ((y: y) A)", &[CallByName, Head]);
+        assert_result("A ((y: y) B)", "A B", &[Normal, Applicative]);
+        assert_result("A ((y: y) B)", "A ((y: y) B)", &[CallByName, Head]);
+        // Normal order substitutes unevaluated arguments, so they may be reduced many times
+        let reductions = |strategy| {
+            interpret(parse_ok("(x: x x) ((a: a) A)"), strategy, provider())
+                .unwrap()
+                .stats
+                .reductions
+        };
+        assert_eq!(reductions(Normal), 3);
+        assert_eq!(reductions(Applicative), 2);
+    }
+
     #[test]
     fn test_with_constants() {
         interpret_eq("TRUE A B", "A");
         interpret_eq("FALSE A B", "B");
-        interpret_eq_full("(f:a:b: f b a) FALSE", "TRUE", true);
-        interpret_eq_full("(a:b: NOT (AND (NOT a) (NOT b))) TRUE TRUE", "TRUE", true);
+        interpret_eq_full(
+            "(f:a:b: f b a) FALSE",
+            "TRUE",
+            ReductionStrategy::Applicative,
+        );
+        interpret_eq_full(
+            "(a:b: NOT (AND (NOT a) (NOT b))) TRUE TRUE",
+            "TRUE",
+            ReductionStrategy::Applicative,
+        );
         interpret_eq("POP (PUSH A FALSE)", "a:b: b");
     }
 
@@ -499,7 +1666,12 @@ pub mod test {
     fn exponentially_large() {
         // This grows exponentially large in a linear number of steps
         assert_eq!(
-            interpret(parse_ok("Y (f: x: f (x x)) A"), false, provider()).unwrap_err(),
+            interpret(
+                parse_ok("Y (f: x: f (x x)) A"),
+                ReductionStrategy::CallByName,
+                provider()
+            )
+            .unwrap_err(),
             InterpretError::TooLarge
         );
     }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use super::{
+    closures::*, interpreter::{
+        find_loop, interpret, InterpretError, InterpretOptions, Interpreted, Limits, ReductionStrategy, Stats, Usage
+    }, parser::Node, tokenizer::Constant, traversers::drop_node, ConstantProvider, Span
+};
+
+// Krivine machine. Call-by-name like the reference interpreter, but arguments are closures that
+// keep the term unevaluated together with its environment, instead of being substituted into the
+// body. Nothing is copied until the result is read back, and since the machine keeps its own stack
+// of pending arguments it can go as deep as the reference interpreter.
+
+/// Argument waiting for the function on the left to be evaluated
+struct Pending<'a> {
+    thunk: Thunk<'a>,
+    span:  Span,
+    /// Level of the application, the body of the function continues one level deeper
+    level: usize,
+}
+
+fn run<'a>(
+    root: &'a Node,
+    options: &InterpretOptions,
+    constants: &'a HashMap<Constant, Box<Node>>,
+    limits: &mut Limits,
+) -> Result<(Value<'a>, u32, Usage), InterpretError> {
+    let mut term = root;
+    let mut env: Env<'a> = None;
+    let mut level = 0;
+    let mut reductions = 0u32;
+    let mut usage = Usage::default();
+    let mut stack: Vec<Pending<'a>> = vec![];
+    let head = loop {
+        // Levels are the same as the depth in the reference interpreter, so both fail on the same
+        // terms.
+        if level > options.max_depth {
+            return Err(InterpretError::TooDeep);
+        }
+        usage.max_depth = usage.max_depth.max(level);
+        limits.check(reductions)?;
+        match term {
+            Node::Apply { left, right, span } => {
+                stack.push(Pending {
+                    thunk: argument(right, &env),
+                    span: *span,
+                    level,
+                });
+                term = left;
+                level += 1;
+            },
+            Node::Function {
+                variable,
+                body,
+                span,
+            } => match stack.pop() {
+                Some(arg) => {
+                    reductions = reductions.saturating_add(1);
+                    env = env_push(env, arg.thunk);
+                    usage.peak_size = usage.peak_size.max(expanded_size(body, &env));
+                    term = body;
+                    level = arg.level + 1;
+                },
+                None =>
+                    return Ok((
+                        Value::Function {
+                            variable,
+                            body,
+                            span: *span,
+                            env,
+                        },
+                        reductions,
+                        usage,
+                    )),
+            },
+            Node::Variable(v, span) =>
+                if let Some(thunk) = env_get(&env, v.depth) {
+                    term = thunk.term;
+                    env = thunk.env.clone();
+                } else {
+                    break Head::Free(&v.original, v.depth - env_len(&env), *span);
+                },
+            Node::Constant(c, span) =>
+                if let Some(def) = constants.get(c) {
+                    term = def;
+                    env = None;
+                    level += 1;
+                } else {
+                    break Head::Constant(c, *span);
+                },
+        }
+    };
+    // The innermost application is at the top of the stack
+    let args = stack.into_iter().rev().map(|p| (p.thunk, p.span)).collect();
+    Ok((Value::Stuck { head, args }, reductions, usage))
+}
+
+/// Gives the same result as interpreting with call-by-name, but without copying arguments, so it
+/// is faster for running many terms. It only fails with TooLarge if the result itself is too
+/// large, but the peak size is of the terms call-by-name would have built. Like the call-by-need interpreter, it leaves
+/// finding loops to the reference interpreter. Only call-by-name is supported, other strategies
+/// use the reference interpreter.
+pub fn interpret_krivine<O: Into<InterpretOptions>>(
+    root: Box<Node>,
+    options: O,
+    provider: ConstantProvider,
+) -> Result<Interpreted, InterpretError> {
+    let options = options.into();
+    if options.strategy != ReductionStrategy::CallByName {
+        return interpret(root, options, provider);
+    }
+    let constants = used_constants(&root, &provider);
+    let mut limits = Limits::new(&options);
+    let result =
+        run(&root, &options, &constants, &mut limits).and_then(|(value, reductions, usage)| {
+            let term = Readback::default().value(&value, 0)?;
+            Ok(Interpreted {
+                stats: Stats { reductions },
+                usage,
+                term,
+            })
+        });
+    match result {
+        Err(InterpretError::TooDeep) => find_loop(root, options, provider, &limits),
+        result => {
+            drop_node(root);
+            result
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::parser::test::parse_ok, *};
+
+    fn assert_same(code: &str) {
+        let expected = interpret(
+            parse_ok(code),
+            ReductionStrategy::CallByName,
+            ConstantProvider::all(),
+        );
+        let krivine = interpret_krivine(
+            parse_ok(code),
+            ReductionStrategy::CallByName,
+            ConstantProvider::all(),
+        );
+        assert_eq!(
+            krivine.map(|i| (i.term, i.stats, i.usage.peak_size)),
+            expected.map(|i| (i.term, i.stats, i.usage.peak_size)),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn same_as_call_by_name() {
+        assert_same("z");
+        assert_same("x: y: x z");
+        assert_same("(x: x x) (y: z)");
+        assert_same("(x: y: x) y");
+        assert_same("(x: y: x) y z");
+        assert_same("(f: f f) (x: y: x y)");
+        assert_same("y: (x: y: x y) y");
+        assert_same("(x: z: x x x z) (y: y) A");
+        assert_same("(x: w x x) ((y: y) (z: z))");
+        assert_same("(x: z) ((x: x x) (x: x x))");
+        assert_same("(x: x x) (x: x x)");
+        assert_same("let ID = x: x in ID ID A");
+        assert_same("TRUE A B");
+        assert_same("POP (PUSH A FALSE)");
+        assert_same("(f:a:b: f b a) FALSE A B");
+        assert_same("3 (x: S x) Z");
+    }
+
+    #[test]
+    fn deep_terms() {
+        let code = format!("{}A{}", "(x: x) (".repeat(1000), ")".repeat(1000));
+        assert_same(&code);
+        let options = InterpretOptions {
+            max_depth: 100,
+            ..ReductionStrategy::CallByName.into()
+        };
+        assert_eq!(
+            interpret_krivine(parse_ok(&code), options, ConstantProvider::all()).unwrap_err(),
+            InterpretError::TooDeep
+        );
+    }
+}
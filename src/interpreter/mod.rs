@@ -1,11 +1,30 @@
+mod closures;
+mod combinators;
 mod constants;
+mod decode;
+mod diff;
+mod hashcons;
 pub mod interpreter;
+mod krivine;
 mod node_display;
 mod parser;
+mod profile;
+mod shared;
+mod span;
 mod tokenizer;
 pub mod traversers;
+mod types;
 
+pub use combinators::*;
 pub use constants::*;
+pub use decode::*;
+pub use diff::*;
+pub use hashcons::*;
 pub use interpreter::*;
+pub use krivine::*;
 pub use parser::*;
+pub use profile::*;
+pub use shared::*;
+pub use span::*;
 pub use tokenizer::*;
+pub use types::*;
@@ -1,17 +1,18 @@
 use std::{
-    collections::{HashMap, HashSet}, fmt::{self, Display}
+    collections::{HashMap, HashSet}, fmt::{self, Display}, ops::Range
 };
 
-use super::{Node, TVariable, Variable};
+use super::{traversers::PathStep, ConstantNames, Node, TVariable, Variable};
 
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Node::Constant(c) => f.write_str(c),
-            Node::Variable(v) => v.fmt(f),
-            Node::Function { variable, body } =>
+            Node::Constant(c, _) => f.write_str(c),
+            Node::Variable(v, _) => v.fmt(f),
+            Node::Function { variable, body, .. } =>
                 f.write_fmt(format_args!("({:?}: {:?})", variable, body)),
-            Node::Apply { left, right } => f.write_fmt(format_args!("({:?} {:?})", left, right)),
+            Node::Apply { left, right, .. } =>
+                f.write_fmt(format_args!("({:?} {:?})", left, right)),
         }
     }
 }
@@ -21,7 +22,7 @@ struct Data {
     // have a suffix if the expression has two variables with the same name
     bound_vars:       Vec<String>,
     // How many times this is variable is bound
-    bound_times:      HashMap<char, usize>,
+    bound_times:      HashMap<TVariable, usize>,
     // Unbound variables that are shadowed by a variable of the same name, somewhere in the term
     shadowed_unbound: HashSet<TVariable>,
 }
@@ -35,7 +36,7 @@ impl Data {
         }
     }
 
-    fn get_text(&mut self, var: Variable) -> String {
+    fn get_text(&mut self, var: &Variable) -> String {
         let cur_depth = self.bound_vars.len();
         if var.depth >= cur_depth {
             debug_assert!(var.depth == cur_depth, "Can't have more depth than current");
@@ -43,16 +44,16 @@ impl Data {
                 // Shadowed unbound variables start with _ to differentiate them
                 format!("_{}", var.original)
             } else {
-                var.original.into()
+                var.original.to_string()
             }
         } else {
             self.bound_vars[cur_depth - var.depth - 1].clone()
         }
     }
 
-    fn get_suffix(&self, var: TVariable) -> String {
+    fn get_suffix(&self, var: &str) -> String {
         self.bound_times
-            .get(&var)
+            .get(var)
             .map(|n| "'".repeat(*n))
             .unwrap_or_default()
     }
@@ -60,50 +61,106 @@ impl Data {
     fn with_bound_var<F: FnOnce(&mut Data) -> fmt::Result>(
         &mut self,
         f: F,
-        var: TVariable,
+        var: &TVariable,
     ) -> fmt::Result {
         self.bound_vars
             .push(var.to_string() + &self.get_suffix(var));
-        *self.bound_times.entry(var).or_insert(0) += 1;
+        *self.bound_times.entry(var.clone()).or_insert(0) += 1;
         let r = f(self);
-        *self.bound_times.get_mut(&var).unwrap() -= 1;
+        *self.bound_times.get_mut(var).unwrap() -= 1;
         self.bound_vars.pop().unwrap();
         r
     }
 }
 
+/// Writes the text, keeping track of where the marked subterms are in it
+struct Output<'a, 'b> {
+    out:   &'a mut dyn fmt::Write,
+    /// Characters written so far
+    len:   usize,
+    /// Path from the root to the node being written
+    path:  Vec<PathStep>,
+    marks: &'b [&'b [PathStep]],
+    found: Vec<Option<Range<usize>>>,
+}
+
+impl Output<'_, '_> {
+    fn write(&mut self, s: &str) -> fmt::Result {
+        self.len += s.chars().count();
+        self.out.write_str(s)
+    }
+
+    fn child<F: FnOnce(&mut Self) -> fmt::Result>(&mut self, step: PathStep, f: F) -> fmt::Result {
+        self.path.push(step);
+        let r = f(self);
+        self.path.pop();
+        r
+    }
+}
+
+/// Name a bound variable is shown with. Lets can bind names that look like constants, which would
+/// be read as constants if shown as they are, so they are shown in lower case.
+fn binder_name(variable: &TVariable) -> TVariable {
+    if !variable.chars().any(|c| c.is_ascii_uppercase()) {
+        return variable.clone();
+    }
+    let name: String = variable
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        name.into()
+    } else {
+        format!("v{}", name).into()
+    }
+}
+
 fn rec_display(
     node: &Node,
     data: &mut Data,
-    f: &mut fmt::Formatter<'_>,
+    f: &mut Output,
     func_prefix: bool,
     needs_assoc_par: bool,
 ) -> fmt::Result {
+    let start = f.len;
     match node {
-        Node::Constant(c) => c.fmt(f)?,
-        Node::Variable(v) => data.get_text(*v).fmt(f)?,
-        Node::Function { variable, body } => {
+        Node::Constant(c, _) => f.write(c)?,
+        Node::Variable(v, _) => f.write(&data.get_text(v))?,
+        Node::Function { variable, body, .. } => {
             if !func_prefix || needs_assoc_par {
-                "(".fmt(f)?;
+                f.write("(")?;
             }
-            f.write_fmt(format_args!("{}{}: ", variable, data.get_suffix(*variable)))?;
-            data.with_bound_var(|data| rec_display(&body, data, f, true, false), *variable)?;
+            let variable = binder_name(variable);
+            f.write(&format!("{}{}: ", variable, data.get_suffix(&variable)))?;
+            f.child(PathStep::Body, |f| {
+                data.with_bound_var(|data| rec_display(&body, data, f, true, false), &variable)
+            })?;
             if !func_prefix || needs_assoc_par {
-                ")".fmt(f)?;
+                f.write(")")?;
             }
         },
-        Node::Apply { left, right } => {
+        Node::Apply { left, right, .. } => {
             if needs_assoc_par {
-                "(".fmt(f)?;
+                f.write("(")?;
             }
-            rec_display(&left, data, f, false, false)?;
-            " ".fmt(f)?;
-            rec_display(&right, data, f, false, true)?;
+            f.child(PathStep::Left, |f| {
+                rec_display(&left, data, f, false, false)
+            })?;
+            f.write(" ")?;
+            f.child(PathStep::Right, |f| {
+                rec_display(&right, data, f, false, true)
+            })?;
             if needs_assoc_par {
-                ")".fmt(f)?;
+                f.write(")")?;
             }
         },
     }
+    for (mark, found) in f.marks.iter().zip(&mut f.found) {
+        if *mark == f.path.as_slice() {
+            *found = Some(start..f.len);
+        }
+    }
     Ok(())
 }
 
@@ -114,42 +171,93 @@ fn mark_shadowed_unbound_variables(
     cur_depth: usize,
 ) {
     match node {
-        Node::Constant(_) => {},
-        Node::Variable(v) =>
+        Node::Constant(..) => {},
+        Node::Variable(v, _) =>
             if v.depth == cur_depth && *map.get(&v.original).unwrap_or(&0) > 0 {
-                shadowed.insert(v.original);
+                shadowed.insert(v.original.clone());
             },
-        Node::Function { variable, body } => {
-            *map.entry(*variable).or_insert(0) += 1;
+        Node::Function { variable, body, .. } => {
+            let variable = binder_name(variable);
+            *map.entry(variable.clone()).or_insert(0) += 1;
             mark_shadowed_unbound_variables(body, map, shadowed, cur_depth + 1);
-            *map.get_mut(variable).unwrap() -= 1;
+            *map.get_mut(&variable).unwrap() -= 1;
         },
-        Node::Apply { left, right } => {
+        Node::Apply { left, right, .. } => {
             mark_shadowed_unbound_variables(left, map, shadowed, cur_depth);
             mark_shadowed_unbound_variables(right, map, shadowed, cur_depth);
         },
     }
 }
 
-impl Display for Node {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Node {
+    fn write_marking(
+        &self,
+        out: &mut dyn fmt::Write,
+        marks: &[&[PathStep]],
+    ) -> Result<Vec<Option<Range<usize>>>, fmt::Error> {
         let mut shadowed = HashSet::new();
         mark_shadowed_unbound_variables(self, &mut HashMap::new(), &mut shadowed, 0);
-        rec_display(self, &mut Data::new(shadowed), f, true, false)
+        let mut output = Output {
+            out,
+            len: 0,
+            path: vec![],
+            marks,
+            found: vec![None; marks.len()],
+        };
+        rec_display(self, &mut Data::new(shadowed), &mut output, true, false)?;
+        Ok(output.found)
+    }
+
+    /// Same text as Display, together with where the subterms at the given paths are in it, as
+    /// ranges of characters. None for paths that are not in the term.
+    pub fn to_string_marking(&self, marks: &[&[PathStep]]) -> (String, Vec<Option<Range<usize>>>) {
+        let mut text = String::new();
+        let found = self
+            .write_marking(&mut text, marks)
+            .expect("Writing to a String doesn't fail");
+        (text, found)
+    }
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_marking(f, &[]).map(|_| ())
+    }
+}
+
+/// Node shown with the subterms that are equal to known constants replaced by their names
+pub struct WithNames<'a> {
+    node:  &'a Node,
+    names: &'a ConstantNames,
+}
+
+impl Node {
+    pub fn with_names<'a>(&'a self, names: &'a ConstantNames) -> WithNames<'a> {
+        WithNames { node: self, names }
     }
 }
 
+impl Display for WithNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.names.fold(self.node).fmt(f) }
+}
+
 #[cfg(test)]
 mod test {
     use crate::interpreter::{
-        interpreter::test::interpret_ok_full, parser::test::{parse_ok, ConvertToNode}
+        interpreter::test::interpret_ok_full, parser::test::{parse_ok, ConvertToNode}, traversers::PathStep, ConstantProvider, ReductionStrategy
     };
 
     fn display_eq(original: &str, display: &str) {
         assert_eq!(display, &format!("{}", parse_ok(original)));
     }
     fn interpret_eq(original: &str, display: &str) {
-        assert_eq!(display, &format!("{}", interpret_ok_full(original, true)));
+        assert_eq!(
+            display,
+            &format!(
+                "{}",
+                interpret_ok_full(original, ReductionStrategy::Applicative)
+            )
+        );
     }
 
     #[test]
@@ -194,6 +302,62 @@ mod test {
         interpret_eq("z ((x: y: x) z)", "z (y: z)");
     }
 
+    #[test]
+    fn test_long_names() {
+        display_eq("acc: x1: acc x1", "acc: x1: acc x1");
+        interpret_eq("(x: acc: x) acc", "acc: _acc");
+        interpret_eq(
+            "rest: (acc: rest: acc rest) rest",
+            "rest: rest': rest rest'",
+        );
+    }
+
+    #[test]
+    fn test_let_names() {
+        // Names that look like constants would be read back as constants
+        display_eq("let ID = x: x in ID A", "(id: id A) (x: x)");
+        display_eq("let ID = x: x in id: ID id", "(id: id': id id') (x: x)");
+        display_eq("let ID = x: x in ID id", "(id: id _id) (x: x)");
+        display_eq("let T2 = x: x in T2", "(t2: t2) (x: x)");
+        assert_eq!(
+            parse_ok(&parse_ok("let T = x: y: x in T T").to_string()),
+            parse_ok("let T = x: y: x in T T")
+        );
+    }
+
+    #[test]
+    fn test_with_names() {
+        let names = ConstantProvider::all().names();
+        let with_names = |code: &str| format!("{}", parse_ok(code).with_names(&names));
+        assert_eq!(with_names("a: b: a"), "TRUE");
+        assert_eq!(with_names("x (a: b: b) (y: y x)"), "x FALSE (y: y x)");
+        assert_eq!(with_names("x: (a: b: a) x"), "x: TRUE x");
+        assert_eq!(with_names("(a: b: a) A"), "TRUE A");
+        // Found by the normal form, the definition uses other constants
+        assert_eq!(with_names("l: l (h: t: x: a: b: b) (a: b: a)"), "EMPTY");
+        assert_eq!(with_names("(a: b: a) (c: d: d)"), "TRUE FALSE");
+        // Subterms on the path are kept
+        let around = |code: &str, path: &[PathStep]| names.fold_around(&parse_ok(code), path);
+        assert_eq!(
+            around("(a: b: a) (c: d: d)", &[PathStep::Left]).to_string(),
+            "(a: b: a) FALSE"
+        );
+        assert_eq!(around("x: x (c: d: d)", &[]).to_string(), "x: x FALSE");
+    }
+
+    #[test]
+    fn test_marking() {
+        use PathStep::*;
+        let (text, found) = parse_ok("x: (y: y) (A x)").to_string_marking(&[
+            &[Body, Left],
+            &[Body, Right],
+            &[Body, Right, Left],
+            &[Left],
+        ]);
+        assert_eq!(text, "x: (y: y) (A x)");
+        assert_eq!(found, vec![Some(3..9), Some(10..15), Some(11..12), None]);
+    }
+
     #[test]
     fn test_same_name_vars() {
         assert_eq!(
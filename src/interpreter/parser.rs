@@ -5,14 +5,16 @@ use std::{
 use thiserror::Error;
 use vec1::Vec1;
 
-use super::tokenizer::{Constant, TVariable, Token};
+use super::{
+    tokenizer::{Constant, TVariable, Token, TokenKind}, traversers::{fold, Folded}, Span
+};
 
 /// depth can be uniquely used to determine the expression, as it points to
 /// which function binded the variable.
 /// It's better than an uid as replacing values make sense. For example,
 /// on (f: f f) (x: y: x y) we may end up with (y: y: y y), but each y
 /// points to a different function.
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Variable {
     /// How many "levels above" is the variable created
     /// If unbound, then this should be the depth of this node
@@ -37,33 +39,137 @@ impl fmt::Debug for Variable {
     }
 }
 
-#[derive(Clone)]
+/// Every node carries the span of the source it was parsed from, which is ignored
+/// when comparing terms.
 pub enum Node {
-    Constant(Constant),
-    Variable(Variable),
+    Constant(Constant, Span),
+    Variable(Variable, Span),
     Function {
         // This variable doesn't have a depth.
         variable: TVariable,
         body:     Box<Node>,
+        span:     Span,
     },
     Apply {
         left:  Box<Node>,
         right: Box<Node>,
+        span:  Span,
     },
 }
 
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Constant(_, span)
+            | Node::Variable(_, span)
+            | Node::Function { span, .. }
+            | Node::Apply { span, .. } => *span,
+        }
+    }
+}
+
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum ParseError {
-    #[error("Function must be first part of expression!")]
-    FunctionInsideBody,
-    #[error("Extra ':' in the input")]
-    ExtraColon,
-    #[error("Some subexpression '()' is empty")]
-    MissingExpression,
-    #[error("Parenthesis is unclosed")]
-    UnclosedParenthesis,
-    #[error("Extra close parenthesis")]
-    ExtraCloseParenthesis,
+    #[error("Function at {0} must be first part of expression!")]
+    FunctionInsideBody(Span),
+    #[error("Extra ':' in the input at {0}")]
+    ExtraColon(Span),
+    #[error("Some subexpression '()' at {0} is empty")]
+    MissingExpression(Span),
+    #[error("Parenthesis at {0} is unclosed")]
+    UnclosedParenthesis(Span),
+    #[error("Extra close parenthesis at {0}")]
+    ExtraCloseParenthesis(Span),
+    #[error("Let at {0} must be first part of expression!")]
+    LetInsideBody(Span),
+    #[error("Let at {0} must be of the form 'let NAME = term in body'")]
+    MalformedLet(Span),
+    #[error("Let at {0} is missing 'in' after its definition")]
+    MissingIn(Span),
+    #[error("Extra 'in' at {0} without a let")]
+    ExtraIn(Span),
+    #[error("Extra '=' at {0} outside of a let")]
+    ExtraEquals(Span),
+    #[error("List at {0} is never closed with ]")]
+    UnclosedBracket(Span),
+    #[error("Extra ']' at {0} without a list")]
+    ExtraCloseBracket(Span),
+    #[error("Extra ',' at {0} outside of a list")]
+    ExtraComma(Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::FunctionInsideBody(span)
+            | ParseError::ExtraColon(span)
+            | ParseError::MissingExpression(span)
+            | ParseError::UnclosedParenthesis(span)
+            | ParseError::ExtraCloseParenthesis(span)
+            | ParseError::LetInsideBody(span)
+            | ParseError::MalformedLet(span)
+            | ParseError::MissingIn(span)
+            | ParseError::ExtraIn(span)
+            | ParseError::ExtraEquals(span)
+            | ParseError::UnclosedBracket(span)
+            | ParseError::ExtraCloseBracket(span)
+            | ParseError::ExtraComma(span) => *span,
+        }
+    }
+}
+
+/// A function enveloping a level, "x:" or "let x = term in"
+#[derive(Debug)]
+struct Binder {
+    variable:   TVariable,
+    /// Span of "x:" or "let x ="
+    span:       Span,
+    /// For lets, the term the variable is defined as. "let x = A in B" is the same as "(x: B) A"
+    definition: Option<Box<Node>>,
+}
+
+/// What created a level that is not the outermost one
+#[derive(Debug)]
+enum Opener {
+    Parenthesis(Span),
+    /// The definition of a let, which ends on "in". Has the variable defined and the span of
+    /// "let x ="
+    Let(TVariable, Span),
+    /// An item of a list literal, which ends on "," or "]". Has the span of "[" and the items
+    /// before this one
+    List(Span, Vec<Node>),
+}
+
+impl Opener {
+    fn span(&self) -> Span {
+        match self {
+            Opener::Parenthesis(span) | Opener::Let(_, span) | Opener::List(span, _) => *span,
+        }
+    }
+}
+
+// List and string literals are sugar for the lists of the pair and list section, so they can only
+// be used once these constants are known. Strings are lists of the character codes as numerals.
+const LIST_PUSH: &str = "PUSH";
+const LIST_EMPTY: &str = "FALSE";
+
+/// PUSH item0 (PUSH item1 (... FALSE)), with all the constants on the given span
+fn list_node(items: Vec<Node>, span: Span) -> Box<Node> {
+    let constant = |c: &str| Box::new(Node::Constant(c.to_string(), span));
+    items
+        .into_iter()
+        .rev()
+        .fold(constant(LIST_EMPTY), |rest, item| {
+            Box::new(Node::Apply {
+                left:  Box::new(Node::Apply {
+                    left:  constant(LIST_PUSH),
+                    span:  span.merge(item.span()),
+                    right: Box::new(item),
+                }),
+                span:  span.merge(rest.span()),
+                right: rest,
+            })
+        })
 }
 
 /// Level represents an unclosed sequence of terms. It is represented by some
@@ -72,7 +178,9 @@ pub enum ParseError {
 #[derive(Debug, Default)]
 struct Level {
     prev_node:            Option<Box<Node>>,
-    enveloping_functions: Vec<TVariable>,
+    enveloping_functions: Vec<Binder>,
+    /// None on the outermost level
+    opener:               Option<Opener>,
 }
 
 impl Level {
@@ -81,6 +189,7 @@ impl Level {
     fn merge(&mut self, node: Box<Node>) {
         self.prev_node = if let Some(prev) = self.prev_node.take() {
             Some(Box::new(Node::Apply {
+                span:  prev.span().merge(node.span()),
                 left:  prev,
                 right: node,
             }))
@@ -91,18 +200,43 @@ impl Level {
 
     /// Finish this level, and turn it into a single term. Fails if prev_node is
     /// None.
-    fn close(mut self, bindings: &mut Bindings) -> Result<Box<Node>, ParseError> {
+    fn close(
+        mut self,
+        bindings: &mut Bindings,
+        closer: Option<Span>,
+    ) -> Result<Box<Node>, ParseError> {
         let mut node = if let Some(n) = self.prev_node.take() {
             n
         } else {
-            return Err(ParseError::MissingExpression);
+            return Err(ParseError::MissingExpression(
+                self.opener
+                    .map(|o| o.span())
+                    .into_iter()
+                    .chain(self.enveloping_functions.last().map(|b| b.span))
+                    .chain(closer)
+                    .reduce(Span::merge)
+                    .unwrap_or_default(),
+            ));
         };
-        while let Some(variable) = self.enveloping_functions.pop() {
-            bindings.pop_var(variable);
+        while let Some(Binder {
+            variable,
+            span,
+            definition,
+        }) = self.enveloping_functions.pop()
+        {
+            bindings.pop_var(&variable);
             node = Box::new(Node::Function {
                 variable,
+                span: span.merge(node.span()),
                 body: node,
             });
+            if let Some(definition) = definition {
+                node = Box::new(Node::Apply {
+                    span:  node.span().merge(definition.span()),
+                    left:  node,
+                    right: definition,
+                });
+            }
         }
         Ok(node)
     }
@@ -110,7 +244,7 @@ impl Level {
 
 #[derive(Debug)]
 struct Bindings {
-    /// For each variable, at which depths it was defined
+    /// For each variable name, at which depths it was defined
     /// Example, for (x: x: x), you would get x with depth 1 and 2
     map:       HashMap<TVariable, Vec1<usize>>,
     cur_depth: usize,
@@ -135,20 +269,22 @@ impl Bindings {
         };
     }
 
+    fn is_bound(&self, name: &str) -> bool { self.map.contains_key(name) }
+
     /// Get the depth for the variable if it was added now
-    fn get_var(&mut self, name: TVariable) -> usize {
-        self.cur_depth - self.map.get(&name).map(|v| *v.last()).unwrap_or(0)
+    fn get_var(&mut self, name: &str) -> usize {
+        self.cur_depth - self.map.get(name).map(|v| *v.last()).unwrap_or(0)
     }
 
-    fn pop_var(&mut self, name: TVariable) {
+    fn pop_var(&mut self, name: &str) {
         self.cur_depth -= 1;
-        match self.map.entry(name) {
-            Entry::Occupied(mut entry) =>
-                if entry.get_mut().pop().is_err() {
-                    entry.remove();
+        match self.map.get_mut(name) {
+            Some(depths) =>
+                if depths.pop().is_err() {
+                    self.map.remove(name);
                 },
             // TODO: Add algorithm error here
-            Entry::Vacant(..) => panic!("Should have entry"),
+            None => panic!("Should have entry"),
         }
     }
 }
@@ -159,106 +295,257 @@ pub fn parse<T: IntoIterator<Item = Token>>(tokens: T) -> Result<Box<Node>, Pars
     let mut levels = Vec1::new(Level::default());
     let mut iter = tokens.into_iter().peekable();
     let mut bindings = Bindings::new();
-    while let Some(token) = iter.next() {
-        match token {
-            Token::Variable(name) =>
-                if iter.peek() == Some(&Token::Colon) {
-                    iter.next().unwrap();
+    while let Some(Token { kind, span }) = iter.next() {
+        match kind {
+            TokenKind::Variable(name) =>
+                if iter.peek().map(|t| &t.kind) == Some(&TokenKind::Colon) {
+                    let span = span.merge(iter.next().unwrap().span);
                     if levels.last().prev_node.is_some() {
-                        return Err(ParseError::FunctionInsideBody);
+                        return Err(ParseError::FunctionInsideBody(span));
                     }
-                    bindings.push_var(name);
-                    levels.last_mut().enveloping_functions.push(name);
+                    bindings.push_var(name.clone());
+                    levels.last_mut().enveloping_functions.push(Binder {
+                        variable: name,
+                        span,
+                        definition: None,
+                    });
                 } else {
-                    levels
-                        .last_mut()
-                        .merge(Box::new(Node::Variable(Variable::new(
-                            bindings.get_var(name),
-                            name,
-                        ))));
+                    levels.last_mut().merge(Box::new(Node::Variable(
+                        Variable::new(bindings.get_var(&name), name),
+                        span,
+                    )));
+                },
+            TokenKind::Constant(c) =>
+            // Lets may give names that look like constants
+                if bindings.is_bound(&c) {
+                    let name: TVariable = c.into();
+                    levels.last_mut().merge(Box::new(Node::Variable(
+                        Variable::new(bindings.get_var(&name), name),
+                        span,
+                    )));
+                } else {
+                    levels.last_mut().merge(Box::new(Node::Constant(c, span)));
+                },
+            TokenKind::String(text) => levels.last_mut().merge(list_node(
+                text.chars()
+                    .map(|c| Node::Constant((c as u32).to_string(), span))
+                    .collect(),
+                span,
+            )),
+            TokenKind::Colon => {
+                return Err(ParseError::ExtraColon(span));
+            },
+            TokenKind::Let => {
+                if levels.last().prev_node.is_some() {
+                    return Err(ParseError::LetInsideBody(span));
+                }
+                let name = match iter.next() {
+                    Some(Token {
+                        kind: TokenKind::Variable(name),
+                        ..
+                    }) => name,
+                    Some(Token {
+                        kind: TokenKind::Constant(name),
+                        ..
+                    }) if !name.starts_with(|c: char| c.is_ascii_digit()) => name.into(),
+                    other => return Err(ParseError::MalformedLet(other.map_or(span, |t| t.span))),
+                };
+                match iter.next() {
+                    Some(Token {
+                        kind: TokenKind::Equals,
+                        span: eq_span,
+                    }) => levels.push(Level {
+                        opener: Some(Opener::Let(name, span.merge(eq_span))),
+                        ..Level::default()
+                    }),
+                    other => return Err(ParseError::MalformedLet(other.map_or(span, |t| t.span))),
+                }
+            },
+            TokenKind::In => match levels.last().opener {
+                Some(Opener::Let(ref name, let_span)) => {
+                    let name = name.clone();
+                    let definition = levels.pop().unwrap().close(&mut bindings, Some(span))?;
+                    // Lets are not recursive, so the name is only bound after its definition
+                    bindings.push_var(name.clone());
+                    levels.last_mut().enveloping_functions.push(Binder {
+                        variable:   name,
+                        span:       let_span,
+                        definition: Some(definition),
+                    });
                 },
-            Token::Constant(c) => {
-                levels.last_mut().merge(Box::new(Node::Constant(c)));
+                _ => return Err(ParseError::ExtraIn(span)),
             },
-            Token::Colon => {
-                return Err(ParseError::ExtraColon);
+            TokenKind::Equals => {
+                return Err(ParseError::ExtraEquals(span));
             },
-            Token::OpenPar => levels.push(Level::default()),
-            Token::ClosePar =>
+            TokenKind::OpenPar => levels.push(Level {
+                opener: Some(Opener::Parenthesis(span)),
+                ..Level::default()
+            }),
+            TokenKind::ClosePar =>
                 if let Ok(last) = levels.pop() {
-                    levels.last_mut().merge(last.close(&mut bindings)?);
+                    match last.opener {
+                        Some(Opener::Let(_, let_span)) =>
+                            return Err(ParseError::MissingIn(let_span)),
+                        Some(Opener::List(list_span, _)) =>
+                            return Err(ParseError::UnclosedBracket(list_span)),
+                        _ => {},
+                    }
+                    levels
+                        .last_mut()
+                        .merge(last.close(&mut bindings, Some(span))?);
                 } else {
-                    return Err(ParseError::ExtraCloseParenthesis);
+                    return Err(ParseError::ExtraCloseParenthesis(span));
+                },
+            TokenKind::OpenBracket => levels.push(Level {
+                opener: Some(Opener::List(span, vec![])),
+                ..Level::default()
+            }),
+            TokenKind::Comma => match levels.last_mut().opener {
+                Some(Opener::List(list_span, ref mut items)) => {
+                    let mut items = std::mem::take(items);
+                    items.push(*levels.pop().unwrap().close(&mut bindings, Some(span))?);
+                    levels.push(Level {
+                        opener: Some(Opener::List(list_span, items)),
+                        ..Level::default()
+                    });
+                },
+                Some(Opener::Let(_, let_span)) => return Err(ParseError::MissingIn(let_span)),
+                _ => return Err(ParseError::ExtraComma(span)),
+            },
+            TokenKind::CloseBracket => match levels.last_mut().opener {
+                Some(Opener::List(list_span, ref mut items)) => {
+                    let mut items = std::mem::take(items);
+                    let last = levels.pop().unwrap();
+                    // The empty list has no items, but any other must not end on ","
+                    if !items.is_empty()
+                        || last.prev_node.is_some()
+                        || !last.enveloping_functions.is_empty()
+                    {
+                        items.push(*last.close(&mut bindings, Some(span))?);
+                    }
+                    levels
+                        .last_mut()
+                        .merge(list_node(items, list_span.merge(span)));
                 },
+                Some(Opener::Let(_, let_span)) => return Err(ParseError::MissingIn(let_span)),
+                Some(Opener::Parenthesis(par_span)) =>
+                    return Err(ParseError::UnclosedParenthesis(par_span)),
+                None => return Err(ParseError::ExtraCloseBracket(span)),
+            },
         }
     }
     if levels.len() > 1 {
-        return Err(ParseError::UnclosedParenthesis);
+        return Err(match levels.last().opener {
+            Some(Opener::Let(_, span)) => ParseError::MissingIn(span),
+            Some(Opener::List(span, _)) => ParseError::UnclosedBracket(span),
+            ref opener => ParseError::UnclosedParenthesis(
+                opener.as_ref().map(|o| o.span()).unwrap_or_default(),
+            ),
+        });
     }
-    Vec::from(levels).pop().unwrap().close(&mut bindings)
+    Vec::from(levels).pop().unwrap().close(&mut bindings, None)
 }
 
-impl Node {
-    fn synthatic_eq(&self, other: &Node, cur_depth: usize) -> bool {
-        match (self, other) {
-            (Node::Constant(c1), Node::Constant(c2)) => c1 == c2,
-            (Node::Variable(v1), Node::Variable(v2)) =>
-            // Unbound vars (depth = cur_depth) need to have same char
-                v1.depth == v2.depth && (v1.depth < cur_depth || v1.original == v2.original),
-            (
-                Node::Function { variable: _, body },
-                Node::Function {
-                    variable: _,
-                    body: body2,
+// Terms may be very deep, so cloning and comparing them doesn't recurse
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        *fold(self, 0, |folded, _| {
+            Box::new(match folded {
+                Folded::Constant(c, span) => Node::Constant(c.clone(), span),
+                Folded::Variable(v, span) => Node::Variable(v.clone(), span),
+                Folded::Function(variable, body, span) => Node::Function {
+                    variable: variable.clone(),
+                    body,
+                    span,
                 },
-            ) => body.synthatic_eq(body2, cur_depth + 1),
-            (
-                Node::Apply { left, right },
-                Node::Apply {
-                    left: left2,
-                    right: right2,
-                },
-            ) => left.synthatic_eq(left2, cur_depth) && right.synthatic_eq(right2, cur_depth),
-            _ => false,
-        }
+                Folded::Apply(left, right, span) => Node::Apply { left, right, span },
+            })
+        })
     }
 }
 
 impl PartialEq for Node {
-    fn eq(&self, other: &Node) -> bool { self.synthatic_eq(other, 0) }
+    fn eq(&self, other: &Node) -> bool {
+        let mut pending = vec![(self, other, 0)];
+        while let Some((node, other, cur_depth)) = pending.pop() {
+            match (node, other) {
+                (Node::Constant(c1, _), Node::Constant(c2, _)) if c1 == c2 => {},
+                // Unbound vars (depth = cur_depth) need to have same char
+                (Node::Variable(v1, _), Node::Variable(v2, _))
+                    if v1.depth == v2.depth
+                        && (v1.depth < cur_depth || v1.original == v2.original) => {},
+                (Node::Function { body, .. }, Node::Function { body: body2, .. }) =>
+                    pending.push((body, body2, cur_depth + 1)),
+                (
+                    Node::Apply { left, right, .. },
+                    Node::Apply {
+                        left: left2,
+                        right: right2,
+                        ..
+                    },
+                ) => {
+                    pending.push((right, right2, cur_depth));
+                    pending.push((left, left2, cur_depth));
+                },
+                _ => return false,
+            }
+        }
+        true
+    }
 }
 
 impl Eq for Node {}
 
 #[cfg(test)]
 pub mod test {
-    use super::{super::tokenizer::tokenize, *};
+    use super::{
+        super::{tokenizer::tokenize, Location}, *
+    };
 
     pub fn parse_ok(str: &str) -> Box<Node> { parse(tokenize(str.chars()).unwrap()).unwrap() }
 
     fn parse_err(str: &str) -> ParseError { parse(tokenize(str.chars()).unwrap()).unwrap_err() }
 
+    fn token(kind: TokenKind) -> Token {
+        Token {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    fn span(line: u32, start: u32, end: u32) -> Span {
+        Span::new(Location::new(line, start), Location::new(line, end))
+    }
+
     impl From<usize> for Variable {
         fn from(depth: usize) -> Self {
             Self {
                 depth,
-                original: '-',
+                original: "-".into(),
             }
         }
     }
 
     impl From<usize> for Box<Node> {
-        fn from(depth: usize) -> Self { Box::new(Node::Variable(depth.into())) }
+        fn from(depth: usize) -> Self { Box::new(Node::Variable(depth.into(), Span::default())) }
     }
 
     impl From<(usize, char)> for Box<Node> {
         fn from((depth, original): (usize, char)) -> Self {
-            Box::new(Node::Variable(Variable { depth, original }))
+            Box::new(Node::Variable(
+                Variable {
+                    depth,
+                    original: original.to_string().into(),
+                },
+                Span::default(),
+            ))
         }
     }
 
     impl From<&str> for Box<Node> {
-        fn from(val: &str) -> Self { Box::new(Node::Constant(val.to_string())) }
+        fn from(val: &str) -> Self { Box::new(Node::Constant(val.to_string(), Span::default())) }
     }
 
     impl From<(Box<Node>, Box<Node>)> for Box<Node> {
@@ -266,6 +553,7 @@ pub mod test {
             Box::new(Node::Apply {
                 left:  args.0,
                 right: args.1,
+                span:  Span::default(),
             })
         }
     }
@@ -273,8 +561,9 @@ pub mod test {
     impl From<((), Box<Node>)> for Box<Node> {
         fn from(args: ((), Box<Node>)) -> Self {
             Box::new(Node::Function {
-                variable: '-',
+                variable: "-".into(),
                 body:     args.1,
+                span:     Span::default(),
             })
         }
     }
@@ -289,12 +578,18 @@ pub mod test {
 
     #[test]
     fn simple() {
-        assert_eq!(parse_ok("A"), Box::new(Node::Constant("A".to_string())));
-        assert_eq!(parse(vec![Token::Variable('x')]).unwrap(), (0, 'x').n());
-        assert_ne!(parse(vec![Token::Variable('x')]).unwrap(), (0, 'y').n());
+        assert_eq!(parse_ok("A"), "A".n());
+        assert_eq!(
+            parse(vec![token(TokenKind::Variable("x".into()))]).unwrap(),
+            (0, 'x').n()
+        );
+        assert_ne!(
+            parse(vec![token(TokenKind::Variable("x".into()))]).unwrap(),
+            (0, 'y').n()
+        );
         assert_eq!(
-            parse_ok("a bc c"),
-            (((0, 'a').n(), "bc".n()).n(), (0, 'c').n()).n()
+            parse_ok("a BC c"),
+            (((0, 'a').n(), "BC".n()).n(), (0, 'c').n()).n()
         );
         assert_eq!(
             parse_ok("a b c"),
@@ -304,7 +599,21 @@ pub mod test {
         assert_eq!(parse_ok("x:y:y"), ((), ((), 0.n()).n()).n());
         assert_eq!(parse_ok("x:y:z"), ((), ((), (2, 'z').n()).n()).n());
         assert_eq!(parse_ok("(x: x) x"), (((), 0.n()).n(), (0, 'x').n()).n());
-        assert_eq!(parse_err(""), ParseError::MissingExpression);
+        assert_eq!(
+            parse_err(""),
+            ParseError::MissingExpression(Span::default())
+        );
+    }
+
+    #[test]
+    fn long_names() {
+        assert_eq!(parse_ok("acc: x: acc x"), parse_ok("a: x: a x"));
+        assert_eq!(parse_ok("left: right: left"), parse_ok("x:y:x"));
+        assert_ne!(parse_ok("left: right: left"), parse_ok("x:y:y"));
+        // Names that start the same are still different variables
+        assert_eq!(parse_ok("x: x1: x"), ((), ((), 1.n()).n()).n());
+        assert_eq!(parse_ok("rest: rest"), ((), 0.n()).n());
+        assert_ne!(parse_ok("rest"), parse_ok("r"));
     }
 
     #[test]
@@ -314,9 +623,64 @@ pub mod test {
             (Box::<Node>::from((0, 'x')), ((), 0.n()).n()).n()
         );
         assert_eq!(parse_ok("a b c"), parse_ok("((a b) c)"));
-        assert_eq!(parse_err("(a b ())"), ParseError::MissingExpression);
-        assert_eq!(parse_err("a)"), ParseError::ExtraCloseParenthesis);
-        assert_eq!(parse_err("a (b c"), ParseError::UnclosedParenthesis);
+        assert_eq!(
+            parse_err("(a b ())"),
+            ParseError::MissingExpression(span(0, 5, 7))
+        );
+        assert_eq!(
+            parse_err("a)"),
+            ParseError::ExtraCloseParenthesis(span(0, 1, 2))
+        );
+        assert_eq!(
+            parse_err("a (b c"),
+            ParseError::UnclosedParenthesis(span(0, 2, 3))
+        );
+        assert_eq!(
+            parse_err("(a\n (b c)\n (d (e)"),
+            ParseError::UnclosedParenthesis(span(2, 1, 2))
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(parse_ok("[]"), parse_ok("FALSE"));
+        assert_eq!(parse_ok("[A]"), parse_ok("PUSH A FALSE"));
+        assert_eq!(
+            parse_ok("f [A, x: x, [B C]] D"),
+            parse_ok("f (PUSH A (PUSH (x: x) (PUSH (PUSH (B C) FALSE) FALSE))) D")
+        );
+        assert_eq!(
+            parse_ok("x: [x, let A = x in A]"),
+            parse_ok("x: PUSH x (PUSH (let A = x in A) FALSE)")
+        );
+        assert_eq!(parse_ok(r#""AB""#), parse_ok("PUSH 65 (PUSH 66 FALSE)"));
+        assert_eq!(parse_ok(r#"[""]"#), parse_ok("PUSH FALSE FALSE"));
+        assert_eq!(parse_ok("[A]").span(), span(0, 0, 3));
+        assert_eq!(
+            parse_err("[A, ]"),
+            ParseError::MissingExpression(span(0, 0, 5))
+        );
+        assert_eq!(
+            parse_err("[A (B]"),
+            ParseError::UnclosedParenthesis(span(0, 3, 4))
+        );
+        assert_eq!(
+            parse_err("([A)"),
+            ParseError::UnclosedBracket(span(0, 1, 2))
+        );
+        assert_eq!(
+            parse_err("[A, B"),
+            ParseError::UnclosedBracket(span(0, 0, 1))
+        );
+        assert_eq!(
+            parse_err("A]"),
+            ParseError::ExtraCloseBracket(span(0, 1, 2))
+        );
+        assert_eq!(parse_err("A, B"), ParseError::ExtraComma(span(0, 1, 2)));
+        assert_eq!(
+            parse_err("[let A = B, C]"),
+            ParseError::MissingIn(span(0, 1, 8))
+        );
     }
 
     #[test]
@@ -352,11 +716,100 @@ pub mod test {
     #[test]
     fn some_errors() {
         assert_eq!(
-            parse(vec![Token::Colon]).unwrap_err(),
-            ParseError::ExtraColon
+            parse(vec![token(TokenKind::Colon)]).unwrap_err(),
+            ParseError::ExtraColon(Span::default())
+        );
+        assert_eq!(
+            parse_err("a: b c: d"),
+            ParseError::FunctionInsideBody(span(0, 5, 7))
+        );
+        assert_eq!(
+            parse_err("x:"),
+            ParseError::MissingExpression(span(0, 0, 2))
+        );
+        assert_eq!(parse_err("x: : y"), ParseError::ExtraColon(span(0, 3, 4)));
+    }
+
+    #[test]
+    fn lets() {
+        assert_eq!(
+            parse_ok("let id = x: x in id A"),
+            parse_ok("(id: id A) (x: x)")
+        );
+        assert_eq!(
+            parse_ok("let ID = x: x in ID A"),
+            parse_ok("(id: id A) (x: x)")
+        );
+        assert_eq!(
+            parse_ok("let T = x: y: x in\n let F = x: y: y in\n T F T"),
+            parse_ok("(t: (f: t f t) (x: y: y)) (x: y: x)")
+        );
+        // Definitions can't see their own name, so this ID is a constant
+        assert_eq!(parse_ok("let ID = ID in ID"), parse_ok("(x: x) ID"));
+        assert_eq!(parse_ok("x: let y = x in y"), parse_ok("x: (y: y) x"));
+        assert_eq!(parse_ok("(let A = B in A) C"), parse_ok("((a: a) B) C"));
+        assert_eq!(
+            parse_ok("let A = let B = C in B in A"),
+            parse_ok("(a: a) ((b: b) C)")
+        );
+    }
+
+    #[test]
+    fn let_errors() {
+        assert_eq!(
+            parse_err("a let B = C in B"),
+            ParseError::LetInsideBody(span(0, 2, 5))
+        );
+        assert_eq!(
+            parse_err("let 2 = C in C"),
+            ParseError::MalformedLet(span(0, 4, 5))
+        );
+        assert_eq!(
+            parse_err("let A B = C in B"),
+            ParseError::MalformedLet(span(0, 6, 7))
         );
-        assert_eq!(parse_err("a: b c: d"), ParseError::FunctionInsideBody);
-        assert_eq!(parse_err("x:"), ParseError::MissingExpression);
-        assert_eq!(parse_err("x: : y"), ParseError::ExtraColon);
+        assert_eq!(parse_err("let A"), ParseError::MalformedLet(span(0, 0, 3)));
+        assert_eq!(parse_err("let A = B"), ParseError::MissingIn(span(0, 0, 7)));
+        assert_eq!(
+            parse_err("(let A = B) A"),
+            ParseError::MissingIn(span(0, 1, 8))
+        );
+        assert_eq!(
+            parse_err("let A = in A"),
+            ParseError::MissingExpression(span(0, 0, 10))
+        );
+        assert_eq!(
+            parse_err("let A = B in"),
+            ParseError::MissingExpression(span(0, 0, 7))
+        );
+        assert_eq!(parse_err("A in B"), ParseError::ExtraIn(span(0, 2, 4)));
+        assert_eq!(
+            parse_err("(let A = B in C in D)"),
+            ParseError::ExtraIn(span(0, 16, 18))
+        );
+        assert_eq!(parse_err("A = B"), ParseError::ExtraEquals(span(0, 2, 3)));
+    }
+
+    #[test]
+    fn node_spans() {
+        let node = parse_ok("x: (x\n  A)");
+        assert_eq!(
+            node.span(),
+            Span::new(Location::new(0, 0), Location::new(1, 3))
+        );
+        if let Node::Function { body, .. } = *node {
+            assert_eq!(
+                body.span(),
+                Span::new(Location::new(0, 4), Location::new(1, 3))
+            );
+            if let Node::Apply { left, right, .. } = *body {
+                assert_eq!(left.span(), span(0, 4, 5));
+                assert_eq!(right.span(), span(1, 2, 3));
+            } else {
+                panic!("Expected application");
+            }
+        } else {
+            panic!("Expected function");
+        }
     }
 }
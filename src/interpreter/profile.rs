@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fmt};
+
+use super::{parser::Node, tokenizer::Constant, TVariable};
+use crate::prelude::*;
+
+// Reductions are attributed to where the function of the redex was written: in the code itself,
+// or in the definition of a constant. Functions are recognized by their variable names, which are
+// shared by every copy of the function, so each expansion of a constant gets new names.
+
+/// Where the function of a redex came from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Origin {
+    /// Written in the interpreted term
+    Code,
+    Constant(Constant),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub reductions: u32,
+    /// Size of the largest term one of the reductions produced
+    pub peak_size:  u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub entries: HashMap<Origin, ProfileEntry>,
+}
+
+impl Profile {
+    /// Adds the reductions of another interpretation, for example another test case
+    pub fn merge(&mut self, other: &Profile) {
+        for (origin, entry) in &other.entries {
+            let cur = self.entries.entry(origin.clone()).or_default();
+            cur.reductions += entry.reductions;
+            cur.peak_size = cur.peak_size.max(entry.peak_size);
+        }
+    }
+
+    /// Entries with the most reductions first
+    pub fn sorted(&self) -> Vec<(&Origin, &ProfileEntry)> {
+        let mut v: Vec<_> = self.entries.iter().collect();
+        v.sort_by(|(o1, e1), (o2, e2)| e2.reductions.cmp(&e1.reductions).then(o1.cmp(o2)));
+        v
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Profiler {
+    /// Constant each function came from, by the address of its variable name. The name is kept
+    /// so the address is not reused.
+    origins: HashMap<usize, (TVariable, Constant)>,
+    profile: Profile,
+}
+
+fn address(variable: &TVariable) -> usize { Arc::as_ptr(variable) as *const u8 as usize }
+
+impl Profiler {
+    /// Gives new variable names to the functions of the definition, which are the same text but
+    /// tell where the function came from
+    pub(super) fn expand(&mut self, name: &Constant, definition: &mut Node) {
+        match definition {
+            Node::Constant(..) | Node::Variable(..) => {},
+            Node::Function { variable, body, .. } => {
+                *variable = TVariable::from(&**variable);
+                self.origins
+                    .insert(address(variable), (variable.clone(), name.clone()));
+                self.expand(name, body);
+            },
+            Node::Apply { left, right, .. } => {
+                self.expand(name, left);
+                self.expand(name, right);
+            },
+        }
+    }
+
+    /// Records the reduction of a function with this variable, which produced a term of this size
+    pub(super) fn reduce(&mut self, variable: &TVariable, size: u32) {
+        let origin = match self.origins.get(&address(variable)) {
+            Some((_, name)) => Origin::Constant(name.clone()),
+            None => Origin::Code,
+        };
+        let entry = self.profile.entries.entry(origin).or_default();
+        entry.reductions += 1;
+        entry.peak_size = entry.peak_size.max(size);
+    }
+
+    pub(super) fn finish(self) -> Profile { self.profile }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Code => write!(f, "(code)"),
+            Origin::Constant(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Table with one line for each origin
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>10} {:>10}",
+            "Function from", "Reductions", "Peak size"
+        )?;
+        for (origin, entry) in self.sorted() {
+            write!(
+                f,
+                "\n{:<16} {:>10} {:>10}",
+                origin.to_string(),
+                entry.reductions,
+                entry.peak_size
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use super::{
+    closures::*, interpreter::{
+        find_loop, interpret, InterpretError, InterpretOptions, Interpreted, Limits, ReductionStrategy, Stats, Usage
+    }, parser::Node, tokenizer::Constant, traversers::drop_node, ConstantProvider, Span
+};
+
+// Call-by-need evaluation. Instead of copying the argument into every occurrence of the variable,
+// like the call-by-name interpreter does, the argument is shared as a thunk that is evaluated at
+// most once. Terms are never copied during evaluation, only when reading back the result.
+//
+// It is the Krivine machine with one more kind of entry in its stack: when a thunk is evaluated,
+// an update is pushed, so the value is saved in the thunk once it is reached.
+
+enum Entry<'a> {
+    /// Argument waiting for the function on the left to be evaluated
+    Argument {
+        thunk: Thunk<'a>,
+        span:  Span,
+        /// Level of the application, the body of the function continues one level deeper
+        level: usize,
+    },
+    /// Thunk being evaluated, with the reductions done before starting
+    Update { thunk: Thunk<'a>, start: u32 },
+}
+
+/// What to do after giving a value to the stack
+enum Next<'a> {
+    Done(Value<'a>),
+    /// Evaluate the body of a function that got its argument, at the given level
+    Body(&'a Node, Env<'a>, usize),
+}
+
+struct SharedInterpreter<'a> {
+    constants:  &'a HashMap<Constant, Box<Node>>,
+    max_depth:  usize,
+    reductions: u32,
+    /// Deepest level reached so far
+    deepest:    usize,
+    /// Largest term call-by-name would have built so far
+    peak_size:  u32,
+    limits:     Limits,
+    stack:      Vec<Entry<'a>>,
+}
+
+impl<'a> SharedInterpreter<'a> {
+    fn add_reductions(&mut self, count: u32) {
+        self.reductions = self.reductions.saturating_add(count);
+    }
+
+    /// Levels grow like the depth of the call-by-name interpreter, one for each application and
+    /// constant, so terms with no reduction fail with TooDeep too. They don't always match, since
+    /// an evaluated thunk doesn't go deeper when it is used again.
+    fn eval(&mut self, root: &'a Node) -> Result<Value<'a>, InterpretError> {
+        let mut term = root;
+        let mut env: Env<'a> = None;
+        let mut level = 0;
+        loop {
+            if level > self.max_depth {
+                return Err(InterpretError::TooDeep);
+            }
+            self.deepest = self.deepest.max(level);
+            self.limits.check(self.reductions)?;
+            let value = match term {
+                Node::Apply { left, right, span } => {
+                    self.stack.push(Entry::Argument {
+                        thunk: argument(right, &env),
+                        span: *span,
+                        level,
+                    });
+                    term = left;
+                    level += 1;
+                    continue;
+                },
+                Node::Constant(c, span) =>
+                    if let Some(def) = self.constants.get(c) {
+                        term = def;
+                        env = None;
+                        level += 1;
+                        continue;
+                    } else {
+                        Value::Stuck {
+                            head: Head::Constant(c, *span),
+                            args: vec![],
+                        }
+                    },
+                Node::Variable(v, span) => match env_get(&env, v.depth) {
+                    Some(thunk) => {
+                        let evaluated = thunk.value.borrow().clone();
+                        match evaluated {
+                            // Count the reductions as if it was evaluated again, so the stats are
+                            // the same as call-by-name.
+                            Some((value, cost)) => {
+                                self.add_reductions(cost);
+                                value
+                            },
+                            None => {
+                                term = thunk.term;
+                                env = thunk.env.clone();
+                                self.stack.push(Entry::Update {
+                                    thunk,
+                                    start: self.reductions,
+                                });
+                                continue;
+                            },
+                        }
+                    },
+                    None => Value::Stuck {
+                        head: Head::Free(&v.original, v.depth - env_len(&env), *span),
+                        args: vec![],
+                    },
+                },
+                Node::Function {
+                    variable,
+                    body,
+                    span,
+                } => Value::Function {
+                    variable,
+                    body,
+                    span: *span,
+                    env: env.clone(),
+                },
+            };
+            match self.apply(value) {
+                Next::Done(value) => return Ok(value),
+                Next::Body(body, body_env, body_level) => {
+                    term = body;
+                    env = body_env;
+                    level = body_level;
+                },
+            }
+        }
+    }
+
+    /// Gives the value to the entries on the stack, until it is empty or a function gets an
+    /// argument
+    fn apply(&mut self, mut value: Value<'a>) -> Next<'a> {
+        while let Some(entry) = self.stack.pop() {
+            match entry {
+                Entry::Update { thunk, start } => {
+                    let cost = self.reductions.saturating_sub(start);
+                    *thunk.value.borrow_mut() = Some((value.clone(), cost));
+                },
+                Entry::Argument { thunk, span, level } => match value {
+                    Value::Function { body, env, .. } => {
+                        self.add_reductions(1);
+                        let env = env_push(env, thunk);
+                        self.peak_size = self.peak_size.max(expanded_size(body, &env));
+                        return Next::Body(body, env, level + 1);
+                    },
+                    Value::Stuck { ref mut args, .. } => args.push((thunk, span)),
+                },
+            }
+        }
+        Next::Done(value)
+    }
+}
+
+/// Same as interpreting with call-by-name, but arguments are shared instead of copied, so it is
+/// much faster and only fails with TooLarge if the result itself is too large. Stats count
+/// reductions as if each argument was evaluated every time it is used, which is what call-by-name
+/// does, and the peak size is of the terms call-by-name would have built. If it goes too
+/// deep, the result is the reference interpreter's, which also finds loops. Only call-by-name is
+/// supported, other strategies use the reference interpreter.
+pub fn interpret_shared<O: Into<InterpretOptions>>(
+    root: Box<Node>,
+    options: O,
+    provider: ConstantProvider,
+) -> Result<Interpreted, InterpretError> {
+    let options = options.into();
+    if options.strategy != ReductionStrategy::CallByName {
+        return interpret(root, options, provider);
+    }
+    let constants = used_constants(&root, &provider);
+    let (result, limits) = {
+        let mut interpreter = SharedInterpreter {
+            constants:  &constants,
+            max_depth:  options.max_depth,
+            reductions: 0,
+            deepest:    0,
+            peak_size:  0,
+            limits:     Limits::new(&options),
+            stack:      vec![],
+        };
+        let result = interpreter.eval(&root).and_then(|value| {
+            let term = Readback::default().value(&value, 0)?;
+            Ok(Interpreted {
+                stats: Stats {
+                    reductions: interpreter.reductions,
+                },
+                usage: Usage {
+                    peak_size: interpreter.peak_size,
+                    max_depth: interpreter.deepest,
+                },
+                term,
+            })
+        });
+        (result, interpreter.limits)
+    };
+    match result {
+        Err(InterpretError::TooDeep) => find_loop(root, options, provider, &limits),
+        result => {
+            drop_node(root);
+            result
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        super::{interpreter::MAX_SIZE, parser::test::parse_ok}, *
+    };
+
+    fn assert_same(code: &str) {
+        let provider = ConstantProvider::all();
+        let expected = interpret(
+            parse_ok(code),
+            ReductionStrategy::CallByName,
+            provider.clone(),
+        )
+        .unwrap();
+        let shared =
+            interpret_shared(parse_ok(code), ReductionStrategy::CallByName, provider).unwrap();
+        assert_eq!(shared.term, expected.term, "{}", code);
+        assert_eq!(shared.stats, expected.stats, "{}", code);
+        assert_eq!(shared.usage.peak_size, expected.usage.peak_size, "{}", code);
+    }
+
+    #[test]
+    fn same_as_call_by_name() {
+        assert_same("z");
+        assert_same("x: y: x z");
+        assert_same("(x: x x) (y: z)");
+        assert_same("(x: y: x) y");
+        assert_same("(x: y: x) y z");
+        assert_same("(f: f f) (x: y: x y)");
+        assert_same("y: (x: y: x y) y");
+        assert_same("(x: z: x x x z) (y: y) A");
+        assert_same("(x: w x x) ((y: y) (z: z))");
+        assert_same("(x: z) ((x: x x) (x: x x))");
+        assert_same("(x: x ((a: a) B)) ((a: a) A)");
+        assert_same("let ID = x: x in ID ID A");
+        assert_same("TRUE A B");
+        assert_same("POP (PUSH A FALSE)");
+        assert_same("(f:a:b: f b a) FALSE A B");
+    }
+
+    #[test]
+    fn infinite() {
+        let error = |code: &str| {
+            interpret_shared(
+                parse_ok(code),
+                ReductionStrategy::CallByName,
+                ConstantProvider::all(),
+            )
+            .unwrap_err()
+        };
+        // Found by the reference interpreter
+        assert_eq!(
+            error("(x: x x) (x: x x)"),
+            InterpretError::Loop {
+                period: 1,
+                term:   parse_ok("(x: x x) (x: x x)"),
+            }
+        );
+        assert_eq!(error("(x: x x A) (x: x x A)"), InterpretError::TooDeep);
+    }
+
+    #[test]
+    fn deep_terms() {
+        // As deep as the size of the terms allows, on a stack much smaller than a main thread's
+        let nests = MAX_SIZE as usize / 2;
+        let code = format!("{}A{}", "(x: x) (".repeat(nests), ")".repeat(nests));
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let interpreted = interpret_shared(
+                    parse_ok(&code),
+                    ReductionStrategy::CallByName,
+                    ConstantProvider::all(),
+                )
+                .unwrap();
+                assert_eq!(interpreted.term, parse_ok("A"));
+                assert_eq!(interpreted.stats.reductions, nests as u32);
+                // Falls back to the reference interpreter on the same deep term
+                let options = InterpretOptions {
+                    max_depth: 100,
+                    ..ReductionStrategy::CallByName.into()
+                };
+                assert_eq!(
+                    interpret_shared(parse_ok(&code), options, ConstantProvider::all())
+                        .unwrap_err(),
+                    InterpretError::TooDeep
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    const NUMERALS: &str = "
+        let PRE = n: f:x: n (g:h: h (g f)) (u: x) (u: u) in
+        let SUB = m: n: n PRE m in
+        let IS = n: n (x: (a: b: b) x x) A in";
+
+    #[test]
+    fn same_on_numerals() {
+        assert_same(&format!("{} IS (PRE (PRE 10))", NUMERALS));
+        assert_same(&format!("{} IS (SUB 12 5)", NUMERALS));
+        assert_same(&format!("{} SUB 4 2", NUMERALS));
+    }
+
+    #[test]
+    fn shares_arguments() {
+        // Copying the numerals around makes them too large
+        let code = format!("{} IS (SUB 60 30)", NUMERALS);
+        assert_eq!(
+            interpret(
+                parse_ok(&code),
+                ReductionStrategy::CallByName,
+                ConstantProvider::all()
+            )
+            .unwrap_err(),
+            InterpretError::TooLarge
+        );
+        assert_eq!(
+            interpret_shared(
+                parse_ok(&code),
+                ReductionStrategy::CallByName,
+                ConstantProvider::all()
+            )
+            .unwrap()
+            .term,
+            parse_ok("A")
+        );
+    }
+}
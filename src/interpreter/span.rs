@@ -0,0 +1,120 @@
+use std::fmt;
+
+/// A position in the source code. Both line and column are 0-indexed, and columns
+/// count characters, not bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub line:   u32,
+    pub column: u32,
+}
+
+impl Location {
+    pub fn new(line: u32, column: u32) -> Self { Self { line, column } }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line + 1, self.column + 1)
+    }
+}
+
+/// Region of the source code some token or term came from. `end` is exclusive.
+/// Terms created during reduction keep the span of the term they were copied from,
+/// and terms not created from any source (like the test case wrapper) have the default span.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Location,
+    pub end:   Location,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Self { Self { start, end } }
+
+    /// Smallest span containing both spans
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end:   self.end.max(other.end),
+        }
+    }
+
+    /// Two lines that can be printed one on top of the other, the first is the line of the source
+    /// where this span starts, the second has carets pointing to the span. Lines are cropped
+    /// so that they have at most `max_width` characters.
+    pub fn caret_diagnostic(&self, source: &str, max_width: usize) -> [String; 2] {
+        let line: Vec<char> = source
+            .split('\n')
+            .nth(self.start.line as usize)
+            .unwrap_or("")
+            .trim_end()
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .collect();
+        let gutter = format!("{} | ", self.start.line + 1);
+        let width = max_width.saturating_sub(gutter.len()).max(1);
+        let start = (self.start.column as usize).min(line.len());
+        let end = if self.end.line == self.start.line {
+            (self.end.column as usize).min(line.len())
+        } else {
+            line.len()
+        }
+        .max(start + 1);
+        // Crop the line so the beginning of the span is visible
+        let offset = if start < width * 2 / 3 {
+            0
+        } else {
+            start - width / 3
+        };
+        let text: String = line.iter().skip(offset).take(width).collect();
+        let carets = (end - start).min(width - (start - offset)).max(1);
+        [
+            format!("{}{}", gutter, text),
+            format!(
+                "{}{}{}",
+                " ".repeat(gutter.len()),
+                " ".repeat(start - offset),
+                "^".repeat(carets)
+            ),
+        ]
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.start.fmt(f) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+
+    fn span(line: u32, start: u32, end: u32) -> Span {
+        Span::new(Location::new(line, start), Location::new(line, end))
+    }
+
+    #[test]
+    fn merge() {
+        assert_eq!(span(0, 2, 4).merge(span(0, 6, 7)), span(0, 2, 7));
+        assert_eq!(
+            span(1, 2, 4).merge(span(0, 6, 7)),
+            Span::new(Location::new(0, 6), Location::new(1, 4))
+        );
+    }
+
+    #[test]
+    fn caret() {
+        assert_eq!(
+            span(0, 3, 5).caret_diagnostic("a (bc d", 80),
+            ["1 | a (bc d".to_string(), "       ^^".to_string()]
+        );
+        assert_eq!(
+            span(1, 1, 2).caret_diagnostic("x:\n\t)", 80),
+            ["2 |  )".to_string(), "     ^".to_string()]
+        );
+        // Long lines are cropped around the span
+        let long = format!("{}(", "a ".repeat(50));
+        let [text, carets] = span(0, 100, 101).caret_diagnostic(&long, 24);
+        assert_le!(text.len(), 24);
+        assert_eq!(text.find('('), carets.find('^'));
+    }
+}
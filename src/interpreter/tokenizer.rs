@@ -1,54 +1,209 @@
+use std::sync::Arc;
+
 use thiserror::Error;
-pub type TVariable = char;
+
+use super::{Location, Span};
+/// Shared so copying terms around doesn't need to allocate the name again
+pub type TVariable = Arc<str>;
 pub type Constant = String;
 
 #[derive(Debug, PartialEq, Eq)]
-pub enum Token {
-    // x, y
+pub enum TokenKind {
+    // x, y, acc
     Variable(TVariable),
-    // hello, goodbye
+    // HELLO, Goodbye, 42
     Constant(Constant),
+    // "abc", without the quotes and with escapes resolved
+    String(String),
     // :
     Colon,
+    // let
+    Let,
+    // =
+    Equals,
+    // in
+    In,
     // (
     OpenPar,
     // )
     ClosePar,
+    // [
+    OpenBracket,
+    // ]
+    CloseBracket,
+    // ,
+    Comma,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
 }
 
 #[derive(Error, Debug)]
 pub enum TokenizeError {
-    #[error("Character '{0}' is unknown")]
-    UnknownCharacter(char),
+    #[error("Character '{0}' at {1} is unknown")]
+    UnknownCharacter(char, Span),
+    #[error("Comment started at {0} is never closed with */")]
+    UnclosedComment(Span),
+    #[error("String started at {0} is never closed with \"")]
+    UnclosedString(Span),
+}
+
+impl TokenizeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TokenizeError::UnknownCharacter(_, span) => *span,
+            TokenizeError::UnclosedComment(span) => *span,
+            TokenizeError::UnclosedString(span) => *span,
+        }
+    }
+}
+
+enum Comment {
+    // From # to the end of the line
+    Line,
+    // Between /* and */, with the span of the opening /*
+    Block(Span),
+}
+
+/// String being read, with the span of the opening quote
+struct OpenString {
+    text: String,
+    span: Span,
+}
+
+fn next_location(loc: Location, c: char) -> Location {
+    if c == '\n' {
+        Location::new(loc.line + 1, 0)
+    } else {
+        Location::new(loc.line, loc.column + 1)
+    }
+}
+
+/// Variables start with a lower case letter, and may be followed by more lower case letters or
+/// digits. Every other word is a constant.
+fn is_variable_name(word: &[char]) -> bool {
+    word.first().is_some_and(|c| c.is_ascii_lowercase())
+        && word
+            .iter()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+}
+
+fn keyword(word: &[char]) -> Option<TokenKind> {
+    match word {
+        ['l', 'e', 't'] => Some(TokenKind::Let),
+        ['i', 'n'] => Some(TokenKind::In),
+        _ => None,
+    }
 }
 
 fn tokenize_vec<S: IntoIterator<Item = char>>(str: S) -> Result<Vec<Token>, TokenizeError> {
     let mut ans = vec![];
+    let mut comment = None;
+    let mut string: Option<OpenString> = None;
     let mut cur_word: Vec<char> = vec![];
-    let flush = |cur_word: &mut Vec<char>, ans: &mut Vec<Token>| {
-        if cur_word.len() == 1 && cur_word[0].is_ascii_lowercase() {
-            ans.push(Token::Variable(cur_word[0]));
+    let mut cur_word_start = Location::default();
+    let mut loc = Location::default();
+    let flush = |cur_word: &mut Vec<char>, ans: &mut Vec<Token>, start: Location, end: Location| {
+        let span = Span::new(start, end);
+        if let Some(keyword) = keyword(cur_word) {
+            ans.push(Token {
+                kind: keyword,
+                span,
+            });
+        } else if is_variable_name(cur_word) {
+            ans.push(Token {
+                kind: TokenKind::Variable(cur_word.iter().collect::<String>().into()),
+                span,
+            });
         } else if !cur_word.is_empty() {
-            ans.push(Token::Constant(cur_word.iter().collect()));
+            ans.push(Token {
+                kind: TokenKind::Constant(cur_word.iter().collect()),
+                span,
+            });
         }
         cur_word.clear();
     };
-    for c in str.into_iter() {
-        if c.is_ascii_alphanumeric() {
+    let mut iter = str.into_iter().peekable();
+    while let Some(c) = iter.next() {
+        let mut next_loc = next_location(loc, c);
+        if let Some(open) = &mut string {
+            match c {
+                '"' => {
+                    ans.push(Token {
+                        kind: TokenKind::String(std::mem::take(&mut open.text)),
+                        span: open.span.merge(Span::new(loc, next_loc)),
+                    });
+                    string = None;
+                },
+                // Backslash escapes the next character, so strings can have quotes
+                '\\' =>
+                    if let Some(escaped) = iter.next() {
+                        next_loc = next_location(next_loc, escaped);
+                        open.text.push(escaped);
+                    },
+                _ => open.text.push(c),
+            }
+        } else if let Some(Comment::Line) = comment {
+            if c == '\n' {
+                comment = None;
+            }
+        } else if let Some(Comment::Block(_)) = comment {
+            if c == '*' && iter.peek() == Some(&'/') {
+                next_loc = next_location(next_loc, iter.next().unwrap());
+                comment = None;
+            }
+        } else if c.is_ascii_alphanumeric() {
+            if cur_word.is_empty() {
+                cur_word_start = loc;
+            }
             cur_word.push(c);
         } else {
-            flush(&mut cur_word, &mut ans);
+            flush(&mut cur_word, &mut ans, cur_word_start, loc);
             if !c.is_whitespace() {
-                match c {
-                    ':' => ans.push(Token::Colon),
-                    '(' => ans.push(Token::OpenPar),
-                    ')' => ans.push(Token::ClosePar),
-                    _ => return Err(TokenizeError::UnknownCharacter(c)),
+                let span = Span::new(loc, next_loc);
+                let kind = match c {
+                    ':' => Some(TokenKind::Colon),
+                    '=' => Some(TokenKind::Equals),
+                    '(' => Some(TokenKind::OpenPar),
+                    ')' => Some(TokenKind::ClosePar),
+                    '[' => Some(TokenKind::OpenBracket),
+                    ']' => Some(TokenKind::CloseBracket),
+                    ',' => Some(TokenKind::Comma),
+                    '"' => {
+                        string = Some(OpenString {
+                            text: String::new(),
+                            span,
+                        });
+                        None
+                    },
+                    '#' => {
+                        comment = Some(Comment::Line);
+                        None
+                    },
+                    '/' if iter.peek() == Some(&'*') => {
+                        next_loc = next_location(next_loc, iter.next().unwrap());
+                        comment = Some(Comment::Block(Span::new(loc, next_loc)));
+                        None
+                    },
+                    _ => return Err(TokenizeError::UnknownCharacter(c, span)),
+                };
+                if let Some(kind) = kind {
+                    ans.push(Token { kind, span });
                 }
             }
         }
+        loc = next_loc;
+    }
+    if let Some(Comment::Block(span)) = comment {
+        return Err(TokenizeError::UnclosedComment(span));
     }
-    flush(&mut cur_word, &mut ans);
+    if let Some(open) = string {
+        return Err(TokenizeError::UnclosedString(open.span));
+    }
+    flush(&mut cur_word, &mut ans, cur_word_start, loc);
     Ok(ans)
 }
 
@@ -63,20 +218,150 @@ pub fn tokenize<S: IntoIterator<Item = char>>(
 mod test {
     use super::*;
 
+    fn kinds(str: &str) -> Vec<TokenKind> {
+        tokenize(str.chars()).unwrap().map(|t| t.kind).collect()
+    }
+
+    fn spans(str: &str) -> Vec<(u32, u32, u32)> {
+        tokenize(str.chars())
+            .unwrap()
+            .map(|t| {
+                assert_eq!(t.span.start.line, t.span.end.line);
+                (t.span.start.line, t.span.start.column, t.span.end.column)
+            })
+            .collect()
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(
-            tokenize("a: asd()B".chars())
-                .unwrap()
-                .collect::<Vec<Token>>(),
+            kinds("a: ASD()B"),
             vec![
-                Token::Variable('a'),
-                Token::Colon,
-                Token::Constant("asd".to_string()),
-                Token::OpenPar,
-                Token::ClosePar,
-                Token::Constant("B".to_string()),
+                TokenKind::Variable("a".into()),
+                TokenKind::Colon,
+                TokenKind::Constant("ASD".to_string()),
+                TokenKind::OpenPar,
+                TokenKind::ClosePar,
+                TokenKind::Constant("B".to_string()),
             ]
         );
     }
+
+    #[test]
+    fn variable_names() {
+        assert_eq!(
+            kinds("acc x1 left2right Acc aB 2x X"),
+            vec![
+                TokenKind::Variable("acc".into()),
+                TokenKind::Variable("x1".into()),
+                TokenKind::Variable("left2right".into()),
+                TokenKind::Constant("Acc".to_string()),
+                TokenKind::Constant("aB".to_string()),
+                TokenKind::Constant("2x".to_string()),
+                TokenKind::Constant("X".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            kinds("let ID=x: x in letter inn LET"),
+            vec![
+                TokenKind::Let,
+                TokenKind::Constant("ID".to_string()),
+                TokenKind::Equals,
+                TokenKind::Variable("x".into()),
+                TokenKind::Colon,
+                TokenKind::Variable("x".into()),
+                TokenKind::In,
+                TokenKind::Variable("letter".into()),
+                TokenKind::Variable("inn".into()),
+                TokenKind::Constant("LET".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_spans() {
+        assert_eq!(
+            spans("a: asd()B"),
+            vec![
+                (0, 0, 1),
+                (0, 1, 2),
+                (0, 3, 6),
+                (0, 6, 7),
+                (0, 7, 8),
+                (0, 8, 9)
+            ]
+        );
+        assert_eq!(
+            spans("f:\n  (x\n)"),
+            vec![(0, 0, 1), (0, 1, 2), (1, 2, 3), (1, 3, 4), (2, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn unknown_character() {
+        let err = tokenize("x:\n x + y".chars()).err().unwrap();
+        assert!(matches!(err, TokenizeError::UnknownCharacter('+', _)));
+        assert_eq!(
+            err.span(),
+            Span::new(Location::new(1, 3), Location::new(1, 4))
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(kinds("x: x # and (some: weird) stuff"), kinds("x: x"));
+        assert_eq!(
+            kinds("f:# first line\n  x: # f applied to\n f/*twice*/(f x) # done"),
+            kinds("f: x: f (f x)")
+        );
+        assert_eq!(kinds("a /* multi\n line: # ( */ b"), kinds("a b"));
+        assert_eq!(kinds("a/**/b"), kinds("a b"));
+        assert_eq!(spans("/* x */ y"), vec![(0, 8, 9)]);
+        assert_eq!(spans("# x\ny"), vec![(1, 0, 1)]);
+        let err = tokenize("x: /* x\n x".chars()).err().unwrap();
+        assert!(matches!(err, TokenizeError::UnclosedComment(_)));
+        assert_eq!(
+            err.span(),
+            Span::new(Location::new(0, 3), Location::new(0, 5))
+        );
+        // Division is not a comment
+        assert!(tokenize("a / b".chars()).is_err());
+    }
+
+    #[test]
+    fn lists_and_strings() {
+        assert_eq!(
+            kinds("[A, x]"),
+            vec![
+                TokenKind::OpenBracket,
+                TokenKind::Constant("A".to_string()),
+                TokenKind::Comma,
+                TokenKind::Variable("x".into()),
+                TokenKind::CloseBracket,
+            ]
+        );
+        assert_eq!(
+            kinds(r#"f "a: b # c" "" "say \"hi\" \\""#),
+            vec![
+                TokenKind::Variable("f".into()),
+                TokenKind::String("a: b # c".to_string()),
+                TokenKind::String(String::new()),
+                TokenKind::String(r#"say "hi" \"#.to_string()),
+            ]
+        );
+        assert_eq!(
+            spans("x \"ab\"\n\"\""),
+            vec![(0, 0, 1), (0, 2, 6), (1, 0, 2)]
+        );
+        let err = tokenize("x \"ab".chars()).err().unwrap();
+        assert!(matches!(err, TokenizeError::UnclosedString(_)));
+        assert_eq!(
+            err.span(),
+            Span::new(Location::new(0, 2), Location::new(0, 3))
+        );
+    }
 }
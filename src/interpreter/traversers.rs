@@ -1,20 +1,153 @@
-use super::{tokenizer::Constant, Node};
-
-fn all_constants_rec<'a>(root: &'a Node, vec: &mut Vec<&'a Constant>) {
-    match root {
-        Node::Constant(c) => vec.push(c),
-        Node::Variable(_) => {},
-        Node::Function { variable: _, body } => all_constants_rec(&body, vec),
-        Node::Apply { left, right } => {
-            all_constants_rec(&left, vec);
-            all_constants_rec(&right, vec);
-        },
+use super::{
+    parser::Variable, tokenizer::{Constant, TVariable}, Node, Span
+};
+
+// Terms can be as deep as the interpreters go, so nothing here recurses on the native stack.
+
+/// Direction from a term to one of its direct subterms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStep {
+    /// Left side of an application
+    Left,
+    /// Right side of an application
+    Right,
+    /// Body of a function
+    Body,
+}
+
+/// Iterator of all subterms, parents before children and left before right, with how many
+/// functions are above each one
+pub struct Subterms<'a> {
+    stack: Vec<(&'a Node, usize)>,
+}
+
+impl<'a> Iterator for Subterms<'a> {
+    type Item = (&'a Node, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, depth) = self.stack.pop()?;
+        match node {
+            Node::Constant(..) | Node::Variable(..) => {},
+            Node::Function { body, .. } => self.stack.push((body, depth + 1)),
+            Node::Apply { left, right, .. } => {
+                self.stack.push((right, depth));
+                self.stack.push((left, depth));
+            },
+        }
+        Some((node, depth))
     }
 }
 
-pub fn all_constants<'a>(root: &'a Node) -> impl Iterator<Item = &'a Constant> {
-    // Can be improved with generators if needed
-    let mut v = Vec::new();
-    all_constants_rec(&root, &mut v);
-    v.into_iter()
+/// Subterms of a term that is inside `cur_depth` functions
+pub fn subterms(root: &Node, cur_depth: usize) -> Subterms<'_> {
+    Subterms {
+        stack: vec![(root, cur_depth)],
+    }
+}
+
+/// Node whose subterms were already folded
+pub enum Folded<'a, T> {
+    Constant(&'a Constant, Span),
+    Variable(&'a Variable, Span),
+    Function(&'a TVariable, T, Span),
+    Apply(T, T, Span),
+}
+
+enum Visit<'a> {
+    Enter(&'a Node, usize),
+    /// The subterms are done
+    Exit(&'a Node, usize),
+}
+
+/// Folds the term from the leaves up, calling f with each node and how many functions are above
+/// it, counting from cur_depth
+pub fn fold<'a, T, F: FnMut(Folded<'a, T>, usize) -> T>(
+    root: &'a Node,
+    cur_depth: usize,
+    mut f: F,
+) -> T {
+    let mut visits = vec![Visit::Enter(root, cur_depth)];
+    let mut done: Vec<T> = vec![];
+    while let Some(visit) = visits.pop() {
+        match visit {
+            Visit::Enter(node, depth) => match node {
+                Node::Constant(c, span) => done.push(f(Folded::Constant(c, *span), depth)),
+                Node::Variable(v, span) => done.push(f(Folded::Variable(v, *span), depth)),
+                Node::Function { body, .. } => {
+                    visits.push(Visit::Exit(node, depth));
+                    visits.push(Visit::Enter(body, depth + 1));
+                },
+                Node::Apply { left, right, .. } => {
+                    visits.push(Visit::Exit(node, depth));
+                    visits.push(Visit::Enter(right, depth));
+                    visits.push(Visit::Enter(left, depth));
+                },
+            },
+            Visit::Exit(node, depth) => {
+                let folded = match node {
+                    Node::Function { variable, span, .. } =>
+                        Folded::Function(variable, done.pop().unwrap(), *span),
+                    Node::Apply { span, .. } => {
+                        let right = done.pop().unwrap();
+                        Folded::Apply(done.pop().unwrap(), right, *span)
+                    },
+                    Node::Constant(..) | Node::Variable(..) => unreachable!("Leaves don't exit"),
+                };
+                done.push(f(folded, depth));
+            },
+        }
+    }
+    done.pop().unwrap()
+}
+
+/// Calls f with every variable in the term, and how many functions are above it, counting from
+/// cur_depth
+pub fn for_each_variable<F: FnMut(&mut Variable, usize)>(
+    root: &mut Node,
+    cur_depth: usize,
+    mut f: F,
+) {
+    let mut stack = vec![(root, cur_depth)];
+    while let Some((node, depth)) = stack.pop() {
+        match node {
+            Node::Constant(..) => {},
+            Node::Variable(v, _) => f(v, depth),
+            Node::Function { body, .. } => stack.push((body, depth + 1)),
+            Node::Apply { left, right, .. } => {
+                stack.push((right, depth));
+                stack.push((left, depth));
+            },
+        }
+    }
+}
+
+/// Drops the term without recursing, which the drop of a deep Box<Node> would do
+pub fn drop_node(root: Box<Node>) {
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        match *node {
+            Node::Constant(..) | Node::Variable(..) => {},
+            Node::Function { body, .. } => stack.push(body),
+            Node::Apply { left, right, .. } => {
+                stack.push(left);
+                stack.push(right);
+            },
+        }
+    }
+}
+
+/// All constants in the term, together with where they are in the source
+pub fn all_constants(root: &Node) -> impl Iterator<Item = (&Constant, Span)> {
+    subterms(root, 0).filter_map(|(node, _)| match node {
+        Node::Constant(c, span) => Some((c, *span)),
+        _ => None,
+    })
+}
+
+/// Where the first function of the term is, if it has any
+pub fn first_function(root: &Node) -> Option<Span> {
+    subterms(root, 0).find_map(|(node, _)| match node {
+        Node::Function { span, .. } => Some(*span),
+        _ => None,
+    })
 }
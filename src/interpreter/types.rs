@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashMap, HashSet}, fmt
+};
+
+use thiserror::Error;
+
+use super::{
+    tokenizer::{Constant, TVariable}, ConstantProvider, Node, Span
+};
+
+// Hindley-Milner type inference with only function types. Terms like (x: x x) or the Y combinator
+// have no type, since they would need an infinite one.
+//
+// Applying a function directly, like in (x: B) A, is typed as "let x = A in B", so x can be used
+// with different types in B. Lets are parsed that way, so they are polymorphic like in ML.
+// Constants defined by the level are typed from their definitions, and may also be used with
+// different types. Unknown constants, like A, have some unknown type, the same on every use.
+
+/// Simple type, with type variables for the unknown parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Var(usize),
+    Function(Box<Type>, Box<Type>),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    #[error("Term at {0} has no type, it would need an infinite one")]
+    Infinite(Span),
+    #[error("Constant {0} at {1} has no type")]
+    Constant(Constant, Span),
+}
+
+impl TypeError {
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::Infinite(span) | TypeError::Constant(_, span) => *span,
+        }
+    }
+}
+
+/// Type that may be used with any type in place of the generic variables
+#[derive(Debug, Clone)]
+struct Scheme {
+    generic: Vec<usize>,
+    ty:      Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Self {
+        Self {
+            generic: vec![],
+            ty,
+        }
+    }
+}
+
+fn function(arg: Type, result: Type) -> Type { Type::Function(Box::new(arg), Box::new(result)) }
+
+struct Inferer<'a> {
+    /// What each type variable was unified with, if anything
+    bindings:  Vec<Option<Type>>,
+    provider:  &'a ConstantProvider,
+    /// Types of the constants with definitions, all of their variables are generic. None if the
+    /// definition has no type.
+    constants: &'a mut HashMap<Constant, Option<Scheme>>,
+    /// Types of the unbound variables, by name and how many functions above the root they are
+    unbound:   HashMap<(TVariable, usize), Type>,
+    /// Types of the constants without definitions
+    unknown:   HashMap<Constant, Type>,
+}
+
+impl<'a> Inferer<'a> {
+    fn new(
+        provider: &'a ConstantProvider,
+        constants: &'a mut HashMap<Constant, Option<Scheme>>,
+    ) -> Self {
+        Self {
+            bindings: vec![],
+            provider,
+            constants,
+            unbound: HashMap::new(),
+            unknown: HashMap::new(),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    /// Replaces all bound variables, so the type only has unbound ones
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(v) => match &self.bindings[*v] {
+                Some(bound) => self.resolve(bound),
+                None => ty.clone(),
+            },
+            Type::Function(arg, result) => function(self.resolve(arg), self.resolve(result)),
+        }
+    }
+
+    fn unify(&mut self, left: &Type, right: &Type, span: Span) -> Result<(), TypeError> {
+        match (self.resolve(left), self.resolve(right)) {
+            (Type::Var(l), Type::Var(r)) if l == r => Ok(()),
+            (Type::Var(v), ty) | (ty, Type::Var(v)) =>
+                if free_vars(&ty).contains(&v) {
+                    Err(TypeError::Infinite(span))
+                } else {
+                    self.bindings[v] = Some(ty);
+                    Ok(())
+                },
+            (Type::Function(arg1, result1), Type::Function(arg2, result2)) => {
+                self.unify(&arg1, &arg2, span)?;
+                self.unify(&result1, &result2, span)
+            },
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: HashMap<usize, Type> =
+            scheme.generic.iter().map(|v| (*v, self.fresh())).collect();
+        substitute(&scheme.ty, &fresh)
+    }
+
+    /// Generic variables are the ones that are not used by the types in the environment, or by the
+    /// types of unknown constants and unbound variables, which are the same on every use
+    fn generalize(&self, ty: &Type, env: &[Scheme]) -> Scheme {
+        let ty = self.resolve(ty);
+        let used: HashSet<usize> = env
+            .iter()
+            .flat_map(|s| {
+                let generic = &s.generic;
+                free_vars(&self.resolve(&s.ty))
+                    .into_iter()
+                    .filter(move |v| !generic.contains(v))
+            })
+            .chain(
+                self.unknown
+                    .values()
+                    .chain(self.unbound.values())
+                    .flat_map(|t| free_vars(&self.resolve(t))),
+            )
+            .collect();
+        let mut generic = free_vars(&ty);
+        generic.retain(|v| !used.contains(v));
+        Scheme { generic, ty }
+    }
+
+    fn constant(&mut self, c: &Constant, span: Span) -> Result<Type, TypeError> {
+        let scheme = match self.constants.get(c) {
+            Some(scheme) => scheme.clone(),
+            None => match self.provider.get(c) {
+                Some(definition) => {
+                    let provider = self.provider;
+                    let mut inferer = Inferer::new(provider, self.constants);
+                    let scheme = inferer
+                        .infer(&definition, &mut vec![])
+                        .ok()
+                        .map(|ty| inferer.generalize(&ty, &[]));
+                    self.constants.insert(c.clone(), scheme.clone());
+                    scheme
+                },
+                None => {
+                    let fresh = self.fresh();
+                    return Ok(self.unknown.entry(c.clone()).or_insert(fresh).clone());
+                },
+            },
+        };
+        match scheme {
+            Some(scheme) => Ok(self.instantiate(&scheme)),
+            None => Err(TypeError::Constant(c.clone(), span)),
+        }
+    }
+
+    fn infer(&mut self, node: &Node, env: &mut Vec<Scheme>) -> Result<Type, TypeError> {
+        match node {
+            Node::Constant(c, span) => self.constant(c, *span),
+            Node::Variable(v, _) =>
+                if v.depth < env.len() {
+                    let scheme = env[env.len() - 1 - v.depth].clone();
+                    Ok(self.instantiate(&scheme))
+                } else {
+                    let key = (v.original.clone(), v.depth - env.len());
+                    let fresh = self.fresh();
+                    Ok(self.unbound.entry(key).or_insert(fresh).clone())
+                },
+            Node::Function { body, .. } => {
+                let arg = self.fresh();
+                env.push(Scheme::mono(arg.clone()));
+                let result = self.infer(body, env);
+                env.pop();
+                Ok(function(arg, result?))
+            },
+            Node::Apply { left, right, span } => match &**left {
+                // Typed as a let, so the argument can be used with different types
+                Node::Function { body, .. } => {
+                    let arg = self.infer(right, env)?;
+                    let scheme = self.generalize(&arg, env);
+                    env.push(scheme);
+                    let result = self.infer(body, env);
+                    env.pop();
+                    result
+                },
+                _ => {
+                    let left = self.infer(left, env)?;
+                    let right = self.infer(right, env)?;
+                    let result = self.fresh();
+                    self.unify(&left, &function(right, result.clone()), *span)?;
+                    Ok(result)
+                },
+            },
+        }
+    }
+}
+
+fn free_vars(ty: &Type) -> Vec<usize> {
+    fn rec(ty: &Type, ans: &mut Vec<usize>) {
+        match ty {
+            Type::Var(v) =>
+                if !ans.contains(v) {
+                    ans.push(*v);
+                },
+            Type::Function(arg, result) => {
+                rec(arg, ans);
+                rec(result, ans);
+            },
+        }
+    }
+    let mut ans = vec![];
+    rec(ty, &mut ans);
+    ans
+}
+
+fn substitute(ty: &Type, map: &HashMap<usize, Type>) -> Type {
+    match ty {
+        Type::Var(v) => map.get(v).cloned().unwrap_or_else(|| ty.clone()),
+        Type::Function(arg, result) => function(substitute(arg, map), substitute(result, map)),
+    }
+}
+
+/// Most general type of the term, with the variables numbered in order of appearance
+pub fn infer_type(node: &Node, provider: &ConstantProvider) -> Result<Type, TypeError> {
+    let mut constants = HashMap::new();
+    let mut inferer = Inferer::new(provider, &mut constants);
+    let ty = inferer.infer(node, &mut vec![])?;
+    let ty = inferer.resolve(&ty);
+    let renamed = free_vars(&ty)
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (v, Type::Var(i)))
+        .collect();
+    Ok(substitute(&ty, &renamed))
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Var(v) if *v < 26 => write!(f, "{}", (b'a' + *v as u8) as char),
+            Type::Var(v) => write!(f, "t{}", v),
+            Type::Function(arg, result) => match **arg {
+                Type::Function(..) => write!(f, "({}) -> {}", arg, result),
+                Type::Var(_) => write!(f, "{} -> {}", arg, result),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        super::{parser::test::parse_ok, Location}, *
+    };
+
+    fn type_of(code: &str) -> String {
+        infer_type(&parse_ok(code), &ConstantProvider::all())
+            .unwrap()
+            .to_string()
+    }
+
+    fn type_err(code: &str) -> TypeError {
+        infer_type(&parse_ok(code), &ConstantProvider::all()).unwrap_err()
+    }
+
+    #[test]
+    fn simple_types() {
+        assert_eq!(type_of("x: x"), "a -> a");
+        assert_eq!(type_of("x: y: x"), "a -> b -> a");
+        assert_eq!(type_of("f: x: f x"), "(a -> b) -> a -> b");
+        assert_eq!(
+            type_of("x: y: z: x z (y z)"),
+            "(a -> b -> c) -> (a -> b) -> a -> c"
+        );
+        assert_eq!(
+            type_of("f: g: x: f (g x)"),
+            "(a -> b) -> (c -> a) -> c -> b"
+        );
+        // Unknown constants and unbound variables have the same type in every use
+        assert_eq!(type_of("f: f A A"), "(a -> a -> b) -> b");
+        assert_eq!(type_of("f: f A B"), "(a -> b -> c) -> c");
+        assert_eq!(type_of("x y"), "a");
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(type_of("TRUE"), "a -> b -> a");
+        assert_eq!(type_of("FALSE"), "a -> b -> b");
+        assert_eq!(type_of("2"), "(a -> a) -> a -> a");
+        assert_eq!(type_of("PAIR"), "a -> b -> (a -> b -> c) -> c");
+        // Each use of a constant may have a different type
+        assert_eq!(type_of("PAIR TRUE 2"), type_of("x: x TRUE 2"));
+        assert_eq!(type_of("x: x"), type_of("let ID = x: x in ID ID"));
+    }
+
+    #[test]
+    fn untypeable() {
+        assert_eq!(
+            type_err("x: x x"),
+            TypeError::Infinite(Span::new(Location::new(0, 3), Location::new(0, 6)))
+        );
+        assert!(matches!(
+            type_err("f: (x: f (x x)) (x: f (x x))"),
+            TypeError::Infinite(_)
+        ));
+        assert!(matches!(type_err("Y"), TypeError::Constant(c, _) if c == "Y"));
+        // Arguments of functions are not polymorphic
+        assert!(matches!(type_err("f: f f"), TypeError::Infinite(_)));
+        // Neither are unknown constants, even when applied directly, so the type is kept by the
+        // reduction
+        assert!(matches!(
+            type_err("f: PAIR (f (A TRUE)) (f (A 2))"),
+            TypeError::Infinite(_)
+        ));
+        assert!(matches!(
+            type_err("(x: f: PAIR (f (x TRUE)) (f (x 2))) A"),
+            TypeError::Infinite(_)
+        ));
+        assert!(matches!(
+            type_err("(x: f: PAIR (f (x TRUE)) (f (x 2))) y"),
+            TypeError::Infinite(_)
+        ));
+    }
+}
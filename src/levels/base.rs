@@ -1,21 +1,249 @@
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::HashMap, convert::TryFrom, fmt, time::{Duration, Instant}
+};
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::SectionName;
 use crate::{
     interpreter::{
-        accumulate_stats, count_functions, interpret, parse, tokenize, traversers::all_constants, ConstantProvider, InterpretError, Interpreted, Node, ParseError, TokenizeError
+        accumulate_stats, count_functions, eta_reduce, first_difference, interpret, interpret_krivine, interpret_shared, parse, tokenize, traversers::{all_constants, first_function}, Budget, CancelToken, ConstantProvider, Difference, InterpretError, InterpretOptions, Interpreted, Node, Numerals, ParseError, ReductionStrategy, Span, Term, TokenizeError
     }, prelude::*, save_system::LevelResult
 };
 
+/// How the result of a test case is compared to the expected result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Equivalence {
+    /// Results must be the same term, up to the names of the variables
+    #[default]
+    Structural,
+    /// Results must have the same normal form, so reductions inside functions don't matter
+    BetaNormal,
+    /// Like BetaNormal, but (x: f x) is also the same as f
+    BetaEta,
+}
+
+impl Equivalence {
+    /// The form in which the term is compared
+    fn canonical(
+        self,
+        term: Box<Node>,
+        options: InterpretOptions,
+        provider: &ConstantProvider,
+    ) -> Result<Term, InterpretError> {
+        let normal = |term| {
+            interpret(
+                term,
+                InterpretOptions {
+                    strategy: ReductionStrategy::Normal,
+                    ..options
+                },
+                provider.clone(),
+            )
+            .map(|i| i.term)
+        };
+        let term = match self {
+            Equivalence::Structural => term,
+            Equivalence::BetaNormal => normal(term)?,
+            Equivalence::BetaEta => eta_reduce(normal(term)?),
+        };
+        Ok(Term::from(term.as_ref()))
+    }
+}
+
+/// What a test case expects, as written in the level configs
+#[derive(Debug, Clone)]
+pub enum ExpectedConfig {
+    Result(String),
+    /// Any one of the results is accepted
+    AnyOf(Vec1<String>),
+    /// Term that receives the result and must reduce to TRUE
+    Predicate(String),
+}
+
+/// Test case as written in the level configs. Either `[application, result]`, or an object
+/// with the application and exactly one of `result`, `any_of` or `predicate`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "RawTestCaseConfig", into = "RawTestCaseConfig")]
+pub struct TestCaseConfig {
+    pub application:   String,
+    pub expected:      ExpectedConfig,
+    /// Not used by predicates
+    pub equivalence:   Equivalence,
+    /// Results known to be wrong, each with the feedback shown to players whose code gives it
+    pub wrong_results: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct FullTestCaseConfig {
+    application:   String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result:        Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    any_of:        Option<Vec1<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    predicate:     Option<String>,
+    #[serde(default)]
+    equivalence:   Equivalence,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    wrong_results: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawTestCaseConfig {
+    Pair(String, String),
+    Full(FullTestCaseConfig),
+}
+
+impl TryFrom<RawTestCaseConfig> for TestCaseConfig {
+    type Error = String;
+
+    fn try_from(raw: RawTestCaseConfig) -> Result<Self, String> {
+        Ok(match raw {
+            RawTestCaseConfig::Pair(application, result) => Self {
+                application,
+                expected: ExpectedConfig::Result(result),
+                equivalence: Equivalence::Structural,
+                wrong_results: vec![],
+            },
+            RawTestCaseConfig::Full(config) => Self {
+                expected: match (config.result, config.any_of, config.predicate) {
+                    (Some(result), None, None) => ExpectedConfig::Result(result),
+                    (None, Some(any_of), None) => ExpectedConfig::AnyOf(any_of),
+                    (None, None, Some(predicate)) => ExpectedConfig::Predicate(predicate),
+                    _ =>
+                        return Err(format!(
+                            "Test case with application '{}' must have exactly one of result, any_of or predicate",
+                            config.application
+                        )),
+                },
+                application: config.application,
+                equivalence: config.equivalence,
+                wrong_results: config.wrong_results,
+            },
+        })
+    }
+}
+
+impl From<TestCaseConfig> for RawTestCaseConfig {
+    fn from(config: TestCaseConfig) -> Self {
+        let mut full = FullTestCaseConfig {
+            application:   config.application,
+            result:        None,
+            any_of:        None,
+            predicate:     None,
+            equivalence:   config.equivalence,
+            wrong_results: config.wrong_results,
+        };
+        match config.expected {
+            ExpectedConfig::Result(result)
+                if config.equivalence == Equivalence::Structural
+                    && full.wrong_results.is_empty() =>
+                return RawTestCaseConfig::Pair(full.application, result),
+            ExpectedConfig::Result(result) => full.result = Some(result),
+            ExpectedConfig::AnyOf(any_of) => full.any_of = Some(any_of),
+            ExpectedConfig::Predicate(predicate) => full.predicate = Some(predicate),
+        }
+        RawTestCaseConfig::Full(full)
+    }
+}
+
+/// Code that must fail some test case, as written in the level configs. Either just the code, or
+/// an object with the code and the feedback shown to players whose code fails the same way.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "RawWrongSolution", into = "RawWrongSolution")]
+pub struct WrongSolution {
+    pub code:     String,
+    pub feedback: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawWrongSolution {
+    Code(String),
+    Full {
+        code:     String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        feedback: Option<String>,
+    },
+}
+
+impl From<RawWrongSolution> for WrongSolution {
+    fn from(raw: RawWrongSolution) -> Self {
+        match raw {
+            RawWrongSolution::Code(code) => Self {
+                code,
+                feedback: None,
+            },
+            RawWrongSolution::Full { code, feedback } => Self { code, feedback },
+        }
+    }
+}
+
+impl From<WrongSolution> for RawWrongSolution {
+    fn from(wrong: WrongSolution) -> Self {
+        match wrong.feedback {
+            None => RawWrongSolution::Code(wrong.code),
+            feedback => RawWrongSolution::Full {
+                code: wrong.code,
+                feedback,
+            },
+        }
+    }
+}
+
+/// What the result of a test case must be
+#[derive(Debug, Clone)]
+pub enum Expected {
+    /// Must be equivalent to one of these
+    AnyOf(Vec1<Box<Node>>),
+    /// Applied to the result, must reduce to TRUE
+    Predicate(Box<Node>),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::AnyOf(results) => {
+                for (i, result) in results.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " or ")?;
+                    }
+                    write!(f, "{}", result)?;
+                }
+                Ok(())
+            },
+            Expected::Predicate(predicate) => write!(f, "anything accepted by {}", predicate),
+        }
+    }
+}
+
+/// Constants the predicate result is applied to, it was TRUE if it reduces to the first one
+const ACCEPTED: &str = "ACCEPTED";
+const REJECTED: &str = "REJECTED";
+
+fn apply(left: Box<Node>, right: Box<Node>) -> Box<Node> {
+    Box::new(Node::Apply {
+        left,
+        right,
+        span: Span::default(),
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct TestCase {
     /// Must be a function that receives the code and returns the result.
     pub application: Box<Node>,
-    /// Result of the application
-    /// TODO: Maybe we need more complex checking?
-    expected_result: Box<Node>,
+    expected:        Expected,
+    /// Canonical forms of the expected results, for fast comparisons. Empty for predicates.
+    expected_terms:  Vec<Term>,
+    equivalence:     Equivalence,
+    /// Canonical forms of the known wrong results, with their feedback
+    wrong_results:   Vec<(Term, String)>,
 }
 
 // Every level has these fields, game levels and user created ones
@@ -26,18 +254,35 @@ pub struct BaseLevel {
     pub extra_info:         Option<String>,
     pub extra_info_is_hint: bool,
     pub test_cases:         Vec1<TestCase>,
+    /// Limits for running each test case
+    pub budget:             Budget,
+    /// Encoding of the number literals, in solutions and test cases
+    pub numerals:           Numerals,
+    /// If false, solutions can't have functions and must be built only from constants
+    pub allow_functions:    bool,
+    /// Code that must fail, the ones with feedback are also compared with the player's runs
+    pub wrong_solutions:    Vec<WrongSolution>,
+}
+
+impl BaseLevel {
+    pub fn interpret_options(&self, cancel: CancelToken) -> InterpretOptions {
+        InterpretOptions {
+            budget: self.budget,
+            cancel,
+            ..ReductionStrategy::CallByName.into()
+        }
+    }
 }
 
 // One the game's core levels
 #[derive(Debug)]
 pub struct GameLevel {
-    pub base:            BaseLevel,
-    pub section:         SectionName,
+    pub base:           BaseLevel,
+    pub section:        SectionName,
     /// index of the level in the section
-    pub idx:             usize,
-    pub solutions:       Vec1<String>,
-    pub wrong_solutions: Vec<String>,
-    pub show_constants:  bool,
+    pub idx:            usize,
+    pub solutions:      Vec1<String>,
+    pub show_constants: bool,
 }
 
 #[derive(Debug)]
@@ -76,6 +321,32 @@ impl Level {
             Level::UserCreatedLevel(uc) => uc.id.map(|id| format!("{}", id)),
         }
     }
+
+    /// Knows every constant the solutions written by the level's author may use
+    fn author_provider(&self) -> ConstantProvider {
+        match self {
+            Level::GameLevel(_) => ConstantProvider::all(),
+            Level::UserCreatedLevel(_) => ConstantProvider::new(self.clone(), None),
+        }
+    }
+
+    /// Feedback of the first known wrong solution that fails the same way: the same test cases
+    /// pass, and the others give the same results or the same kind of error. Only wrong solutions
+    /// with feedback are run, and they stop once cancel is cancelled.
+    pub fn feedback(&self, outcomes: &RunOutcomes, cancel: CancelToken) -> Option<&str> {
+        if outcomes.all_correct() {
+            return None;
+        }
+        self.base()
+            .wrong_solutions
+            .iter()
+            .filter(|wrong| wrong.feedback.is_some())
+            .find(|wrong| {
+                self.test_cancellable(wrong.code.chars(), self.author_provider(), cancel.clone())
+                    .map_or(false, |wrong_runs| wrong_runs.outcomes() == *outcomes)
+            })
+            .and_then(|wrong| wrong.feedback.as_deref())
+    }
 }
 
 pub fn parse_or_fail(str: &str) -> Box<Node> {
@@ -86,49 +357,208 @@ pub fn parse_or_fail(str: &str) -> Box<Node> {
 pub struct TestCaseRun {
     pub test_expression: Box<Node>,
     pub result:          Result<Interpreted, InterpretError>,
-    pub expected_result: Box<Node>,
+    pub expected:        Expected,
+    /// Time it took to interpret the test expression, without checking the result
+    pub time:            Duration,
+    /// Feedback of the test case if the result is one of its known wrong results
+    pub hint:            Option<String>,
+    correct:             bool,
+    /// Form in which the result was compared, None if the run failed or it couldn't be brought
+    /// to it
+    canonical:           Option<Term>,
+    /// Same for the expected results. Empty for predicates.
+    expected_terms:      Vec<Term>,
+}
+
+/// How a run ended, to compare the runs of different code on the same test case
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Correct,
+    /// With the form in which the result was compared, None if it couldn't be brought to it
+    Wrong(Option<Term>),
+    /// Errors of the same kind are the same failure
+    Failed(std::mem::Discriminant<InterpretError>),
+}
+
+/// How each test case of a solution ended, to find known wrong solutions that fail the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutcomes(Vec<Outcome>);
+
+impl RunOutcomes {
+    pub fn all_correct(&self) -> bool { self.0.iter().all(|o| *o == Outcome::Correct) }
 }
 
 impl TestCaseRun {
-    pub fn is_correct(&self) -> bool {
-        self.result
-            .as_ref()
-            .map_or(false, |r| r.term == self.expected_result)
+    pub fn is_correct(&self) -> bool { self.correct }
+
+    pub fn reductions(&self) -> Option<u32> {
+        self.result.as_ref().ok().map(|r| r.stats.reductions)
+    }
+
+    /// The closest expected result, the one that differs deepest or the first one on ties, and
+    /// the result, both in the form the test case compares them, with where they first differ.
+    /// None if the result is correct or couldn't be compared, or for predicates.
+    pub fn difference(&self) -> Option<(Box<Node>, Box<Node>, Difference)> {
+        let result = self.canonical.as_ref()?.to_node();
+        self.expected_terms
+            .iter()
+            .map(|expected| {
+                let expected = expected.to_node();
+                let difference = first_difference(&expected, &result)?;
+                Some((expected, difference))
+            })
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, d)| d.path.len())
+            .map(|(expected, difference)| (expected, result, difference))
+    }
+
+    fn outcome(&self) -> Outcome {
+        match &self.result {
+            _ if self.correct => Outcome::Correct,
+            Ok(_) => Outcome::Wrong(self.canonical.clone()),
+            Err(err) => Outcome::Failed(std::mem::discriminant(err)),
+        }
     }
 }
 
 impl TestCase {
-    pub fn from(application: Box<Node>, expected_result: Box<Node>) -> Self {
-        Self {
+    /// Fails if the expected or known wrong results can't be brought to the form needed by the
+    /// equivalence
+    pub fn new(
+        application: Box<Node>,
+        expected: Expected,
+        equivalence: Equivalence,
+        numerals: Numerals,
+        wrong_results: Vec<(Box<Node>, String)>,
+    ) -> Result<Self, InterpretError> {
+        let provider = ConstantProvider::all().with_numerals(numerals);
+        let canonical =
+            |r: Box<Node>| equivalence.canonical(r, ReductionStrategy::Normal.into(), &provider);
+        let expected_terms = match &expected {
+            Expected::AnyOf(results) => results
+                .iter()
+                .map(|r| canonical(r.clone()))
+                .collect::<Result<_, _>>()?,
+            Expected::Predicate(_) => vec![],
+        };
+        let wrong_results = wrong_results
+            .into_iter()
+            .map(|(r, feedback)| Ok((canonical(r)?, feedback)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
             application,
-            expected_result,
-        }
+            expected,
+            expected_terms,
+            equivalence,
+            wrong_results,
+        })
     }
 
-    pub fn from_or_fail(application: &str, result: &str) -> Self {
-        Self::from(
-            parse_or_fail(application),
-            // fine to use all here since this is not user supplied
-            interpret(parse_or_fail(result), false, ConstantProvider::all())
-                .expect("Failed to interpret result")
-                .term,
+    pub fn from_or_fail(config: &TestCaseConfig, numerals: Numerals) -> Self {
+        // fine to use all here since this is not user supplied
+        let result = |result: &str| {
+            interpret_krivine(
+                parse_or_fail(result),
+                ReductionStrategy::CallByName,
+                ConstantProvider::all().with_numerals(numerals),
+            )
+            .expect("Failed to interpret result")
+            .term
+        };
+        let expected = match &config.expected {
+            ExpectedConfig::Result(r) => Expected::AnyOf(vec1![result(r)]),
+            ExpectedConfig::AnyOf(rs) => Expected::AnyOf(rs.mapped_ref(|r| result(r))),
+            ExpectedConfig::Predicate(p) => Expected::Predicate(parse_or_fail(p)),
+        };
+        Self::new(
+            parse_or_fail(&config.application),
+            expected,
+            config.equivalence,
+            numerals,
+            config
+                .wrong_results
+                .iter()
+                .map(|(r, feedback)| (result(r), feedback.clone()))
+                .collect(),
         )
+        .expect("Failed to normalize result")
     }
 
     fn test_expression(&self, expression: Box<Node>) -> Box<Node> {
-        Box::new(Node::Apply {
-            left:  self.application.clone(),
-            right: expression,
-        })
+        apply(self.application.clone(), expression)
+    }
+
+    /// The canonical form is the result's, if it could be normalized
+    fn accepts(
+        &self,
+        result: &Node,
+        canonical: Option<&Term>,
+        options: InterpretOptions,
+        provider: &ConstantProvider,
+    ) -> bool {
+        match &self.expected {
+            // Results that can't be normalized are not correct
+            Expected::AnyOf(_) =>
+                canonical.map_or(false, |term| self.expected_terms.contains(term)),
+            Expected::Predicate(predicate) => {
+                let constant = |c: &str| Box::new(Node::Constant(c.to_string(), Span::default()));
+                interpret_shared(
+                    apply(
+                        apply(
+                            apply(predicate.clone(), Box::new(result.clone())),
+                            constant(ACCEPTED),
+                        ),
+                        constant(REJECTED),
+                    ),
+                    options,
+                    provider.clone(),
+                )
+                .map_or(
+                    false,
+                    |r| matches!(*r.term, Node::Constant(ref c, _) if c == ACCEPTED),
+                )
+            },
+        }
     }
 
-    pub fn test(&self, expression: Box<Node>, provider: ConstantProvider) -> TestCaseRun {
+    pub fn test(
+        &self,
+        expression: Box<Node>,
+        provider: ConstantProvider,
+        options: InterpretOptions,
+    ) -> TestCaseRun {
         let test_expression = self.test_expression(expression);
-        let result = interpret(test_expression.clone(), false, provider);
+        let start = Instant::now();
+        let result = interpret_shared(test_expression.clone(), options.clone(), provider.clone());
+        let time = start.elapsed();
+        let canonical = result.as_ref().ok().and_then(|r| {
+            self.equivalence
+                .canonical(r.term.clone(), options.clone(), &provider)
+                .ok()
+        });
+        let correct = result.as_ref().map_or(false, |r| {
+            self.accepts(&r.term, canonical.as_ref(), options, &provider)
+        });
+        // Known wrong results are compared the same way as the expected ones
+        let hint = match &canonical {
+            Some(term) if !correct => self
+                .wrong_results
+                .iter()
+                .find(|(wrong, _)| wrong == term)
+                .map(|(_, feedback)| feedback.clone()),
+            _ => None,
+        };
         TestCaseRun {
             test_expression,
             result,
-            expected_result: self.expected_result.clone(),
+            expected: self.expected.clone(),
+            time,
+            hint,
+            correct,
+            canonical,
+            expected_terms: self.expected_terms.clone(),
         }
     }
 }
@@ -139,8 +569,22 @@ pub enum LevelTestError {
     TokenizeError(#[from] TokenizeError),
     #[error("While parsing tokens: {0}")]
     ParseError(#[from] ParseError),
-    #[error("Constant {0} is not known")]
-    UnknownConstant(String),
+    #[error("Constant {0} at {1} is not known")]
+    UnknownConstant(String, Span),
+    #[error("Functions are not allowed in this level, use only constants. Found one at {0}")]
+    FunctionNotAllowed(Span),
+}
+
+impl LevelTestError {
+    /// Where in the code the error happened
+    pub fn span(&self) -> Span {
+        match self {
+            LevelTestError::TokenizeError(err) => err.span(),
+            LevelTestError::ParseError(err) => err.span(),
+            LevelTestError::UnknownConstant(_, span) => *span,
+            LevelTestError::FunctionNotAllowed(span) => *span,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -149,6 +593,23 @@ pub struct TestCaseRuns {
     pub code: Box<Node>,
 }
 
+impl TestCaseRuns {
+    pub fn outcomes(&self) -> RunOutcomes {
+        RunOutcomes(self.runs.iter().map(TestCaseRun::outcome).collect())
+    }
+
+    /// Index of the run with the most reductions, the one that raises the average the most. Only
+    /// runs that didn't fail are considered.
+    pub fn worst(&self) -> Option<usize> {
+        self.runs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, run)| run.reductions().map(|r| (i, r)))
+            .max_by_key(|&(i, r)| (r, std::cmp::Reverse(i)))
+            .map(|(i, _)| i)
+    }
+}
+
 pub type TestRunResults = Result<TestCaseRuns, LevelTestError>;
 
 pub fn get_result(results: &TestRunResults) -> LevelResult {
@@ -178,9 +639,9 @@ pub fn get_result(results: &TestRunResults) -> LevelResult {
 }
 
 fn check_constants(node: &Node, provider: ConstantProvider) -> Result<(), LevelTestError> {
-    for constant in all_constants(&node) {
+    for (constant, span) in all_constants(&node) {
         if provider.get(&constant).is_none() {
-            return Err(LevelTestError::UnknownConstant(constant.to_string()));
+            return Err(LevelTestError::UnknownConstant(constant.to_string(), span));
         }
     }
     Ok(())
@@ -191,18 +652,40 @@ impl Level {
         &self,
         code: S,
         provider: ConstantProvider,
+    ) -> TestRunResults {
+        self.test_cancellable(code, provider, CancelToken::default())
+    }
+
+    /// Same as test, but all test cases stop with InterpretError::Cancelled once cancel is
+    /// cancelled.
+    pub fn test_cancellable<S: IntoIterator<Item = char>>(
+        &self,
+        code: S,
+        provider: ConstantProvider,
+        cancel: CancelToken,
     ) -> TestRunResults {
         let ts = Instant::now();
         let node = parse(tokenize(code)?)?;
         check_constants(&node, provider)?;
+        if !self.base().allow_functions {
+            if let Some(span) = first_function(&node) {
+                return Err(LevelTestError::FunctionNotAllowed(span));
+            }
+        }
         // From here, we use all constants as the test cases may have unknown constants and that's fine
-        let provider = ConstantProvider::all();
+        let provider = ConstantProvider::all().with_numerals(self.base().numerals);
         let ans = Ok(TestCaseRuns {
             runs: self
                 .base()
                 .test_cases
                 .par_iter()
-                .map(|t| t.test(node.clone(), provider.clone()))
+                .map(|t| {
+                    t.test(
+                        node.clone(),
+                        provider.clone(),
+                        self.base().interpret_options(cancel.clone()),
+                    )
+                })
                 .collect(),
             code: node,
         });
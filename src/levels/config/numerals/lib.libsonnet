@@ -1,11 +1,41 @@
 local bool = import '../boolean/lib.libsonnet';
 local pl = import '../pair_and_list/lib.libsonnet';
 {
+  # Encoding of the numerals, 'church', 'scott' or 'binary'. Levels with other numerals use the
+  # helpers of with_numerals, and set their numerals to the same, for example:
+  #   local lib = (import '../numerals/lib.libsonnet').with_numerals('scott');
+  #   { numerals: lib.numerals, test_cases: [lib.test_num('f: f 2', 2)], ... }
+  numerals:: 'church',
+  with_numerals(encoding):: self { numerals:: encoding },
   # Doesn't return FALSE if num is not equal, just some trash
   num_is_eq_func(x)::
-    '(n: n SND %s)' % [pl.list_no_sentinel(std.repeat(['Z'], x) + ['TRUE'])],
+    '(n: %s)' % [self.num_is_eq('n', x)],
   num_is_eq(term, x):::
-    '%s SND %s' % [term, pl.list_no_sentinel(std.repeat(['Z'], x) + ['TRUE'])],
+    local encoding = self.numerals;
+    if encoding == 'church' then
+      '%s SND %s' % [term, pl.list_no_sentinel(std.repeat(['Z'], x) + ['TRUE'])]
+    else if encoding == 'scott' then
+      self.scott_is_eq(term, x)
+    else if encoding == 'binary' then
+      self.binary_is_eq(term, x)
+    else
+      error 'Unknown encoding ' + encoding,
+  # Unlike church, these return FALSE if num is not equal
+  scott_is_eq(term, x)::
+    if x == 0 then
+      '%s (p: FALSE) TRUE' % [term]
+    else
+      '%s (p: %s) FALSE' % [term, self.scott_is_eq('p', x - 1)],
+  # Bits go from least to most significant
+  binary_is_eq(term, x)::
+    if x == 0 then
+      '%s (h:t:d: FALSE) TRUE' % [term]
+    else
+      '%s (h:t:d: AND (%s) (%s)) FALSE' % [
+        term,
+        if x % 2 == 1 then 'h' else 'NOT h',
+        self.binary_is_eq('t', std.floor(x / 2)),
+      ],
   test_num(term, x)::
     bool.test_true(self.num_is_eq(term, x)),
   # post applied
@@ -1,7 +1,11 @@
+use std::time::Duration;
+
 use serde::Deserialize;
 
-use super::{BaseLevel, GameLevel, TestCase};
-use crate::prelude::*;
+use super::{BaseLevel, GameLevel, TestCase, TestCaseConfig, WrongSolution};
+use crate::{
+    interpreter::{Budget, Numerals}, prelude::*
+};
 fn get_true() -> bool { true }
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -9,10 +13,11 @@ pub struct JLevel {
     pub name:                   String,
     pub description:            String,
     pub extra_info:             Option<String>,
-    pub test_cases:             Vec1<(String, String)>,
+    pub test_cases:             Vec1<TestCaseConfig>,
     pub solutions:              Vec1<String>,
+    /// Code that must fail, optionally with feedback for players whose code fails the same way
     #[serde(default)]
-    pub wrong_solutions:        Vec<String>,
+    pub wrong_solutions:        Vec<WrongSolution>,
     #[serde(default)]
     pub provides_constant:      bool,
     #[serde(default = "get_true")]
@@ -21,20 +26,40 @@ pub struct JLevel {
     pub before_level_constants: Vec<(String, String)>,
     #[serde(default)]
     pub extra_info_is_hint:     bool,
+    /// Test cases fail if they take more reductions than this
+    pub max_reductions:         Option<u32>,
+    /// Test cases fail if they take longer than this many milliseconds
+    pub time_limit_ms:          Option<u64>,
+    /// Encoding of number literals, if different from the section's
+    pub numerals:               Option<Numerals>,
+    /// If false, solutions must be written only with constants, for example combinators
+    #[serde(default = "get_true")]
+    pub allow_functions:        bool,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct JSection {
-    pub name:   SectionName,
-    pub levels: Vec1<JLevel>,
+    pub name:     SectionName,
+    pub levels:   Vec1<JLevel>,
+    /// Encoding of number literals in the section's levels, Church numerals if missing
+    pub numerals: Option<Numerals>,
+}
+
+/// Check of the helpers of the level configs, a term and what it reduces to. The helpers follow
+/// the numerals of the levels, so checks of numerals other than Church say which ones they use.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum JTest {
+    Church(String, String),
+    WithNumerals(String, String, Numerals),
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct JLevelConfig {
     pub sections: Vec1<JSection>,
-    pub tests:    Vec1<(String, String)>,
+    pub tests:    Vec1<JTest>,
 }
 
 const RAW_LEVEL_CONFIG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/level_config.json"));
@@ -53,6 +78,7 @@ fn load_all() -> Vec1<Section> {
             .into_iter()
             .filter_map(|s| {
                 let section_name = s.name;
+                let section_numerals = s.numerals;
                 Some(Section {
                     name:   s.name,
                     levels: {
@@ -67,21 +93,31 @@ fn load_all() -> Vec1<Section> {
                                     if l.extra_info_is_hint {
                                         debug_assert!(l.extra_info.is_some());
                                     }
+                                    let numerals =
+                                        l.numerals.or(section_numerals).unwrap_or(Numerals::Church);
                                     let level = GameLevel {
                                         base: BaseLevel {
-                                            name:        l.name,
+                                            name: l.name,
                                             description: l.description,
-                                            extra_info:  l.extra_info,
-                                            test_cases:  l
+                                            extra_info: l.extra_info,
+                                            test_cases: l
                                                 .test_cases
-                                                .mapped(|t| TestCase::from_or_fail(&t.0, &t.1)),
+                                                .mapped(|t| TestCase::from_or_fail(&t, numerals)),
 
                                             extra_info_is_hint: l.extra_info_is_hint,
+                                            budget: Budget {
+                                                max_reductions: l.max_reductions,
+                                                time_limit:     l
+                                                    .time_limit_ms
+                                                    .map(Duration::from_millis),
+                                            },
+                                            numerals,
+                                            allow_functions: l.allow_functions,
+                                            wrong_solutions: l.wrong_solutions,
                                         },
                                         idx,
                                         section: section_name,
                                         solutions: l.solutions,
-                                        wrong_solutions: l.wrong_solutions,
                                         show_constants: l.show_constants,
                                     };
                                     idx += 1;
@@ -137,16 +173,22 @@ lazy_static! {
 
 #[cfg(test)]
 mod test {
-    use std::{assert_matches::assert_matches, collections::HashSet, time::Duration};
+    use std::{
+        assert_matches::assert_matches, collections::{HashMap, HashSet}, time::Duration
+    };
 
     use rayon::prelude::*;
     use strum::IntoEnumIterator;
 
     use super::{
-        super::{base::Level, get_result}, *
+        super::{
+            base::{Level, UserCreatedLevel}, get_result, parse_or_fail, Equivalence, ExpectedConfig, LevelTestError
+        }, *
     };
     use crate::{
-        interpreter::{interpreter::test::interpret_ok, ConstantProvider}, save_system::{LevelResult, SaveProfile}
+        interpreter::{
+            interpret, interpret_krivine, traversers::{drop_node, PathStep}, CancelToken, ConstantProvider, InterpretError, InterpretOptions, ReductionStrategy, MAX_SIZE
+        }, save_system::{LevelResult, SaveProfile}
     };
 
     #[test]
@@ -171,12 +213,22 @@ mod test {
 
     #[test]
     fn test_jsonnet_tests() {
-        raw_load_level_config()
-            .tests
-            .into_iter()
-            .for_each(|(a, b)| {
-                assert_eq!(interpret_ok(&a), interpret_ok(&b), "'{}' != '{}'", &a, &b)
-            });
+        raw_load_level_config().tests.into_iter().for_each(|test| {
+            let (a, b, numerals) = match test {
+                JTest::Church(a, b) => (a, b, Numerals::Church),
+                JTest::WithNumerals(a, b, numerals) => (a, b, numerals),
+            };
+            let interpret_ok = |code: &str| {
+                interpret(
+                    parse_or_fail(code),
+                    ReductionStrategy::CallByName,
+                    ConstantProvider::all().with_numerals(numerals),
+                )
+                .unwrap()
+                .term
+            };
+            assert_eq!(interpret_ok(&a), interpret_ok(&b), "'{}' != '{}'", a, b)
+        });
     }
 
     fn solution_section(section: SectionName) {
@@ -212,7 +264,7 @@ mod test {
                         idx_sol + 1,
                         idx_test + 1,
                         r.test_expression,
-                        r.expected_result,
+                        r.expected,
                         l.base.name,
                         r.result.clone().map(|r| format!("{}", r.term)),
                     )
@@ -258,15 +310,422 @@ mod test {
         Trees
     );
 
+    #[test]
+    fn shared_same_as_call_by_name() {
+        LEVELS
+            .iter()
+            .flat_map(|s| &s.levels)
+            .collect::<Vec<_>>()
+            .par_iter()
+            .for_each(|l| {
+                l.solutions.iter().for_each(|s| {
+                    let runs = Level::GameLevel(l)
+                        .test(s.chars(), ConstantProvider::all())
+                        .unwrap()
+                        .runs;
+                    runs.into_iter().for_each(|r| {
+                        let expected = interpret(
+                            r.test_expression.clone(),
+                            ReductionStrategy::CallByName,
+                            ConstantProvider::all(),
+                        );
+                        if let Ok(expected) = expected {
+                            let shared = r.result.unwrap();
+                            assert_eq!(shared.term, expected.term, "On {}", r.test_expression);
+                            assert_eq!(shared.stats, expected.stats, "On {}", r.test_expression);
+                        }
+                    })
+                })
+            });
+    }
+
+    #[test]
+    fn krivine_same_as_call_by_name() {
+        LEVELS
+            .iter()
+            .flat_map(|s| &s.levels)
+            .collect::<Vec<_>>()
+            .par_iter()
+            .for_each(|l| {
+                l.solutions.iter().for_each(|s| {
+                    let runs = Level::GameLevel(l)
+                        .test(s.chars(), ConstantProvider::all())
+                        .unwrap()
+                        .runs;
+                    runs.into_iter().for_each(|r| {
+                        let expected = interpret(
+                            r.test_expression.clone(),
+                            ReductionStrategy::CallByName,
+                            ConstantProvider::all(),
+                        );
+                        let krivine = interpret_krivine(
+                            r.test_expression.clone(),
+                            ReductionStrategy::CallByName,
+                            ConstantProvider::all(),
+                        );
+                        match expected {
+                            Ok(expected) => {
+                                let krivine = krivine.unwrap();
+                                assert_eq!(krivine.term, expected.term, "On {}", r.test_expression);
+                                assert_eq!(
+                                    krivine.stats, expected.stats,
+                                    "On {}",
+                                    r.test_expression
+                                );
+                            },
+                            // Krivine never copies terms, so it can't fail with TooLarge
+                            Err(InterpretError::TooLarge) => (),
+                            Err(err) =>
+                                assert_eq!(krivine.unwrap_err(), err, "On {}", r.test_expression),
+                        }
+                    })
+                })
+            });
+    }
+
+    #[test]
+    fn equivalences() {
+        let is_correct = |solution: &str, result: &str, equivalence| {
+            TestCase::from_or_fail(
+                &TestCaseConfig {
+                    application: "f: f".to_string(),
+                    expected: ExpectedConfig::Result(result.to_string()),
+                    equivalence,
+                    wrong_results: vec![],
+                },
+                Numerals::Church,
+            )
+            .test(
+                parse_or_fail(solution),
+                ConstantProvider::all(),
+                ReductionStrategy::CallByName.into(),
+            )
+            .is_correct()
+        };
+        assert!(is_correct("x: x", "y: y", Equivalence::Structural));
+        assert!(!is_correct("x: (y: y) x", "x: x", Equivalence::Structural));
+        assert!(is_correct("x: (y: y) x", "x: x", Equivalence::BetaNormal));
+        assert!(!is_correct("x: g x", "g", Equivalence::BetaNormal));
+        assert!(is_correct("x: g x", "g", Equivalence::BetaEta));
+        assert!(is_correct("n: f:x: f (n f x)", "SUC", Equivalence::BetaEta));
+        assert!(!is_correct("x: x x", "x: x", Equivalence::BetaEta));
+    }
+
+    #[test]
+    fn deep_solutions() {
+        // As deep as the size of the terms allows, on a stack much smaller than a main thread's
+        let nests = MAX_SIZE as usize / 2 - 2;
+        let solution = format!("{}a:b: a{}", "(x: x) (".repeat(nests), ")".repeat(nests));
+        let test_case = TestCase::from_or_fail(
+            &TestCaseConfig {
+                application:   "f: f".to_string(),
+                expected:      ExpectedConfig::Result("TRUE".to_string()),
+                equivalence:   Equivalence::Structural,
+                wrong_results: vec![],
+            },
+            Numerals::Church,
+        );
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let run = test_case.test(
+                    parse_or_fail(&solution),
+                    ConstantProvider::all(),
+                    ReductionStrategy::CallByName.into(),
+                );
+                assert!(run.is_correct());
+                // One more for the application
+                assert_eq!(run.result.unwrap().stats.reductions, nests as u32 + 1);
+                // Dropping a deep Box<Node> recurses
+                drop_node(run.test_expression);
+                // Going too deep falls back to the reference interpreter, on the same deep term
+                let options = InterpretOptions {
+                    max_depth: 100,
+                    ..ReductionStrategy::CallByName.into()
+                };
+                let run =
+                    test_case.test(parse_or_fail(&solution), ConstantProvider::all(), options);
+                assert_eq!(run.result.unwrap_err(), InterpretError::TooDeep);
+                drop_node(run.test_expression);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn expectations() {
+        let is_correct = |solution: &str, application: &str, expected| {
+            TestCase::from_or_fail(
+                &TestCaseConfig {
+                    application: application.to_string(),
+                    expected,
+                    equivalence: Equivalence::Structural,
+                    wrong_results: vec![],
+                },
+                Numerals::Church,
+            )
+            .test(
+                parse_or_fail(solution),
+                ConstantProvider::all(),
+                ReductionStrategy::CallByName.into(),
+            )
+            .is_correct()
+        };
+        let any_of = || ExpectedConfig::AnyOf(vec1!["A".to_string(), "B".to_string()]);
+        assert!(is_correct("a:b: a", "f: f A B", any_of()));
+        assert!(is_correct("a:b: b", "f: f A B", any_of()));
+        assert!(!is_correct("a:b: C", "f: f A B", any_of()));
+        let predicate = || ExpectedConfig::Predicate("x: NOT x".to_string());
+        assert!(is_correct("a:b: b", "f: f TRUE FALSE", predicate()));
+        assert!(!is_correct("a:b: a", "f: f TRUE FALSE", predicate()));
+        // Predicates don't need to give back a boolean to reject
+        assert!(!is_correct("a:b: C", "f: f TRUE FALSE", predicate()));
+    }
+
+    #[test]
+    fn numerals() {
+        let is_correct = |solution: &str, result: &str, numerals| {
+            TestCase::from_or_fail(
+                &TestCaseConfig {
+                    application:   "f: f 2".to_string(),
+                    expected:      ExpectedConfig::Result(result.to_string()),
+                    equivalence:   Equivalence::BetaNormal,
+                    wrong_results: vec![],
+                },
+                numerals,
+            )
+            .test(
+                parse_or_fail(solution),
+                ConstantProvider::all().with_numerals(numerals),
+                ReductionStrategy::CallByName.into(),
+            )
+            .is_correct()
+        };
+        let church_suc = "n: f:x: f (n f x)";
+        let scott_suc = "n: s:z: s n";
+        assert!(is_correct(church_suc, "3", Numerals::Church));
+        assert!(!is_correct(scott_suc, "3", Numerals::Church));
+        assert!(is_correct(scott_suc, "3", Numerals::Scott));
+        assert!(!is_correct(church_suc, "3", Numerals::Scott));
+        // 2 = [0, 1], 3 = [1, 1]
+        let binary_suc = "n: PUSH TRUE (POP n)";
+        assert!(is_correct(binary_suc, "3", Numerals::Binary));
+        assert!(!is_correct(binary_suc, "3", Numerals::Church));
+    }
+
+    #[test]
+    fn literals_need_constants() {
+        let unknown = |section: usize, code: &str| {
+            let level = &LEVELS[section].levels[0];
+            let provider =
+                ConstantProvider::new(level.into(), Some(Arc::new(SaveProfile::fake(vec![]))));
+            match Level::GameLevel(level).test(code.chars(), provider) {
+                Err(LevelTestError::UnknownConstant(c, _)) => Some(c),
+                _ => None,
+            }
+        };
+        // Boolean levels know FALSE but not PUSH
+        assert_eq!(unknown(1, "x: []"), None);
+        assert_eq!(unknown(1, "x: \"\""), None);
+        assert_eq!(unknown(1, "x: [x]"), Some("PUSH".to_string()));
+    }
+
+    #[test]
+    fn functions_not_allowed() {
+        let level = |allow| {
+            Level::UserCreatedLevel(Arc::new(UserCreatedLevel {
+                base:            BaseLevel {
+                    name:               "no functions".to_string(),
+                    description:        String::new(),
+                    extra_info:         None,
+                    extra_info_is_hint: false,
+                    test_cases:         vec1![TestCase::from_or_fail(
+                        &TestCaseConfig {
+                            application:   "f: f A B".to_string(),
+                            expected:      ExpectedConfig::Result("B".to_string()),
+                            equivalence:   Equivalence::BetaNormal,
+                            wrong_results: vec![],
+                        },
+                        Numerals::Church,
+                    )],
+                    budget:             Budget::default(),
+                    numerals:           Numerals::Church,
+                    allow_functions:    allow,
+                    wrong_solutions:    vec![],
+                },
+                extra_constants: HashMap::new(),
+                id:              None,
+            }))
+        };
+        let test = |level: &Level, code: &str| {
+            level.test(code.chars(), ConstantProvider::new(level.clone(), None))
+        };
+        let no_functions = level(false);
+        assert_matches!(
+            get_result(&test(&no_functions, "FALSE")),
+            LevelResult::Success { .. }
+        );
+        assert_matches!(
+            test(&no_functions, "TRUE (a:b: b)"),
+            Err(LevelTestError::FunctionNotAllowed(_))
+        );
+        // Combinators are unlocked only for these levels
+        assert_matches!(
+            get_result(&test(&no_functions, "KCOMB ICOMB")),
+            LevelResult::Success { .. }
+        );
+        assert_matches!(
+            test(&level(true), "KCOMB ICOMB"),
+            Err(LevelTestError::UnknownConstant(..))
+        );
+    }
+
+    #[test]
+    fn difference_to_closest() {
+        let diff = |solution: &str, expected, equivalence| {
+            TestCase::from_or_fail(
+                &TestCaseConfig {
+                    application: "f: f".to_string(),
+                    expected,
+                    equivalence,
+                    wrong_results: vec![],
+                },
+                Numerals::Church,
+            )
+            .test(
+                parse_or_fail(solution),
+                ConstantProvider::all(),
+                ReductionStrategy::CallByName.into(),
+            )
+            .difference()
+        };
+        let any_of = || ExpectedConfig::AnyOf(vec1!["A B C".to_string(), "A (B C)".to_string()]);
+        let (closest, result, difference) =
+            diff("A (B D)", any_of(), Equivalence::Structural).unwrap();
+        assert_eq!(closest, parse_or_fail("A (B C)"));
+        assert_eq!(result, parse_or_fail("A (B D)"));
+        assert_eq!(difference.path, vec![PathStep::Right, PathStep::Right]);
+        assert_eq!(
+            (difference.expected, difference.actual),
+            ("C".into(), "D".into())
+        );
+        assert!(diff("A B C", any_of(), Equivalence::Structural).is_none());
+        // Normal forms are compared, not the redex that is still in the expected result
+        let (_, _, difference) = diff(
+            "x: x B",
+            ExpectedConfig::Result("x: x ((y: y) A)".to_string()),
+            Equivalence::BetaNormal,
+        )
+        .unwrap();
+        assert_eq!(difference.path, vec![PathStep::Body, PathStep::Right]);
+        assert_eq!(
+            (difference.expected, difference.actual),
+            ("A".into(), "B".into())
+        );
+    }
+
+    #[test]
+    fn worst_test_case() {
+        LEVELS.iter().flat_map(|s| &s.levels).for_each(|l| {
+            let runs = Level::GameLevel(l)
+                .test(l.solutions[0].chars(), ConstantProvider::all())
+                .unwrap();
+            let max = runs.runs.iter().filter_map(|r| r.reductions()).max();
+            let worst = runs.worst().expect("Solutions don't fail");
+            assert_eq!(runs.runs[worst].reductions(), max, "On {}", l.base.name);
+            // Ties go to the first one
+            assert!(runs.runs[..worst].iter().all(|r| r.reductions() < max));
+            for run in &runs.runs {
+                assert!(run.result.as_ref().unwrap().usage.peak_size > 0);
+            }
+        });
+    }
+
+    #[test]
+    fn feedback() {
+        let test_case = |application: &str, result: &str, wrong_results| {
+            TestCase::from_or_fail(
+                &TestCaseConfig {
+                    application: application.to_string(),
+                    expected: ExpectedConfig::Result(result.to_string()),
+                    equivalence: Equivalence::Structural,
+                    wrong_results,
+                },
+                Numerals::Church,
+            )
+        };
+        let level = Level::UserCreatedLevel(Arc::new(UserCreatedLevel {
+            base:            BaseLevel {
+                name:               "first".to_string(),
+                description:        String::new(),
+                extra_info:         None,
+                extra_info_is_hint: false,
+                test_cases:         vec1![
+                    test_case(
+                        "f: f A B",
+                        "A",
+                        vec![("B".to_string(), "Not the second".to_string())]
+                    ),
+                    test_case("f: f B A", "B", vec![]),
+                ],
+                budget:             Budget::default(),
+                numerals:           Numerals::Church,
+                allow_functions:    true,
+                wrong_solutions:    vec![WrongSolution {
+                    code:     "a:b: b".to_string(),
+                    feedback: Some("Swapped".to_string()),
+                }],
+            },
+            extra_constants: HashMap::new(),
+            id:              None,
+        }));
+        let test = |code: &str| level.test(code.chars(), ConstantProvider::all()).unwrap();
+        let feedback = |code: &str| {
+            level
+                .feedback(&test(code).outcomes(), CancelToken::default())
+                .map(str::to_string)
+        };
+        assert_eq!(feedback("a:b: b"), Some("Swapped".to_string()));
+        // Fails the same way as the wrong solution
+        assert_eq!(feedback("a: (b: b)"), Some("Swapped".to_string()));
+        assert_eq!(feedback("a:b: a"), None);
+        assert_eq!(feedback("a:b: TRUE"), None);
+        // Only known wrong results of a test case get its hint
+        let hints =
+            |code: &str| -> Vec<_> { test(code).runs.into_iter().map(|r| r.hint).collect() };
+        assert_eq!(
+            hints("a:b: b"),
+            vec![Some("Not the second".to_string()), None]
+        );
+        assert_eq!(hints("a:b: TRUE"), vec![None, None]);
+        // Compared in normal form, like the expected results
+        let run = TestCase::from_or_fail(
+            &TestCaseConfig {
+                application:   "f: f A".to_string(),
+                expected:      ExpectedConfig::Result("x: A".to_string()),
+                equivalence:   Equivalence::BetaNormal,
+                wrong_results: vec![("x: x".to_string(), "Identity".to_string())],
+            },
+            Numerals::Church,
+        )
+        .test(
+            parse_or_fail("a: y: (z: z) y"),
+            ConstantProvider::all(),
+            ReductionStrategy::CallByName.into(),
+        );
+        assert_eq!(run.hint, Some("Identity".to_string()));
+    }
+
     #[test]
     fn test_wrong_solutions() {
         LEVELS.iter().flat_map(|s| &s.levels).for_each(|l| {
-            l.wrong_solutions.iter().for_each(|s| {
+            l.base.wrong_solutions.iter().for_each(|s| {
                 assert_matches!(
-                    get_result(&Level::GameLevel(l).test(s.chars(), ConstantProvider::all())),
+                    get_result(&Level::GameLevel(l).test(s.code.chars(), ConstantProvider::all())),
                     LevelResult::Failure,
                     "Code was solution {} on level {}",
-                    s,
+                    s.code,
                     l.base.name
                 )
             })
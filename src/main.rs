@@ -1,6 +1,5 @@
 #![feature(div_duration)]
 #![feature(try_blocks)]
-#![feature(coroutines, coroutine_trait)]
 #![feature(iter_advance_by)]
 #![feature(trait_alias)]
 #![feature(assert_matches)]